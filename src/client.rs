@@ -1,29 +1,223 @@
+use std::collections::VecDeque;
+
 use log::*;
 use systemstat::Duration;
 use xactor::*;
 
-use crate::socket::WriteSocket;
+use crate::database::CompressionKind;
+use crate::socket::{ReadSocket, WriteSocket};
+use crate::utils::Redactor;
 use serde::Serialize;
 use typename::TypeName;
 
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// smaller payloads barely shrink, so they are sent as they are
+const COMPRESS_THRESHOLD: usize = 256;
+const OTLP_BATCH: usize = 512;
+const OTLP_FLUSH: Duration = Duration::from_secs(5);
+/// artifact chunks kept for the server to acknowledge, beyond which the oldest cannot be resumed
+const MAX_UNACKED_BYTES: usize = 64 << 20;
+
 pub struct SendClient {
-    pub(crate) socket: WriteSocket
+    pub(crate) socket: Option<WriteSocket>,
+    pub(crate) reader: Option<ReadSocket>,
+    pub(crate) server: String,
+    pub(crate) queue: VecDeque<Frame>,
+    pub(crate) max_queue: usize,
+    pub(crate) backoff: Duration,
+    pub(crate) unacked: VecDeque<Frame>,
+    /// set once the server says it acknowledges artifact chunks
+    pub(crate) acks: bool,
+    pub(crate) compression: Option<CompressionKind>,
+    pub(crate) otlp: Option<Addr<OtlpExporter>>,
+    /// trace payloads only go to the collector, while replies and heartbeats still reach the server
+    pub(crate) otlp_only: bool,
+    pub(crate) redactor: Redactor,
+}
+
+pub struct Frame {
+    content: String,
+    /// artifact id and end offset of artifact chunks, which are kept until the server acknowledges them
+    artifact: Option<(String, u64)>,
 }
 
 macro_rules! msg_template {
     () => {r#"{{"type": "{}", "content": {}}}"#};
 }
 
+macro_rules! compressed_template {
+    () => {r#"{{"type": "{}", "encoding": "{}", "content": "{}"}}"#};
+}
+
+fn envelope(type_name: &str, data: String, compression: Option<CompressionKind>) -> String {
+    match compression {
+        Some(kind) if data.len() >= COMPRESS_THRESHOLD => match kind.compress(data.as_bytes()) {
+            Ok(compressed) => format!(compressed_template!(), type_name, kind.encoding(), base64::encode(compressed)),
+            Err(e) => {
+                warn!("cannot compress {} payload: {}", type_name, e);
+                format!(msg_template!(), type_name, data)
+            }
+        },
+        _ => format!(msg_template!(), type_name, data)
+    }
+}
+
+#[xactor::message(result = "()")]
+pub struct Reconnect;
+
+#[xactor::message(result = "()")]
+pub struct Disconnected;
+
+#[xactor::message(result = "Option<ReadSocket>")]
+pub struct TakeReader;
+
+#[xactor::message(result = "()")]
+pub struct ArtifactFrame(pub crate::trace::TraceArtifact);
+
+/// A payload of a model that asks for compression regardless of the global setting.
+pub struct Compressed<T>(pub T, pub CompressionKind);
+
+impl<T: Send + 'static> Message for Compressed<T> {
+    type Result = ();
+}
+
+/// Sent by the server to negotiate the compression level, or to turn compression off.
+#[xactor::message(result = "()")]
+pub struct SetCompression(pub Option<i32>);
+
+/// Sends out what is queued, replying with the number of frames left behind.
+#[xactor::message(result = "usize")]
+pub struct Flush;
+
+/// Sent by the server to tell whether it acknowledges artifact chunks.
+#[xactor::message(result = "()")]
+pub struct SetAcks(pub bool);
+
+/// Sent by the server once it holds an artifact up to `offset`; `resume` asks for everything after it again.
+#[xactor::message(result = "()")]
+pub struct Acknowledge {
+    pub artifact_id: String,
+    pub offset: u64,
+    pub resume: bool,
+}
+
 impl SendClient {
-    pub fn new(socket: WriteSocket) -> Self {
+    pub fn new(socket: WriteSocket, server: String, max_queue: usize, compression: Option<CompressionKind>) -> Self {
         SendClient {
-            socket
+            socket: Some(socket),
+            reader: None,
+            server,
+            queue: VecDeque::new(),
+            max_queue,
+            backoff: MIN_BACKOFF,
+            unacked: VecDeque::new(),
+            acks: false,
+            compression,
+            otlp: None,
+            otlp_only: false,
+            redactor: Redactor::default(),
         }
     }
 
-    async fn send_json<T : Serialize + TypeName>(&mut self, data: T) -> anyhow::Result<()> {
-        let data = simd_json::to_string(&data)?;
-        self.socket.send(format!(msg_template!(), T::type_name(), data)).await
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    pub fn with_otlp(mut self, otlp: Addr<OtlpExporter>, otlp_only: bool) -> Self {
+        self.otlp.replace(otlp);
+        self.otlp_only = otlp_only;
+        self
+    }
+
+    fn enqueue(&mut self, frame: Frame) {
+        if self.queue.len() >= self.max_queue {
+            if self.queue.pop_front().is_some() {
+                warn!("send queue is full, dropping the oldest frame");
+            }
+        }
+        if self.max_queue > 0 {
+            self.queue.push_back(frame);
+        }
+    }
+
+    async fn flush(&mut self, ctx: &Context<Self>) {
+        while let Some(frame) = self.queue.front() {
+            let socket = match &mut self.socket {
+                Some(socket) => socket,
+                None => return
+            };
+            match socket.send(frame.content.clone()).await {
+                Ok(_) => {
+                    if let Some(frame) = self.queue.pop_front() {
+                        if self.acks && frame.artifact.is_some() {
+                            self.keep_unacked(frame);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("failed to send data, reconnecting: {}", e);
+                    self.disconnect(ctx);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn keep_unacked(&mut self, frame: Frame) {
+        let mut bytes = self.unacked.iter().map(|x| x.content.len()).sum::<usize>() + frame.content.len();
+        while self.unacked.len() >= self.max_queue || bytes > MAX_UNACKED_BYTES {
+            match self.unacked.pop_front() {
+                Some(dropped) => bytes -= dropped.content.len(),
+                None => break
+            }
+            warn!("too many unacknowledged artifact chunks, the oldest one cannot be resumed");
+        }
+        if self.max_queue > 0 && frame.content.len() <= MAX_UNACKED_BYTES {
+            self.unacked.push_back(frame);
+        }
+    }
+
+    /// Puts unacknowledged chunks matching `filter` back in front of the queue, keeping their order.
+    fn requeue<F: Fn(&(String, u64)) -> bool>(&mut self, filter: F) {
+        let (resend, keep): (VecDeque<_>, VecDeque<_>) = self.unacked.drain(..)
+            .partition(|x| x.artifact.as_ref().map(|x| filter(x)).unwrap_or(false));
+        self.unacked = keep;
+        for frame in resend.into_iter().rev() {
+            self.queue.push_front(frame);
+        }
+    }
+
+    fn disconnect(&mut self, ctx: &Context<Self>) {
+        if self.socket.take().is_some() {
+            self.reader = None;
+            ctx.send_later(Reconnect, self.backoff);
+        }
+    }
+
+    async fn send_json<T : Serialize + TypeName>(&mut self, ctx: &Context<Self>, data: T,
+                                                 compression: Option<CompressionKind>) -> anyhow::Result<()> {
+        let data = self.redactor.to_json(&data)?;
+        let type_name = T::type_name();
+        let reply = type_name.ends_with("ClientReply");
+        if let Some(otlp) = &mut self.otlp {
+            if !reply {
+                otlp.send(OtlpRecord {
+                    type_name: type_name.clone(),
+                    payload: data.clone(),
+                })?;
+            }
+            if self.otlp_only && !reply && !type_name.ends_with("HeartbeatPacket") {
+                return Ok(());
+            }
+        }
+        self.enqueue(Frame {
+            content: envelope(type_name.as_str(), data, compression.or(self.compression)),
+            artifact: None,
+        });
+        self.flush(ctx).await;
+        Ok(())
     }
 }
 
@@ -37,13 +231,244 @@ impl Actor for SendClient {
 
 #[async_trait::async_trait]
 impl<T : typename::TypeName + Serialize + Message<Result = ()>> Handler<T> for SendClient {
-    async fn handle(&mut self, _: &Context<Self>, msg: T) -> <T as Message>::Result {
-        match self.send_json(msg).await {
+    async fn handle(&mut self, ctx: &Context<Self>, msg: T) -> <T as Message>::Result {
+        match self.send_json(ctx, msg, None).await {
             Err(e) => error!("{}", e),
             Ok(_) => debug!("{} data sent successfully", T::type_name())
         }
     }
 }
 
+#[async_trait::async_trait]
+impl<T : typename::TypeName + Serialize + Send + 'static> Handler<Compressed<T>> for SendClient {
+    async fn handle(&mut self, ctx: &Context<Self>, msg: Compressed<T>) {
+        match self.send_json(ctx, msg.0, Some(msg.1)).await {
+            Err(e) => error!("{}", e),
+            Ok(_) => debug!("compressed {} data sent successfully", T::type_name())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Flush> for SendClient {
+    async fn handle(&mut self, ctx: &Context<Self>, _: Flush) -> usize {
+        if let Some(otlp) = &mut self.otlp {
+            otlp.call(FlushOtlp).await.check_error();
+        }
+        self.flush(ctx).await;
+        self.queue.len()
+    }
+}
 
+#[async_trait::async_trait]
+impl Handler<SetCompression> for SendClient {
+    async fn handle(&mut self, _: &Context<Self>, msg: SetCompression) {
+        info!("payload compression negotiated to {:?}", msg.0);
+        self.compression = msg.0.map(CompressionKind::Zstd);
+    }
+}
 
+#[async_trait::async_trait]
+impl Handler<SetAcks> for SendClient {
+    async fn handle(&mut self, _: &Context<Self>, msg: SetAcks) {
+        info!("artifact acknowledgement negotiated to {}", msg.0);
+        self.acks = msg.0;
+        if !self.acks {
+            self.unacked.clear();
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Reconnect> for SendClient {
+    async fn handle(&mut self, ctx: &Context<Self>, _: Reconnect) {
+        if self.socket.is_some() {
+            return;
+        }
+        match crate::socket::create_sockets(self.server.as_str()).await {
+            Ok((rd, wt)) => {
+                info!("reconnected to {}", self.server);
+                self.socket.replace(wt);
+                self.reader.replace(rd);
+                self.backoff = MIN_BACKOFF;
+                // chunks that may have been lost with the old connection are sent again
+                self.requeue(|_| true);
+                self.flush(ctx).await;
+            }
+            Err(e) => {
+                self.backoff = std::cmp::min(self.backoff * 2, MAX_BACKOFF);
+                warn!("failed to reconnect to {}, retry in {:?}: {}", self.server, self.backoff, e);
+                ctx.send_later(Reconnect, self.backoff);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Disconnected> for SendClient {
+    async fn handle(&mut self, ctx: &Context<Self>, _: Disconnected) {
+        self.disconnect(ctx);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<TakeReader> for SendClient {
+    async fn handle(&mut self, _: &Context<Self>, _: TakeReader) -> Option<ReadSocket> {
+        self.reader.take()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ArtifactFrame> for SendClient {
+    async fn handle(&mut self, ctx: &Context<Self>, frame: ArtifactFrame) {
+        // artifacts have no place in the otlp export, so they are not shipped at all
+        if self.otlp.is_some() && self.otlp_only {
+            trace!("artifact {} dropped, only otlp is exported", frame.0.artifact_id);
+            return;
+        }
+        let artifact = (frame.0.artifact_id.clone(), frame.0.offset + frame.0.length as u64);
+        match simd_json::to_string(&frame.0) {
+            Ok(data) => {
+                self.enqueue(Frame {
+                    content: envelope(crate::trace::TraceArtifact::type_name().as_str(), data, self.compression),
+                    artifact: Some(artifact),
+                });
+                self.flush(ctx).await;
+            }
+            Err(e) => error!("{}", e)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Acknowledge> for SendClient {
+    async fn handle(&mut self, ctx: &Context<Self>, ack: Acknowledge) {
+        let Acknowledge { artifact_id, offset, resume } = ack;
+        self.unacked.retain(|x| match &x.artifact {
+            Some((id, end)) => *id != artifact_id || *end > offset,
+            None => false
+        });
+        if resume {
+            self.requeue(|(id, _)| *id == artifact_id);
+            self.flush(ctx).await;
+        }
+    }
+}
+
+pub struct OtlpExporter {
+    pub(crate) endpoint: String,
+    pub(crate) batch: Vec<OtlpRecord>,
+}
+
+#[xactor::message(result = "()")]
+pub struct OtlpRecord {
+    pub type_name: String,
+    pub payload: String,
+}
+
+#[xactor::message(result = "()")]
+pub struct FlushOtlp;
+
+impl OtlpExporter {
+    pub fn new(endpoint: String) -> Self {
+        OtlpExporter {
+            endpoint,
+            batch: Vec::new(),
+        }
+    }
+
+    async fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let now = crate::utils::unix_now() as u128 * 1_000_000_000;
+        // payloads travel as log records, so no particular profile schema is imposed on the collector
+        let records = self.batch.drain(..)
+            .map(|x| serde_json::json!({
+                "timeUnixNano": now.to_string(),
+                "body": { "stringValue": x.payload },
+                "attributes": [{ "key": "girasol.type", "value": { "stringValue": x.type_name } }]
+            }))
+            .collect::<Vec<_>>();
+        let count = records.len();
+        let body = serde_json::json!({
+            "resourceLogs": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": "girasol" } }]
+                },
+                "scopeLogs": [{
+                    "scope": { "name": "girasol" },
+                    "logRecords": records
+                }]
+            }]
+        });
+        match otlp_post(self.endpoint.as_str(), body.to_string()).await {
+            Ok(_) => debug!("exported {} records to {}", count, self.endpoint),
+            Err(e) => error!("failed to export {} records to {}: {}", count, self.endpoint, e)
+        }
+    }
+}
+
+async fn otlp_exchange<S>(stream: &mut S, request: String) -> anyhow::Result<String>
+    where S: async_std::io::Read + async_std::io::Write + Unpin {
+    use async_std::io::prelude::*;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(String::from_utf8_lossy(&response).to_string())
+}
+
+async fn otlp_post(endpoint: &str, body: String) -> anyhow::Result<()> {
+    let tls = endpoint.starts_with("https://");
+    let rest = endpoint.trim_start_matches("https://").trim_start_matches("http://");
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/v1/logs")
+    };
+    let host = authority.split(':').next().unwrap_or(authority);
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:{}", authority, if tls { 443 } else { 80 })
+    };
+    let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+                           Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                          path, authority, body.len(), body);
+    let mut stream = async_std::net::TcpStream::connect(address).await?;
+    let response = if tls {
+        let mut stream = async_tls::TlsConnector::default().connect(host, stream).await?;
+        otlp_exchange(&mut stream, request).await?
+    } else {
+        otlp_exchange(&mut stream, request).await?
+    };
+    let status = response.lines().next().unwrap_or("");
+    match status.split_ascii_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(anyhow::anyhow!("collector replied {}", status))
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for OtlpExporter {
+    async fn started(&mut self, ctx: &Context<Self>) {
+        info!("otlp exporter started for {}", self.endpoint);
+        ctx.send_interval_with(|| FlushOtlp, OTLP_FLUSH)
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<OtlpRecord> for OtlpExporter {
+    async fn handle(&mut self, _: &Context<Self>, record: OtlpRecord) {
+        self.batch.push(record);
+        if self.batch.len() >= OTLP_BATCH {
+            self.flush().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FlushOtlp> for OtlpExporter {
+    async fn handle(&mut self, _: &Context<Self>, _: FlushOtlp) {
+        self.flush().await;
+    }
+}
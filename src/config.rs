@@ -1,7 +1,9 @@
 use std::io::Write;
+use std::path::PathBuf;
 
 use anyhow::*;
 use log::*;
+use prettytable::*;
 use structopt::*;
 use xactor::Addr;
 
@@ -13,28 +15,157 @@ pub enum SubCommand {
     #[structopt(about = "Start the endpoint")]
     Endpoint {
         #[structopt(short, long, env = "GIRASOL_SERVER", help="The server websocket address")]
-        server: String
+        server: String,
+        #[structopt(short, long, default_value = "1024", help="Max frames to hold while the server is unreachable")]
+        queue: usize,
+        #[structopt(long, help="Compress payloads with zstd at this level unless the server negotiates another")]
+        compression: Option<i32>,
+        #[structopt(long, env = "GIRASOL_OTLP", help="Also export trace payloads to this OTLP/HTTP collector")]
+        otlp: Option<String>,
+        #[structopt(long, help="Only export trace payloads to the OTLP collector")]
+        otlp_only: bool,
+        #[structopt(long, help="Max trace rounds running at the same time, the rest wait in a queue")]
+        max_concurrent_traces: Option<usize>,
+        #[structopt(long, default_value = "30", help="Seconds to wait for running rounds on shutdown")]
+        drain_timeout: u64,
     },
     #[structopt(about = "Add new trace model")]
     Add {
         #[structopt(short, long, env = "EDITOR", default_value = "nano", help="The editor to use")]
-        editor: String
+        editor: String,
+        #[structopt(short, long, default_value = "perf", possible_values = crate::database::METHODS,
+        help="The trace method of the template")]
+        method: String,
+        #[structopt(short, long, parse(from_os_str),
+        help="Add every model file of the directory instead, all of them or none")]
+        dir: Option<PathBuf>,
+        #[structopt(long, help="Skip checking the target binary on this machine")]
+        no_check: bool
     },
-    #[structopt(about = "Remove a trace model")]
+    #[structopt(about = "Remove trace models")]
     Remove {
+        #[structopt(short, long, required = true, help="The name of the model, all of them or none go if repeated")]
+        name: Vec<String>
+    },
+    #[structopt(about = "Rename a trace model")]
+    Rename {
+        #[structopt(help="The current name of the model")]
+        from: String,
+        #[structopt(help="The new name of the model")]
+        to: String,
+    },
+    #[structopt(about = "Update an existing trace model in the editor", alias = "edit")]
+    Update {
+        #[structopt(short, long, help="The name of the model")]
+        name: String,
+        #[structopt(short, long, env = "EDITOR", default_value = "nano", help="The editor to use")]
+        editor: String,
+        #[structopt(long, help="Skip checking the target binary on this machine")]
+        no_check: bool
+    },
+    #[structopt(about = "Enable a trace model")]
+    Enable {
+        #[structopt(short, long, help="The name of the model")]
+        name: String
+    },
+    #[structopt(about = "Disable a trace model without removing it")]
+    Disable {
+        #[structopt(short, long, help="The name of the model")]
+        name: String
+    },
+    #[structopt(about = "Archive a trace model, keeping it and its history but never running it")]
+    Archive {
+        #[structopt(short, long, help="The name of the model")]
+        name: String
+    },
+    #[structopt(about = "Bring an archived trace model back")]
+    Unarchive {
         #[structopt(short, long, help="The name of the model")]
         name: String
     },
     #[structopt(about = "List all trace models")]
     List {
         #[structopt(short, long, help="Whether to show detailed information in json")]
-        detail: bool
+        detail: bool,
+        #[structopt(short, long, help="Only list models whose names start with this prefix")]
+        filter: Option<String>,
+        #[structopt(long = "tag", help="Only list models carrying this tag, may be repeated")]
+        tags: Vec<String>,
+        #[structopt(long, help="Print all models as a json array for scripts")]
+        json: bool,
+        #[structopt(long, help="List the archived models instead")]
+        archived: bool
     },
     #[structopt(about = "Check one trace model")]
     Check {
         #[structopt(short, long, help="The name of the model")]
         name: String
     },
+    #[structopt(about = "Export trace models into a json file")]
+    Export {
+        #[structopt(short, long, parse(from_os_str), help="The output file")]
+        output: PathBuf,
+        #[structopt(short, long = "name", help="Only export the model of this name, may be repeated")]
+        names: Vec<String>,
+    },
+    #[structopt(about = "Import trace models from a json file")]
+    Import {
+        #[structopt(short, long, parse(from_os_str), help="The input file")]
+        input: PathBuf,
+        #[structopt(short, long, help="Whether to overwrite existing models")]
+        overwrite: bool,
+        #[structopt(long, help="Skip checking the target binaries on this machine")]
+        no_check: bool
+    },
+    #[structopt(about = "Show traces running in the active endpoint")]
+    Status,
+    #[structopt(about = "Snapshot the database into a file, through the endpoint if one is active")]
+    Backup {
+        #[structopt(parse(from_os_str), help="The backup file")]
+        output: PathBuf
+    },
+    #[structopt(about = "Replace the database with a backup, the endpoint has to be stopped")]
+    Restore {
+        #[structopt(parse(from_os_str), help="The backup file")]
+        input: PathBuf
+    },
+    #[structopt(about = "Pause a trace in the active endpoint, which persists across restarts")]
+    Pause {
+        #[structopt(short, long, help="The name of the model")]
+        name: String
+    },
+    #[structopt(about = "Resume a paused trace in the active endpoint")]
+    Resume {
+        #[structopt(short, long, help="The name of the model")]
+        name: String
+    },
+    #[structopt(about = "Compare the function profiles of two stored rounds")]
+    Diff {
+        #[structopt(short, long, help="The name of the model")]
+        name: String,
+        #[structopt(short, long, help="The round to compare against, the baseline if omitted")]
+        from: Option<usize>,
+        #[structopt(short, long, help="The round to compare, the latest if omitted")]
+        to: Option<usize>
+    },
+    #[structopt(about = "Pin a stored round as the baseline of a model")]
+    Baseline {
+        #[structopt(short, long, help="The name of the model")]
+        name: String,
+        #[structopt(short, long, help="The round to pin, the latest if omitted")]
+        round: Option<usize>
+    },
+    #[structopt(about = "Show the latest rounds of a model")]
+    History {
+        #[structopt(short, long, help="The name of the model")]
+        name: String,
+        #[structopt(short, long, help="Max rounds to show")]
+        limit: Option<usize>,
+        #[structopt(long, help="Print the rounds as a json array for scripts")]
+        json: bool
+    },
+    #[structopt(about = "Maintain the database")]
+    Db(DbCommand),
     #[structopt(about = "Local run")]
     Local {
         #[structopt(short, long, help="The name of the model")]
@@ -42,26 +173,116 @@ pub enum SubCommand {
         #[structopt(short, long, help="Round to go")]
         round: usize,
         #[structopt(short, long, help="Output file pattern")]
-        pattern: String
+        pattern: String,
+        #[structopt(long, help="Print the command to run without spawning it")]
+        dry_run: bool
     }
 }
 
+#[derive(StructOpt, Debug)]
+pub enum DbCommand {
+    #[structopt(about = "Look for entries that cannot be read or belong to no model")]
+    Verify {
+        #[structopt(long, help="Move the entries found into the quarantine tree")]
+        quarantine: bool
+    },
+}
 
 #[derive(StructOpt, Debug)]
 pub struct Config {
     #[structopt(short = "d", long, env = "GIRASOL_HOME", help = "The home directory of Girasol")]
     pub home: String,
+    #[structopt(long, env = "GIRASOL_REDACT", use_delimiter = true,
+                help = "Regex whose matches are masked in payloads sent upstream and in stored round records")]
+    pub redact: Vec<String>,
+    #[structopt(long, env = "GIRASOL_DB_KEY_FILE", parse(from_os_str),
+                help = "File of a 32 bytes key, raw or in base64, to encrypt the stored models with")]
+    pub db_key_file: Option<PathBuf>,
+    #[structopt(long, env = "GIRASOL_STORAGE", default_value = "sled", possible_values = crate::storage::BACKENDS,
+                help = "The storage backend of the database, sqlite suits homes on network filesystems")]
+    pub storage: String,
+    #[structopt(long, help = "Days to keep round records and profiles for")]
+    pub history_max_days: Option<u64>,
+    #[structopt(long, default_value = "32", help = "Round records and profiles to keep per model")]
+    pub history_max_entries: usize,
+    #[structopt(long, help = "Max megabytes of round records, and of profiles, to keep")]
+    pub history_max_mb: Option<u64>,
     #[structopt(subcommand)]
     pub subcommand: SubCommand,
 }
 
-pub async fn handle_list(mut db: Addr<crate::database::DataActor>, detail: bool) {
-    match db.call(DbMsg::QueryAll).await
+impl Config {
+    pub fn history(&self) -> crate::database::HistoryRetention {
+        crate::database::HistoryRetention {
+            max_age_secs: self.history_max_days.map(|x| x * 24 * 3600),
+            max_entries: self.history_max_entries,
+            max_bytes: self.history_max_mb.map(|x| x * 1024 * 1024),
+        }
+    }
+
+    pub fn check_path(&self) -> bool {
+        match &self.subcommand {
+            SubCommand::Add { no_check, .. }
+            | SubCommand::Update { no_check, .. }
+            | SubCommand::Import { no_check, .. } => !*no_check,
+            _ => true
+        }
+    }
+}
+
+const LIST_PAGE: usize = 256;
+
+/// Prints all models page by page, so that agents with many models never hold them all.
+async fn list_paged(db: Addr<crate::database::DataActor>, detail: bool, json: bool, archived: bool) -> Result<()> {
+    use futures::StreamExt;
+    let mut pages = Box::pin(crate::database::model_pages(db, LIST_PAGE));
+    let mut names = Vec::new();
+    let mut first = true;
+    if json || detail {
+        println!("[");
+    }
+    while let Some(page) = pages.next().await {
+        for i in page? {
+            if i.archived_at.is_some() != archived {
+                continue;
+            }
+            if !(json || detail) {
+                names.push(i.name);
+                continue;
+            }
+            let model = if json { simd_json::to_string(&i)? } else { simd_json::to_string_pretty(&i)? };
+            if !first {
+                println!(",");
+            }
+            print!("{}", model);
+            first = false;
+        }
+    }
+    if json || detail {
+        println!("\n]");
+    } else {
+        println!("{}", simd_json::to_string_pretty(&names)?);
+    }
+    Ok(())
+}
+
+pub async fn handle_list(mut db: Addr<crate::database::DataActor>, detail: bool, filter: Option<String>,
+                         tags: Vec<String>, json: bool, archived: bool) {
+    let msg = match (filter, tags.is_empty()) {
+        (None, true) => return list_paged(db, detail, json, archived).await.check_error(),
+        (prefix, _) => DbMsg::Search { prefix, contains: None, tags },
+    };
+    match db.call(msg).await
         .map_err(|x| x.into())
         .and_then(|x| x) {
         Err(e) => error!("{}", e),
-        Ok(DbReply::AllList(list)) => {
-            if detail {
+        Ok(DbReply::AllList(mut list)) => {
+            list.retain(|x| x.archived_at.is_some() == archived);
+            if json {
+                simd_json::to_string(&list).map(|x| println!("{}", x))
+                    .map_err(|x| x.into())
+                    .check_error()
+            } else if detail {
                 simd_json::to_string_pretty(&list).map(|x| println!("{}", x))
                     .map_err(|x| x.into())
                     .check_error()
@@ -77,11 +298,11 @@ pub async fn handle_list(mut db: Addr<crate::database::DataActor>, detail: bool)
     }
 }
 
-pub async fn handle_add(mut db: Addr<crate::database::DataActor>, editor: String) {
-    let content: Result<TraceModel, Error> = tempfile::NamedTempFile::new()
+fn edit_model(editor: String, model: &TraceModel) -> Result<TraceModel> {
+    tempfile::NamedTempFile::new()
         .map_err(|x| x.into())
         .and_then(|mut file| {
-            simd_json::to_string_pretty(&crate::database::TraceModel::default())
+            simd_json::to_string_pretty(model)
                 .map_err(|x| x.into())
                 .and_then(|x| file.write_all(x.as_bytes())
                     .map_err(|x| x.into()))
@@ -104,7 +325,15 @@ pub async fn handle_add(mut db: Addr<crate::database::DataActor>, editor: String
         .and_then(|x| {
             simd_json::from_reader(x)
                 .map_err(|x| x.into())
-        });
+        })
+}
+
+pub async fn handle_add(mut db: Addr<crate::database::DataActor>, editor: String, method: String) {
+    let content = crate::database::TraceContent::template(method.as_str())
+        .and_then(|content| edit_model(editor, &TraceModel {
+            content,
+            ..TraceModel::default()
+        }));
     match content {
         Ok(model) => {
             to_table(&model).map(|x| x.printstd())
@@ -133,7 +362,61 @@ pub async fn handle_add(mut db: Addr<crate::database::DataActor>, editor: String
     }
 }
 
-pub async fn handle_check(mut db: Addr<crate::database::DataActor>, name: String) -> bool {
+/// Reads the models of a directory, one model per `.json` file.
+fn read_model_dir(dir: &PathBuf) -> Result<Vec<TraceModel>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|x| x.extension().map(|x| x == "json").unwrap_or(false));
+    paths.sort();
+    paths.iter()
+        .map(|x| std::fs::File::open(x)
+            .map_err(|e| e.into())
+            .and_then(|file| simd_json::from_reader(file).map_err(|e| e.into()))
+            .map_err(|e: Error| anyhow!("{}: {}", x.display(), e)))
+        .collect()
+}
+
+pub async fn handle_add_dir(mut db: Addr<crate::database::DataActor>, dir: PathBuf) {
+    let models = match read_model_dir(&dir) {
+        Ok(models) => models,
+        Err(e) => {
+            error!("{}", e);
+            async_std::process::exit(1);
+        }
+    };
+    if models.is_empty() {
+        warn!("no model files in {}", dir.display());
+        return;
+    }
+    let names = models.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+    match db.call(DbMsg::AddMany(models)).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("nothing added: {}", e),
+        _ => info!("added {} models: {}", names.len(), names.join(", "))
+    }
+}
+
+pub async fn handle_remove_many(mut db: Addr<crate::database::DataActor>, names: Vec<String>) {
+    println!("are you sure to remove: {} [Y/n]", names.join(", "));
+    let mut line = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut line) {
+        error!("{}", e);
+        async_std::process::exit(1);
+    }
+    if "y" != line.trim().to_ascii_lowercase() {
+        async_std::process::exit(0);
+    }
+    match db.call(DbMsg::RemoveMany(names)).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("nothing removed: {}", e),
+        _ => info!("removed successfully")
+    }
+}
+
+pub async fn handle_check(mut db: Addr<crate::database::DataActor>, name: String) -> Option<u64> {
     match db.call(DbMsg::Get(name)).await
         .map_err(|x| x.into())
         .and_then(|x| x) {
@@ -141,7 +424,7 @@ pub async fn handle_check(mut db: Addr<crate::database::DataActor>, name: String
             match to_table(&model) {
                 Ok(e) => {
                     e.printstd();
-                    return true;
+                    return Some(model.revision);
                 }
                 Err(e) => {
                     error!("{}", e);
@@ -151,13 +434,14 @@ pub async fn handle_check(mut db: Addr<crate::database::DataActor>, name: String
         Err(e) => error!("{}", e),
         _ => unsafe { std::intrinsics::unreachable(); }
     }
-    false
+    None
 }
 
 pub async fn handle_remove(mut db: Addr<crate::database::DataActor>, name: String) {
-    if !handle_check(db.clone(), name.clone()).await {
-        async_std::process::exit(1);
-    }
+    let revision = match handle_check(db.clone(), name.clone()).await {
+        Some(revision) => revision,
+        None => async_std::process::exit(1)
+    };
     println!("are you sure to remove: {} [Y/n]", name);
     let mut line = String::new();
     if let Err(e) = std::io::stdin().read_line(&mut line) {
@@ -167,10 +451,290 @@ pub async fn handle_remove(mut db: Addr<crate::database::DataActor>, name: Strin
     if "y" != line.trim().to_ascii_lowercase() {
         async_std::process::exit(0);
     }
-    match db.call(DbMsg::Remove(name)).await
+    match db.call(DbMsg::Remove { name, revision: Some(revision) }).await
         .map_err(|x| x.into())
         .and_then(|x| x) {
         Err(e) => error!("{}", e),
         _ => info!("removed successfully")
     }
-}
\ No newline at end of file
+}
+
+pub async fn handle_rename(mut db: Addr<crate::database::DataActor>, from: String, to: String) {
+    match db.call(DbMsg::Rename { from: from.clone(), to: to.clone() }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        _ => info!("renamed {} to {}", from, to)
+    }
+}
+
+pub async fn handle_update(mut db: Addr<crate::database::DataActor>, name: String, editor: String) {
+    let content = match db.call(DbMsg::Get(name.clone())).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Ok(DbReply::GetResult(previous)) => edit_model(editor, &previous).map(|x| (previous, x)),
+        Err(e) => Err(e),
+        _ => Err(anyhow!("unexpected database reply"))
+    };
+    match content {
+        Ok((previous, model)) => {
+            if model.name != name {
+                error!("cannot rename {} to {} during update", name, model.name);
+                async_std::process::exit(1);
+            }
+            to_table(&model).map(|x| x.printstd())
+                .check_error();
+            println!("are you sure to update: {} [Y/n]", model.name);
+            let mut line = String::new();
+            if let Err(e) = std::io::stdin().read_line(&mut line) {
+                error!("{}", e);
+                async_std::process::exit(1);
+            }
+            if "y" != line.trim().to_ascii_lowercase() {
+                async_std::process::exit(0);
+            }
+            match db.call(DbMsg::Update { model, revision: previous.revision }).await
+                .map_err(|x| x.into())
+                .and_then(|x| x) {
+                Err(e) => error!("{}", e),
+                _ => info!("updated successfully")
+            }
+        }
+        Err(e) => error!("{}", e)
+    }
+}
+
+pub async fn handle_enable(mut db: Addr<crate::database::DataActor>, name: String, enabled: bool) {
+    match db.call(DbMsg::SetEnabled { name: name.clone(), enabled }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        _ if enabled => info!("{} enabled", name),
+        _ => info!("{} disabled", name)
+    }
+}
+
+pub async fn handle_archive(mut db: Addr<crate::database::DataActor>, name: String, archived: bool) {
+    match db.call(DbMsg::SetArchived { name: name.clone(), archived }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        _ if archived => info!("{} archived", name),
+        _ => info!("{} unarchived", name)
+    }
+}
+
+pub async fn handle_export(mut db: Addr<crate::database::DataActor>, output: PathBuf, names: Vec<String>) {
+    match db.call(DbMsg::QueryAll).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        Ok(DbReply::AllList(mut list)) => {
+            if !names.is_empty() {
+                list.retain(|x| names.contains(&x.name));
+                for i in names.iter().filter(|x| list.iter().all(|y| &y.name != *x)) {
+                    error!("{} does not exist", i);
+                }
+            }
+            match simd_json::to_string_pretty(&list)
+                .map_err(|x| x.into())
+                .and_then(|x| std::fs::write(&output, x).map_err(|x| x.into())) {
+                Err(e) => error!("{}", e),
+                _ => info!("exported {} models to {}", list.len(), output.display())
+            }
+        }
+        _ => error!("unexpected database reply")
+    }
+}
+
+pub async fn handle_import(mut db: Addr<crate::database::DataActor>, input: PathBuf, overwrite: bool) {
+    let list: Result<Vec<TraceModel>> = std::fs::File::open(&input)
+        .map_err(|x| x.into())
+        .and_then(|x| simd_json::from_reader(x).map_err(|x| x.into()));
+    match list {
+        Ok(list) => {
+            let mut failed = Vec::new();
+            let mut imported = Vec::new();
+            let mut conflicts = Vec::new();
+            for model in list {
+                let name = model.name.clone();
+                if !overwrite {
+                    if let Ok(Ok(DbReply::GetResult(_))) = db.call(DbMsg::Get(name.clone())).await {
+                        conflicts.push(name);
+                        continue;
+                    }
+                }
+                let msg = if overwrite { DbMsg::Upsert(model) } else { DbMsg::Add(model) };
+                match db.call(msg).await
+                    .map_err(|x| x.into())
+                    .and_then(|x| x) {
+                    Err(e) => failed.push((name, e)),
+                    _ => imported.push(name)
+                }
+            }
+            for name in &imported {
+                info!("imported {}", name);
+            }
+            for name in &conflicts {
+                warn!("skipped {} as it exists, pass --overwrite to replace it", name);
+            }
+            for (name, e) in &failed {
+                error!("failed to import {}: {}", name, e);
+            }
+            info!("{} models imported, {} skipped, {} failed", imported.len(), conflicts.len(), failed.len());
+        }
+        Err(e) => error!("{}", e)
+    }
+}
+
+async fn query_profile(db: &mut Addr<crate::database::DataActor>, msg: DbMsg)
+                       -> Result<crate::trace::FunctionProfile> {
+    match db.call(msg).await?? {
+        DbReply::ProfileResult(profile) => Ok(profile),
+        _ => Err(anyhow!("unexpected database reply"))
+    }
+}
+
+pub async fn handle_diff(mut db: Addr<crate::database::DataActor>, name: String,
+                         from: Option<usize>, to: Option<usize>) {
+    let before = match from {
+        Some(round) => query_profile(&mut db, DbMsg::Profile { name: name.clone(), round: Some(round) }).await,
+        None => query_profile(&mut db, DbMsg::Baseline(name.clone())).await
+    };
+    let after = query_profile(&mut db, DbMsg::Profile { name, round: to }).await;
+    match (before, after) {
+        (Ok(before), Ok(after)) => {
+            let mut table = Table::new();
+            table.add_row(row![bFy->"function", bFy->"before", bFy->"after", bFy->"delta"]);
+            for i in crate::trace::diff_profiles(&before, &after) {
+                table.add_row(row![bFb->i.function, format!("{:.2}%", i.before_pct),
+                    format!("{:.2}%", i.after_pct), format!("{:+.2}%", i.delta_pct)]);
+            }
+            println!("round {} -> round {}", before.round, after.round);
+            table.printstd();
+        }
+        (Err(e), _) | (_, Err(e)) => error!("{}", e)
+    }
+}
+
+pub async fn handle_baseline(mut db: Addr<crate::database::DataActor>, name: String, round: Option<usize>) {
+    match db.call(DbMsg::SetBaseline { name: name.clone(), round }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        _ => info!("baseline of {} updated", name)
+    }
+}
+
+pub async fn handle_history(mut db: Addr<crate::database::DataActor>, name: String,
+                            limit: Option<usize>, json: bool) {
+    let list = match db.call(DbMsg::History { name, limit }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Ok(DbReply::RunList(list)) => list,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+        _ => {
+            error!("unexpected database reply");
+            return;
+        }
+    };
+    if json {
+        simd_json::to_string(&list).map(|x| println!("{}", x))
+            .map_err(|x| x.into())
+            .check_error();
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row![bFy->"round", bFy->"started", bFy->"seconds", bFy->"status",
+                       bFy->"attempt", bFy->"exit code", bFy->"bytes", bFy->"records", bFy->"output"]);
+    for i in list {
+        let started = chrono::NaiveDateTime::from_timestamp(i.started_at as i64, 0);
+        table.add_row(row![bFb->i.round, started, i.ended_at.saturating_sub(i.started_at),
+                           if i.failed { "failed" } else { "ok" }, i.attempt,
+                           i.exit_code.map(|x| x.to_string()).unwrap_or_default(),
+                           i.output_bytes, i.records, i.output.unwrap_or_default()]);
+    }
+    table.printstd();
+}
+
+pub async fn handle_verify(mut db: Addr<crate::database::DataActor>, quarantine: bool) {
+    let list = match db.call(DbMsg::Verify { quarantine }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Ok(DbReply::Verified(list)) => list,
+        Err(e) => {
+            error!("{}", e);
+            async_std::process::exit(1);
+        }
+        _ => {
+            error!("unexpected database reply");
+            async_std::process::exit(1);
+        }
+    };
+    if list.is_empty() {
+        info!("no problems found");
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row![bFy->"tree", bFy->"key", bFy->"problem"]);
+    for i in &list {
+        table.add_row(row![bFb->i.tree, i.key, i.problem]);
+    }
+    table.printstd();
+    if quarantine {
+        info!("{} entries moved into quarantine", list.len());
+    } else {
+        warn!("{} entries found, pass --quarantine to move them aside", list.len());
+        async_std::process::exit(1);
+    }
+}
+
+pub async fn handle_backup(mut db: Addr<crate::database::DataActor>, output: PathBuf) {
+    match db.call(DbMsg::Backup(output.clone())).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        _ => info!("backed up to {}", output.display())
+    }
+}
+
+pub async fn handle_restore(mut db: Addr<crate::database::DataActor>, home: &str, input: PathBuf) {
+    println!("are you sure to replace the database with {} [Y/n]", input.display());
+    let mut line = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut line) {
+        error!("{}", e);
+        async_std::process::exit(1);
+    }
+    match line.trim().to_ascii_lowercase().as_str() {
+        "" | "y" | "yes" => (),
+        _ => async_std::process::exit(0)
+    }
+    let previous = PathBuf::from(home).join("pre-restore");
+    match db.call(DbMsg::Restore { input: input.clone(), previous }).await
+        .map_err(|x| x.into())
+        .and_then(|x| x) {
+        Err(e) => error!("{}", e),
+        _ => info!("restored from {}", input.display())
+    }
+}
+
+pub async fn handle_control(home: &str, request: crate::control::ControlRequest) {
+    match crate::control::request(home, request).await {
+        Ok(crate::control::ControlReply::Done(message)) => info!("{}", message),
+        Ok(crate::control::ControlReply::Error(e)) => error!("{}", e),
+        Ok(crate::control::ControlReply::Status(_)) => error!("unexpected reply from the endpoint"),
+        Err(e) => error!("{}", e)
+    }
+}
+
+pub async fn handle_status(home: &str) {
+    match crate::control::query_status(home).await {
+        Ok(list) => {
+            crate::control::to_status_table(&list).printstd();
+        }
+        Err(e) => error!("{}", e)
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs::read_dir;
+use std::process::Command;
+
+use anyhow::*;
+
+use crate::database::{ContainerRuntime, ContainerTarget};
+
+fn inspect_pid(program: &str, container_id: &str) -> Result<u32> {
+    let output = Command::new(program)
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{.State.Pid}}")
+        .arg(container_id)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{} inspect failed: {}", program, String::from_utf8_lossy(&output.stderr)));
+    }
+    match String::from_utf8_lossy(&output.stdout).trim().parse::<u32>()? {
+        0 => Err(anyhow!("container {} is not running", container_id)),
+        pid => Ok(pid)
+    }
+}
+
+fn cgroup_pid(container_id: &str) -> Result<u32> {
+    read_dir("/proc")?
+        .filter_map(Result::ok)
+        .filter_map(|x| x.file_name().to_str().and_then(|x| x.parse::<u32>().ok()))
+        .filter(|x| std::fs::read_to_string(format!("/proc/{}/cgroup", x))
+            .map(|x| x.contains(container_id))
+            .unwrap_or(false))
+        // the init process of the container is the oldest one in its cgroup, pids may wrap around
+        .min_by_key(|x| (crate::utils::start_ticks(*x as i32).unwrap_or(u64::MAX), *x))
+        .ok_or_else(|| anyhow!("no process found in the cgroup of container {}", container_id))
+}
+
+pub fn resolve_init_pid(target: &ContainerTarget) -> Result<u32> {
+    match target.runtime {
+        ContainerRuntime::Docker => inspect_pid("docker", target.container_id.as_str())
+            .or_else(|_| cgroup_pid(target.container_id.as_str())),
+        ContainerRuntime::Podman => inspect_pid("podman", target.container_id.as_str())
+            .or_else(|_| cgroup_pid(target.container_id.as_str())),
+        ContainerRuntime::Containerd => cgroup_pid(target.container_id.as_str()),
+    }
+}
+
+pub fn same_pid_namespace(pid: i32, init_pid: u32) -> bool {
+    match (std::fs::read_link(format!("/proc/{}/ns/pid", pid)),
+           std::fs::read_link(format!("/proc/{}/ns/pid", init_pid))) {
+        (Ok(x), Ok(y)) => x == y,
+        _ => false
+    }
+}
+
+pub fn nsenter_args(init_pid: u32) -> Vec<String> {
+    vec![String::from("-t"), init_pid.to_string(), String::from("-m"), String::from("-u"), String::from("-i"),
+         String::from("-n"), String::from("-p"), String::from("--")]
+}
@@ -0,0 +1,157 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+#[cfg(unix)]
+use async_std::io::prelude::*;
+#[cfg(unix)]
+use async_std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use async_std::stream::StreamExt;
+use log::*;
+use prettytable::*;
+use xactor::Addr;
+
+use crate::database::DataActor;
+#[cfg(unix)]
+use crate::database::DbMsg;
+use crate::trace::{HouseKeeper, TraceStatus};
+#[cfg(unix)]
+use crate::trace::{AllStatus, SetPaused};
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum ControlRequest {
+    Status,
+    Pause(String),
+    Resume(String),
+    Backup(PathBuf),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum ControlReply {
+    Status(Vec<TraceStatus>),
+    Done(String),
+    Error(String),
+}
+
+/// How long a client may take to send its request.
+#[cfg(unix)]
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub fn socket_path<A: AsRef<Path>>(home: A) -> PathBuf {
+    home.as_ref().join("control.sock")
+}
+
+#[cfg(unix)]
+pub async fn serve<A: AsRef<Path>>(home: A, keeper: Addr<HouseKeeper>, db: Addr<DataActor>) -> Result<()> {
+    let path = socket_path(home);
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path).await?;
+    info!("control socket listening at {}", path.display());
+    async_std::task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(mut stream) => {
+                    let mut keeper = keeper.clone();
+                    let mut db = db.clone();
+                    // one slow client does not hold up the others
+                    async_std::task::spawn(async move {
+                        let reply = match handle_request(&mut stream, &mut keeper, &mut db).await {
+                            Ok(reply) => reply,
+                            Err(e) => ControlReply::Error(e.to_string())
+                        };
+                        match simd_json::to_string(&reply) {
+                            Ok(json) => if let Err(e) = stream.write_all(json.as_bytes()).await {
+                                error!("failed to reply control request: {}", e);
+                            },
+                            Err(e) => error!("failed to encode control reply: {}", e)
+                        }
+                    });
+                }
+                Err(e) => error!("control socket error: {}", e)
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn handle_request(stream: &mut UnixStream, keeper: &mut Addr<HouseKeeper>, db: &mut Addr<DataActor>)
+                        -> Result<ControlReply> {
+    let mut content = String::new();
+    async_std::future::timeout(REQUEST_TIMEOUT, stream.read_to_string(&mut content)).await
+        .map_err(|_| anyhow!("no control request within {}s", REQUEST_TIMEOUT.as_secs()))??;
+    let request: ControlRequest = simd_json::from_str(content.as_mut_str())?;
+    match request {
+        ControlRequest::Status => keeper.call(AllStatus).await
+            .map(ControlReply::Status),
+        ControlRequest::Pause(name) => keeper.call(SetPaused { name: name.clone(), paused: true }).await?
+            .map(|_| ControlReply::Done(format!("{} paused", name))),
+        ControlRequest::Resume(name) => keeper.call(SetPaused { name: name.clone(), paused: false }).await?
+            .map(|_| ControlReply::Done(format!("{} resumed", name))),
+        ControlRequest::Backup(path) => db.call(DbMsg::Backup(path.clone())).await?
+            .map(|_| ControlReply::Done(format!("backed up to {}", path.display()))),
+    }
+}
+
+#[cfg(unix)]
+pub async fn request<A: AsRef<Path>>(home: A, request: ControlRequest) -> Result<ControlReply> {
+    let path = socket_path(home);
+    let mut stream = match UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound
+            || e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            return Err(anyhow!("no active endpoint"));
+        }
+        Err(e) => return Err(e.into())
+    };
+    stream.write_all(simd_json::to_string(&request)?.as_bytes()).await?;
+    // the endpoint reads the request until the write half is closed
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut content = String::new();
+    stream.read_to_string(&mut content).await?;
+    simd_json::from_str(content.as_mut_str()).map_err(|x| x.into())
+}
+
+/// Whether an endpoint is listening on the control socket.
+#[cfg(unix)]
+pub async fn endpoint_active<A: AsRef<Path>>(home: A) -> bool {
+    UnixStream::connect(socket_path(home)).await.is_ok()
+}
+
+// there are no unix sockets to talk over, so the endpoint cannot be reached from the command line
+#[cfg(not(unix))]
+pub async fn serve<A: AsRef<Path>>(_: A, _: Addr<HouseKeeper>, _: Addr<DataActor>) -> Result<()> {
+    warn!("the control socket is only available on unix, status, pause and resume are not served");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn request<A: AsRef<Path>>(_: A, _: ControlRequest) -> Result<ControlReply> {
+    Err(anyhow!("the control socket is only available on unix"))
+}
+
+#[cfg(not(unix))]
+pub async fn endpoint_active<A: AsRef<Path>>(_: A) -> bool {
+    false
+}
+
+pub async fn query_status<A: AsRef<Path>>(home: A) -> Result<Vec<TraceStatus>> {
+    match request(home, ControlRequest::Status).await? {
+        ControlReply::Status(list) => Ok(list),
+        ControlReply::Error(e) => Err(anyhow!(e)),
+        ControlReply::Done(_) => Err(anyhow!("unexpected reply to status query"))
+    }
+}
+
+pub fn to_status_table(list: &[TraceStatus]) -> Table {
+    let mut table = Table::new();
+    table.add_row(row![bFy->"name", bFy->"method", bFy->"running", bFy->"lasting", bFy->"sent", bFy->"paused"]);
+    for i in list {
+        table.add_row(row![bFb->i.name, i.method, format!("{}s", i.running),
+            format!("{}s", i.lasting), i.sent, i.paused]);
+    }
+    table
+}
@@ -10,145 +10,2023 @@ use log::*;
 use serde::Serialize;
 use xactor::{Actor, Handler, Message};
 
-pub async fn init<A: AsRef<Path>>(home: A) -> Result<sled::Db> {
-    sled::open(home.as_ref().join("database"))
-        .map_err(|x| x.into())
-}
-
-pub async fn query_str<A: AsRef<str>>(key: A, db: &sled::Db) -> Result<String> {
-    db.get(key.as_ref())
-        .map_err(|x| x.into())
-        .and_then(|x|
-            x.ok_or(Error::msg(format!("key {} not set", key.as_ref()))))
-        .and_then(|x| String::from_utf8(x.to_vec())
-            .map_err(|x| x.into()))
-}
-
-pub async fn query_json<K, T>(key: K, db: &sled::Db) -> Result<T>
-    where for<'de>
-          T: serde::Deserialize<'de>,
-          K: AsRef<str> {
-    db.get(key.as_ref())
-        .map_err(|x| x.into())
-        .and_then(|x|
-            x.ok_or(Error::msg(format!("key {} not set", key.as_ref()))))
-        .and_then(|x| {
-            let mut v = x.to_vec();
-            simd_json::serde::from_slice(v.as_mut_slice())
-                .map_err(|x| x.into())
+use crate::storage::{DEFAULT_TREE, Storage};
+
+pub async fn init<A: AsRef<Path>>(home: A, backend: &str, sealer: &Sealer) -> Result<Box<dyn Storage>> {
+    let db = crate::storage::open(backend, home.as_ref())?;
+    split_trees(db.as_ref())?;
+    migrate(db.as_ref(), sealer)?;
+    Ok(db)
+}
+
+const SEALED_MAGIC: &[u8] = b"GSE1";
+const NONCE_SIZE: usize = 12;
+
+/// Encrypts stored models with ChaCha20-Poly1305, values written before a key was set are read as they are.
+#[derive(Clone, Default)]
+pub struct Sealer {
+    cipher: Option<chacha20poly1305::ChaCha20Poly1305>,
+}
+
+impl Sealer {
+    /// The key file holds 32 bytes, either raw or in base64.
+    pub fn from_key_file<A: AsRef<Path>>(path: A) -> Result<Self> {
+        use chacha20poly1305::aead::NewAead;
+        let content = std::fs::read(path.as_ref())?;
+        let key = if content.len() == 32 {
+            content
+        } else {
+            base64::decode(String::from_utf8_lossy(&content).trim())?
+        };
+        if key.len() != 32 {
+            return Err(anyhow!("{} does not hold a 32 bytes key", path.as_ref().display()));
+        }
+        let key = chacha20poly1305::aead::generic_array::GenericArray::from_slice(&key);
+        Ok(Sealer {
+            cipher: Some(chacha20poly1305::ChaCha20Poly1305::new(key)),
         })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    fn sealed(value: &[u8]) -> bool {
+        value.starts_with(SEALED_MAGIC)
+    }
+
+    pub fn seal(&self, plain: Vec<u8>) -> Result<Vec<u8>> {
+        use chacha20poly1305::aead::Aead;
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => return Ok(plain)
+        };
+        let nonce = rand::random::<[u8; NONCE_SIZE]>();
+        let sealed = cipher.encrypt(chacha20poly1305::aead::generic_array::GenericArray::from_slice(&nonce),
+                                    plain.as_slice())
+            .map_err(|_| anyhow!("cannot encrypt value"))?;
+        Ok([SEALED_MAGIC, &nonce[..], sealed.as_slice()].concat())
+    }
+
+    pub fn open(&self, stored: &[u8]) -> Result<Vec<u8>> {
+        use chacha20poly1305::aead::Aead;
+        if !Sealer::sealed(stored) {
+            return Ok(stored.to_vec());
+        }
+        let cipher = self.cipher.as_ref()
+            .ok_or_else(|| anyhow!("value is encrypted, but no database key is given"))?;
+        let nonce = stored.get(SEALED_MAGIC.len()..SEALED_MAGIC.len() + NONCE_SIZE)
+            .ok_or_else(|| anyhow!("encrypted value is truncated"))?;
+        cipher.decrypt(chacha20poly1305::aead::generic_array::GenericArray::from_slice(nonce),
+                       &stored[SEALED_MAGIC.len() + NONCE_SIZE..])
+            .map_err(|_| anyhow!("cannot decrypt value, is the database key right?"))
+    }
+}
+
+const SLED_DEFAULT_TREE: &str = "__sled__default";
+
+/// A tree of the database in a portable form, keys and values are in base64.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SnapshotTree {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+/// Dumps every tree of the database into a file, returning the number of entries written.
+fn backup(db: &dyn Storage, path: &Path) -> Result<usize> {
+    let mut count = 0;
+    let mut trees = Vec::new();
+    for name in db.tree_names()? {
+        let entries = db.scan_prefix(name.as_str(), &[])?
+            .into_iter()
+            .map(|(key, value)| (base64::encode(&key), base64::encode(&value)))
+            .collect::<Vec<_>>();
+        count += entries.len();
+        trees.push(SnapshotTree {
+            name: base64::encode(&name),
+            entries,
+        });
+    }
+    // written aside first, so that an interrupted backup never replaces a good one
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, serde_json::to_vec(&trees)?)?;
+    std::fs::rename(&partial, path)?;
+    Ok(count)
+}
+
+type Snapshot = Vec<(String, Vec<(Vec<u8>, Vec<u8>)>)>;
+
+fn read_snapshot(path: &Path) -> Result<Snapshot> {
+    let trees: Vec<SnapshotTree> = serde_json::from_slice(&std::fs::read(path)?)?;
+    let mut decoded = Vec::new();
+    for i in trees {
+        let entries = i.entries.iter()
+            .map(|(key, value)| Ok((base64::decode(key)?, base64::decode(value)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let name = String::from_utf8(base64::decode(&i.name)?)?;
+        // backups taken straight from sled name the model tree after its default tree
+        let name = if name == SLED_DEFAULT_TREE { DEFAULT_TREE.to_string() } else { name };
+        decoded.push((name, entries));
+    }
+    Ok(decoded)
+}
+
+fn write_snapshot(db: &dyn Storage, snapshot: Snapshot) -> Result<usize> {
+    for name in db.tree_names()? {
+        db.clear(name.as_str())?;
+    }
+    let mut count = 0;
+    for (name, entries) in snapshot {
+        for (key, value) in entries {
+            db.insert(name.as_str(), &key, &value)?;
+            count += 1;
+        }
+    }
+    db.flush()?;
+    Ok(count)
+}
+
+/// Replaces everything in the database with the content of a backup. The database is saved
+/// to `previous` first, and put back from there if the restore fails midway.
+fn restore(db: &dyn Storage, path: &Path, previous: &Path, sealer: &Sealer) -> Result<usize> {
+    let decoded = read_snapshot(path)?;
+    backup(db, previous)?;
+    info!("the database before restoring is saved to {}", previous.display());
+    let result = write_snapshot(db, decoded)
+        .and_then(|count| {
+            // backups of older agents come with older models, and with them in older trees
+            split_trees(db)?;
+            migrate(db, sealer)?;
+            Ok(count)
+        });
+    if let Err(e) = &result {
+        error!("cannot restore from {}, rolling back: {}", path.display(), e);
+        if let Err(e) = read_snapshot(previous).and_then(|x| write_snapshot(db, x)) {
+            error!("cannot roll back, the database is kept in {}: {}", previous.display(), e);
+        }
+    }
+    result
+}
+
+const MODEL_TREE: &str = "models";
+const SETTINGS_TREE: &str = "settings";
+const SCHEMA_VERSION: &str = "schema_version";
+
+/// Trees that older versions kept data in, with the trees that data lives in now.
+const MOVED_TREES: &[(&str, &str)] = &[(DEFAULT_TREE, MODEL_TREE), ("meta", SETTINGS_TREE)];
+
+/// Moves the data of older versions into the trees of its kind, entries already moved are kept.
+fn split_trees(db: &dyn Storage) -> Result<()> {
+    for (from, to) in MOVED_TREES {
+        let entries = db.scan_prefix(from, &[])?;
+        if entries.is_empty() {
+            continue;
+        }
+        info!("moving {} entries into the {} tree", entries.len(), to);
+        for (key, value) in entries {
+            if !db.contains(to, &key)? {
+                db.insert(to, &key, &value)?;
+            }
+            db.remove(from, &key)?;
+        }
+    }
+    db.flush()
+}
+
+/// Upgrades of stored models, the n-th one lifts a model from version n to n + 1. Fields added
+/// so far come with defaults, so models of the first schema need none yet.
+const MIGRATIONS: &[fn(&mut serde_json::Value) -> Result<()>] = &[];
+
+/// Brings every stored model up to the latest schema, models that fail are left as they are
+/// and retried on the next start. Models in plain text get sealed once a key is given.
+fn migrate(db: &dyn Storage, sealer: &Sealer) -> Result<()> {
+    let stored = db.get(SETTINGS_TREE, SCHEMA_VERSION.as_bytes())?
+        .and_then(|x| String::from_utf8(x.to_vec()).ok())
+        .and_then(|x| x.parse::<usize>().ok());
+    if stored.is_none() {
+        db.insert(SETTINGS_TREE, SCHEMA_VERSION.as_bytes(), MIGRATIONS.len().to_string().as_bytes())?;
+    }
+    let version = stored.unwrap_or(0).min(MIGRATIONS.len());
+    let upgrade = version < MIGRATIONS.len();
+    if !upgrade && !sealer.enabled() {
+        return Ok(());
+    }
+    if upgrade {
+        info!("migrating models from schema version {} to {}", version, MIGRATIONS.len());
+    }
+    let models = db.scan_prefix(MODEL_TREE, &[])?;
+    let mut failed = 0;
+    for (key, value) in models {
+        if !upgrade && Sealer::sealed(&value) {
+            continue;
+        }
+        let result = sealer.open(&value)
+            .and_then(|x| serde_json::from_slice::<serde_json::Value>(&x).map_err(|x| x.into()))
+            .and_then(|mut model| {
+                for i in &MIGRATIONS[version..] {
+                    i(&mut model)?;
+                }
+                serde_json::to_vec(&model).map_err(|x| x.into())
+            })
+            .and_then(|x| sealer.seal(x));
+        match result {
+            Ok(model) => {
+                db.insert(MODEL_TREE, &key, &model)?;
+            }
+            Err(e) => {
+                failed += 1;
+                error!("cannot migrate model {}: {}", String::from_utf8_lossy(&key), e);
+            }
+        }
+    }
+    if failed == 0 {
+        db.insert(SETTINGS_TREE, SCHEMA_VERSION.as_bytes(), MIGRATIONS.len().to_string().as_bytes())?;
+    }
+    db.flush()?;
+    Ok(())
+}
+
+pub struct DataActor {
+    db: Box<dyn Storage>,
+    check_path: bool,
+    /// applied to round records, models are kept verbatim as the targets need their real arguments
+    redactor: crate::utils::Redactor,
+    history: HistoryRetention,
+    sealer: Sealer,
+}
+
+/// How much of the round records and profiles is kept.
+#[derive(Copy, Clone)]
+pub struct HistoryRetention {
+    pub max_age_secs: Option<u64>,
+    /// per model
+    pub max_entries: usize,
+    /// per tree, the oldest entries of any model go first
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        HistoryRetention {
+            max_age_secs: None,
+            max_entries: RUN_HISTORY,
+            max_bytes: None,
+        }
+    }
+}
+
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[xactor::message(result = "()")]
+struct Prune;
+
+impl DataActor {
+    pub fn new(db: Box<dyn Storage>, check_path: bool) -> Self {
+        DataActor {
+            db,
+            check_path,
+            redactor: Default::default(),
+            history: Default::default(),
+            sealer: Default::default(),
+        }
+    }
+
+    pub fn with_sealer(mut self, sealer: Sealer) -> Self {
+        self.sealer = sealer;
+        self
+    }
+
+    fn load(&self, name: &str) -> Result<TraceModel> {
+        let value = self.db.get(MODEL_TREE, name.as_bytes())?
+            .ok_or_else(|| anyhow!("key {} not set", name))?;
+        let mut value = self.sealer.open(&value)?;
+        simd_json::from_slice(value.as_mut_slice()).map_err(|x| x.into())
+    }
+
+    fn encode(&self, model: &TraceModel) -> Result<Vec<u8>> {
+        self.sealer.seal(simd_json::to_vec(model)?)
+    }
+
+    /// Changes the stored model as the next revision, unless it was changed meanwhile.
+    fn modify<F: FnOnce(&mut TraceModel) -> Result<()>>(&self, name: &str, change: F) -> Result<()> {
+        let current = self.db.get(MODEL_TREE, name.as_bytes())?
+            .ok_or_else(|| anyhow!("{} does not exist", name))?;
+        let mut model = decode_model(&self.sealer, &current)?;
+        change(&mut model)?;
+        model.revision += 1;
+        let value = self.encode(&model)?;
+        if self.db.compare_and_swap(MODEL_TREE, name.as_bytes(), Some(current.as_slice()), Some(value.as_slice()))? {
+            self.db.flush_later();
+            Ok(())
+        } else {
+            Err(anyhow!("{} was changed meanwhile", name))
+        }
+    }
+
+    pub fn with_history(mut self, history: HistoryRetention) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Drops what falls out of the history retention, returning how many entries went.
+    fn prune(&self, tree: &str) -> Result<usize> {
+        let now = crate::utils::unix_now();
+        // (model, started_at, key, bytes), ordered by model and then by start time
+        let mut entries = Vec::new();
+        for (key, value) in self.db.scan_prefix(tree, &[])? {
+            let split = key.iter().position(|x| *x == 0).unwrap_or(key.len());
+            let started_at = key.get(split + 1..split + 21)
+                .and_then(|x| std::str::from_utf8(x).ok())
+                .and_then(|x| x.parse::<u64>().ok())
+                .unwrap_or(0);
+            entries.push((key[..split].to_vec(), started_at, key, value.len() as u64));
+        }
+        let mut doomed = vec![false; entries.len()];
+        let mut start = 0;
+        while start < entries.len() {
+            let end = start + entries[start..].iter()
+                .take_while(|x| x.0 == entries[start].0)
+                .count();
+            let excess = (end - start).saturating_sub(self.history.max_entries);
+            for (index, entry) in entries[start..end].iter().enumerate() {
+                let expired = self.history.max_age_secs
+                    .map(|x| entry.1 + x < now)
+                    .unwrap_or(false);
+                doomed[start + index] = index < excess || expired;
+            }
+            start = end;
+        }
+        if let Some(max_bytes) = self.history.max_bytes {
+            let mut kept = (0..entries.len()).filter(|x| !doomed[*x]).collect::<Vec<_>>();
+            let mut total = kept.iter().map(|x| entries[*x].3).sum::<u64>();
+            kept.sort_by_key(|x| entries[*x].1);
+            for i in kept {
+                if total <= max_bytes {
+                    break;
+                }
+                total -= entries[i].3;
+                doomed[i] = true;
+            }
+        }
+        let mut removed = 0;
+        for (entry, doomed) in entries.iter().zip(doomed) {
+            if doomed {
+                self.db.remove(tree, &entry.2)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn with_redactor(mut self, redactor: crate::utils::Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// The changes that keep the tag index in step with the tags of a model, to be written
+    /// along with the model itself.
+    fn tag_changes(&self, name: &str, old: &[String], new: &[String]) -> Result<Vec<crate::storage::Change>> {
+        let mut changes = Vec::new();
+        for i in old.iter().filter(|x| !new.contains(x)) {
+            let key = tag_key(i, name).into_bytes();
+            if self.db.contains(TAG_TREE, &key)? && changes.iter().all(|x: &crate::storage::Change| x.key != key) {
+                changes.push(crate::storage::Change { key, value: None });
+            }
+        }
+        for i in new {
+            let key = tag_key(i, name).into_bytes();
+            if !self.db.contains(TAG_TREE, &key)? && changes.iter().all(|x: &crate::storage::Change| x.key != key) {
+                changes.push(crate::storage::Change { key, value: Some(Vec::new()) });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Finds the entries that cannot be read, or that belong to a model which is gone.
+    fn verify(&self) -> Result<Vec<(BadEntry, Vec<u8>)>> {
+        let mut bad = Vec::new();
+        let mut models = hashbrown::HashMap::new();
+        for (key, value) in self.db.scan_prefix(MODEL_TREE, &[])? {
+            let name = String::from_utf8_lossy(&key).to_string();
+            match decode_model(&self.sealer, &value) {
+                Ok(model) if model.name != name =>
+                    bad.push((BadEntry::new(MODEL_TREE, &key, format!("holds the model {}", model.name)), key)),
+                Ok(model) => {
+                    models.insert(name, model.tags);
+                }
+                Err(e) => bad.push((BadEntry::new(MODEL_TREE, &key, e.to_string()), key))
+            }
+        }
+        for tree in &[RUN_TREE, PROFILE_TREE, BASELINE_TREE, SCHEDULE_TREE, TAG_TREE] {
+            for (key, mut value) in self.db.scan_prefix(tree, &[])? {
+                let split = key.iter().position(|x| *x == 0).unwrap_or(key.len());
+                let owner = String::from_utf8_lossy(&key[..split]).to_string();
+                let decoded = match *tree {
+                    RUN_TREE => simd_json::from_slice::<crate::trace::RunRecord>(value.as_mut_slice()).map(|_| ()),
+                    PROFILE_TREE | BASELINE_TREE =>
+                        simd_json::from_slice::<crate::trace::FunctionProfile>(value.as_mut_slice()).map(|_| ()),
+                    SCHEDULE_TREE => simd_json::from_slice::<crate::trace::ScheduleState>(value.as_mut_slice()).map(|_| ()),
+                    _ => Ok(())
+                };
+                let problem = match decoded {
+                    Err(e) => Some(e.to_string()),
+                    Ok(_) if *tree == TAG_TREE => {
+                        // the owner is the tag here, the model follows it
+                        let name = String::from_utf8_lossy(key.get(split + 1..).unwrap_or_default()).to_string();
+                        match models.get(&name) {
+                            Some(tags) if tags.contains(&owner) => None,
+                            Some(_) => Some(format!("{} does not carry the tag", name)),
+                            None => Some(format!("belongs to {}, which is gone", name))
+                        }
+                    }
+                    Ok(_) if !models.contains_key(&owner) => Some(format!("belongs to {}, which is gone", owner)),
+                    Ok(_) => None
+                };
+                if let Some(problem) = problem {
+                    bad.push((BadEntry::new(tree, &key, problem), key));
+                }
+            }
+        }
+        Ok(bad)
+    }
+
+    /// Renames a model along with its history and tags, and points the models running after it
+    /// to the new name. What is written is undone if any step fails.
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        use crate::storage::Change;
+        let stored = self.db.get(MODEL_TREE, from.as_bytes())?
+            .ok_or_else(|| anyhow!("key {} not set", from))?;
+        let mut model = decode_model(&self.sealer, &stored)?;
+        model.name = to.to_string();
+        model.revision += 1;
+        model.validate()?;
+        // the history follows the model, records in it keep the name they were taken under
+        let mut moves = Vec::new();
+        for tree in &[RUN_TREE, PROFILE_TREE, BASELINE_TREE, SCHEDULE_TREE] {
+            moves.push((*tree, move_entries(self.db.as_ref(), tree, from, to)?));
+        }
+        let mut forward = Vec::new();
+        let mut backward = Vec::new();
+        for tag in &model.tags {
+            let (old, new) = (tag_key(tag, from).into_bytes(), tag_key(tag, to).into_bytes());
+            // the index may have gone stale, which is no reason to hold the rename up
+            if self.db.contains(TAG_TREE, &old)? {
+                forward.push(Change { key: old.clone(), value: None });
+                backward.push(Change { key: old, value: Some(Vec::new()) });
+            }
+            if !self.db.contains(TAG_TREE, &new)? {
+                forward.push(Change { key: new.clone(), value: Some(Vec::new()) });
+                backward.push(Change { key: new, value: None });
+            }
+        }
+        moves.push((TAG_TREE, (forward, backward)));
+        let mut dependents = Vec::new();
+        for (key, value) in self.db.scan_prefix(MODEL_TREE, &[])? {
+            let mut dependent = match decode_model(&self.sealer, &value) {
+                Ok(dependent) if dependent.run_after.as_ref().map(|x| x == from).unwrap_or(false) => dependent,
+                _ => continue
+            };
+            dependent.run_after = Some(to.to_string());
+            dependent.revision += 1;
+            let encoded = self.encode(&dependent)?;
+            dependents.push((key, value, encoded));
+        }
+        self.db.rename(MODEL_TREE, from.as_bytes(), to.as_bytes(), &self.encode(&model)?)?;
+        let mut result = Ok(());
+        let mut moved = Vec::new();
+        for (tree, (forward, backward)) in moves {
+            if let Err(e) = self.db.write_all(tree, &forward) {
+                result = Err(e);
+                break;
+            }
+            moved.push((tree, backward));
+        }
+        let mut updated = Vec::new();
+        if result.is_ok() {
+            for (key, old, new) in dependents {
+                match self.db.compare_and_swap(MODEL_TREE, &key, Some(old.as_slice()), Some(new.as_slice())) {
+                    Ok(true) => updated.push((key, old)),
+                    Ok(false) => {
+                        result = Err(anyhow!("{} changed during the rename", String::from_utf8_lossy(&key)));
+                        break;
+                    }
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Err(e) = result {
+            for (key, old) in updated {
+                if let Err(e) = self.db.insert(MODEL_TREE, &key, &old) {
+                    error!("cannot undo the rename in {}: {}", String::from_utf8_lossy(&key), e);
+                }
+            }
+            for (tree, backward) in moved.into_iter().rev() {
+                if let Err(e) = self.db.write_all(tree, &backward) {
+                    error!("cannot undo the rename of {} in the {} tree: {}", from, tree, e);
+                }
+            }
+            if let Err(e) = self.db.rename(MODEL_TREE, to.as_bytes(), from.as_bytes(), &stored) {
+                error!("cannot undo the rename of {}: {}", from, e);
+            }
+            return Err(e);
+        }
+        self.db.flush_later();
+        Ok(())
+    }
+
+    fn check(&self, model: &TraceModel) -> Result<()> {
+        model.validate()?;
+        if self.check_path {
+            model.content.check_path()?;
+        }
+        self.check_cycle(model, &[])
+    }
+
+    /// Follows `run_after` from the model through `pending` and then the stored models,
+    /// failing if the chain comes back to a model it has passed.
+    fn check_cycle(&self, model: &TraceModel, pending: &[TraceModel]) -> Result<()> {
+        let mut visited = hashbrown::HashSet::new();
+        visited.insert(model.name.clone());
+        let mut next = model.run_after.clone();
+        while let Some(name) = next {
+            if !visited.insert(name.clone()) {
+                return Err(anyhow!("{} runs after {}, which closes a cycle", model.name, name));
+            }
+            next = match pending.iter().find(|x| x.name == name) {
+                Some(model) => model.run_after.clone(),
+                None => self.load(name.as_str()).ok().and_then(|x| x.run_after)
+            };
+        }
+        Ok(())
+    }
+}
+
+fn check_executable(path: &str) -> Result<()> {
+    let real = std::fs::canonicalize(path)
+        .map_err(|e| anyhow!("cannot resolve {}: {}", path, e))?;
+    let meta = std::fs::metadata(&real)
+        .map_err(|e| anyhow!("cannot stat {}: {}", real.display(), e))?;
+    if !meta.is_file() {
+        return Err(anyhow!("{} is not a file", real.display()));
+    }
+    if !crate::utils::executable(&meta) {
+        return Err(anyhow!("{} is not executable", real.display()));
+    }
+    Ok(())
+}
+
+
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "method", content = "content")]
+pub enum TraceContent {
+    SystemTap {
+        function_list: Vec<String>,
+        #[serde(default)]
+        usdt_probes: Vec<UsdtProbe>,
+        process: String,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+    },
+    PerfBranch {
+        frequency: Frequency,
+        absolute_path: String,
+        additional_args: Vec<String>,
+        #[serde(default)]
+        call_graph: Option<CallGraphMode>,
+        #[serde(default)]
+        cpus: Option<Vec<u32>>,
+        #[serde(default)]
+        tid_filter: Option<Vec<u32>>,
+        #[serde(default)]
+        max_overhead_pct: Option<f64>,
+        #[serde(default)]
+        flamegraph: Option<FlamegraphOutput>,
+    },
+    BpfTrace {
+        script: String,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+    },
+    Ftrace {
+        events: Vec<FtraceEvent>,
+        args: Vec<String>,
+    },
+    Strace {
+        process: String,
+        syscalls: Vec<String>,
+        follow_forks: bool,
+        summary_only: bool,
+        args: Vec<String>,
+    },
+    PerfStat {
+        events: Vec<String>,
+        interval_ms: u64,
+    },
+    OffCpu {
+        min_block_us: u64,
+        stack_depth: usize,
+    },
+    IntelPt {
+        absolute_path: String,
+        snapshot: bool,
+        size_limit_mb: Option<usize>,
+        itrace: Option<String>,
+        additional_args: Vec<String>,
+    },
+    Callgrind {
+        program: String,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+    },
+    Custom {
+        command: String,
+        args: Vec<String>,
+        output_path: Option<String>,
+        #[serde(default)]
+        parser: CustomParser,
+    },
+    SchedLatency {
+        additional_args: Vec<String>,
+    },
+    PerfMem {
+        absolute_path: String,
+        mode: MemMode,
+        additional_args: Vec<String>,
+    },
+    Sampler {
+        tool: SamplerTool,
+        process_name: String,
+        format: SamplerFormat,
+        rate: Option<usize>,
+    },
+    AsyncProfiler {
+        profiler_path: String,
+        process_name: String,
+        event: ProfilerEvent,
+        format: ProfilerFormat,
+    },
+    Pcap {
+        interface: String,
+        filter: String,
+        snap_len: usize,
+        max_bytes_per_round: Option<u64>,
+    },
+    PerfC2C {
+        absolute_path: String,
+        additional_args: Vec<String>,
+    },
+    Energy {
+        domains: Vec<String>,
+    },
+    Gpu {
+        tool: GpuTool,
+        devices: Vec<u32>,
+        metrics: Vec<String>,
+        #[serde(default)]
+        command: Vec<String>,
+    },
+    Heaptrack {
+        program: String,
+        args: Vec<String>,
+        attach: bool,
+        summary_only: bool,
+    },
+    Lttng {
+        events: Vec<String>,
+        #[serde(default)]
+        context: Vec<String>,
+    },
+    DTrace {
+        script: String,
+        target: Option<String>,
+    },
+    Etw {
+        profiles: Vec<String>,
+    },
+    PerfLive {
+        absolute_path: String,
+        frequency: Frequency,
+        flush_secs: u64,
+        additional_args: Vec<String>,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuTool {
+    Nvidia,
+    Rocprof,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfilerEvent {
+    Cpu,
+    Alloc,
+    Lock,
+}
+
+impl ProfilerEvent {
+    pub fn as_arg(&self) -> &'static str {
+        match self {
+            ProfilerEvent::Cpu => "cpu",
+            ProfilerEvent::Alloc => "alloc",
+            ProfilerEvent::Lock => "lock",
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfilerFormat {
+    Jfr,
+    Collapsed,
+}
+
+impl ProfilerFormat {
+    pub fn as_arg(&self) -> &'static str {
+        match self {
+            ProfilerFormat::Jfr => "jfr",
+            ProfilerFormat::Collapsed => "collapsed",
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerTool {
+    PySpy,
+    RbSpy,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerFormat {
+    Speedscope,
+    Flamegraph,
+    Collapsed,
+}
+
+impl SamplerFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SamplerFormat::Speedscope => "json",
+            SamplerFormat::Flamegraph => "svg",
+            SamplerFormat::Collapsed => "txt",
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum MemMode {
+    Load,
+    Store,
+    All,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Native,
+    /// collapsed stacks, one `frame;frame;frame count` line per distinct stack
+    Folded,
+    /// pprof `profile.proto`, readable by `go tool pprof` and speedscope
+    Pprof,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Native
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum FlamegraphOutput {
+    Svg,
+    Folded,
+    Both,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum CustomParser {
+    Raw,
+    Lines,
+    Folded,
+}
+
+impl Default for CustomParser {
+    fn default() -> Self {
+        CustomParser::Raw
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct DynamicProbe {
+    pub(crate) binary: Option<String>,
+    pub(crate) function: String,
+    pub(crate) offset: Option<u64>,
+    pub(crate) args: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct UsdtProbe {
+    pub(crate) binary: String,
+    pub(crate) provider: String,
+    pub(crate) name: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct FtraceEvent {
+    pub(crate) event: String,
+    pub(crate) filter: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(tag = "frequency_mode", content = "value")]
+pub enum Frequency {
+    Max,
+    Default,
+    Specific(usize),
+    Auto {
+        target_samples_per_sec: usize,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum CallGraphMode {
+    Fp,
+    Dwarf,
+    Lbr,
+}
+
+impl CallGraphMode {
+    pub fn as_arg(&self) -> &'static str {
+        match self {
+            CallGraphMode::Fp => "fp",
+            CallGraphMode::Dwarf => "dwarf",
+            CallGraphMode::Lbr => "lbr",
+        }
+    }
+}
+
+impl Default for Frequency {
+    fn default() -> Self {
+        Frequency::Default
+    }
+}
+
+impl Default for TraceContent {
+    fn default() -> Self {
+        TraceContent::PerfBranch {
+            frequency: Frequency::Default,
+            absolute_path: String::new(),
+            additional_args: Vec::new(),
+            call_graph: None,
+            cpus: None,
+            tid_filter: None,
+            max_overhead_pct: None,
+            flamegraph: None,
+        }
+    }
+}
+
+pub const METHODS: &[&str] = &["perf", "stap", "bpftrace", "ftrace", "strace", "perf-stat", "offcpu", "intel-pt",
+    "callgrind", "custom", "sched", "perf-mem", "sampler", "async-profiler", "pcap", "perf-c2c", "energy",
+    "gpu", "heaptrack", "lttng", "dtrace", "etw", "perf-live"];
+
+impl TraceContent {
+    pub fn template(method: &str) -> Result<Self> {
+        match method {
+            "perf" => Ok(TraceContent::default()),
+            "stap" => Ok(TraceContent::SystemTap {
+                function_list: Vec::new(),
+                usdt_probes: Vec::new(),
+                process: String::new(),
+                args: Vec::new(),
+                envs: Vec::new(),
+            }),
+            "bpftrace" => Ok(TraceContent::BpfTrace {
+                script: String::new(),
+                args: Vec::new(),
+                envs: Vec::new(),
+            }),
+            "ftrace" => Ok(TraceContent::Ftrace {
+                events: vec![FtraceEvent {
+                    event: String::from("sched:sched_switch"),
+                    filter: None,
+                }],
+                args: Vec::new(),
+            }),
+            "strace" => Ok(TraceContent::Strace {
+                process: String::new(),
+                syscalls: Vec::new(),
+                follow_forks: false,
+                summary_only: true,
+                args: Vec::new(),
+            }),
+            "perf-stat" => Ok(TraceContent::PerfStat {
+                events: vec![String::from("cycles"), String::from("instructions")],
+                interval_ms: 1000,
+            }),
+            "offcpu" => Ok(TraceContent::OffCpu {
+                min_block_us: 1,
+                stack_depth: 127,
+            }),
+            "intel-pt" => Ok(TraceContent::IntelPt {
+                absolute_path: String::new(),
+                snapshot: false,
+                size_limit_mb: Some(64),
+                itrace: None,
+                additional_args: Vec::new(),
+            }),
+            "callgrind" => Ok(TraceContent::Callgrind {
+                program: String::new(),
+                args: Vec::new(),
+                envs: Vec::new(),
+            }),
+            "custom" => Ok(TraceContent::Custom {
+                command: String::new(),
+                args: Vec::new(),
+                output_path: None,
+                parser: CustomParser::Raw,
+            }),
+            "sched" => Ok(TraceContent::SchedLatency {
+                additional_args: Vec::new(),
+            }),
+            "perf-mem" => Ok(TraceContent::PerfMem {
+                absolute_path: String::new(),
+                mode: MemMode::All,
+                additional_args: Vec::new(),
+            }),
+            "sampler" => Ok(TraceContent::Sampler {
+                tool: SamplerTool::PySpy,
+                process_name: String::new(),
+                format: SamplerFormat::Speedscope,
+                rate: None,
+            }),
+            "async-profiler" => Ok(TraceContent::AsyncProfiler {
+                profiler_path: String::from("asprof"),
+                process_name: String::from("java"),
+                event: ProfilerEvent::Cpu,
+                format: ProfilerFormat::Collapsed,
+            }),
+            "pcap" => Ok(TraceContent::Pcap {
+                interface: String::from("any"),
+                filter: String::new(),
+                snap_len: 262144,
+                max_bytes_per_round: Some(64 << 20),
+            }),
+            "perf-c2c" => Ok(TraceContent::PerfC2C {
+                absolute_path: String::new(),
+                additional_args: Vec::new(),
+            }),
+            "energy" => Ok(TraceContent::Energy {
+                domains: Vec::new(),
+            }),
+            "gpu" => Ok(TraceContent::Gpu {
+                tool: GpuTool::Nvidia,
+                devices: Vec::new(),
+                metrics: vec![String::from("pucm")],
+                command: Vec::new(),
+            }),
+            "heaptrack" => Ok(TraceContent::Heaptrack {
+                program: String::new(),
+                args: Vec::new(),
+                attach: false,
+                summary_only: true,
+            }),
+            "lttng" => Ok(TraceContent::Lttng {
+                events: vec![String::from("*")],
+                context: vec![String::from("vpid"), String::from("vtid")],
+            }),
+            "dtrace" => Ok(TraceContent::DTrace {
+                script: String::from("profile-997 { @[ustack()] = count(); }"),
+                target: None,
+            }),
+            "etw" => Ok(TraceContent::Etw {
+                profiles: vec![String::from("CPU")],
+            }),
+            "perf-live" => Ok(TraceContent::PerfLive {
+                absolute_path: String::new(),
+                frequency: Frequency::Specific(49),
+                flush_secs: 5,
+                additional_args: Vec::new(),
+            }),
+            _ => Err(anyhow!("unknown trace method: {}", method))
+        }
+    }
+
+    pub fn method(&self) -> &'static str {
+        match self {
+            TraceContent::PerfBranch { .. } => "perf",
+            TraceContent::SystemTap { .. } => "stap",
+            TraceContent::BpfTrace { .. } => "bpftrace",
+            TraceContent::Ftrace { .. } => "ftrace",
+            TraceContent::Strace { .. } => "strace",
+            TraceContent::PerfStat { .. } => "perf-stat",
+            TraceContent::OffCpu { .. } => "offcpu",
+            TraceContent::IntelPt { .. } => "intel-pt",
+            TraceContent::Callgrind { .. } => "callgrind",
+            TraceContent::Custom { .. } => "custom",
+            TraceContent::SchedLatency { .. } => "sched",
+            TraceContent::PerfMem { .. } => "perf-mem",
+            TraceContent::Sampler { .. } => "sampler",
+            TraceContent::AsyncProfiler { .. } => "async-profiler",
+            TraceContent::Pcap { .. } => "pcap",
+            TraceContent::PerfC2C { .. } => "perf-c2c",
+            TraceContent::Energy { .. } => "energy",
+            TraceContent::Gpu { .. } => "gpu",
+            TraceContent::Heaptrack { .. } => "heaptrack",
+            TraceContent::Lttng { .. } => "lttng",
+            TraceContent::DTrace { .. } => "dtrace",
+            TraceContent::Etw { .. } => "etw",
+            TraceContent::PerfLive { .. } => "perf-live",
+        }
+    }
+
+    /// Whether the method runs on this platform, all but a few of them rely on linux.
+    pub fn supported(&self) -> bool {
+        match self {
+            TraceContent::Custom { .. } => true,
+            TraceContent::DTrace { .. } => cfg!(unix),
+            TraceContent::Etw { .. } => cfg!(windows),
+            _ => cfg!(target_os = "linux")
+        }
+    }
+
+    pub fn tool(&self) -> Option<&str> {
+        match self {
+            TraceContent::PerfBranch { .. } | TraceContent::PerfStat { .. } | TraceContent::IntelPt { .. } |
+            TraceContent::SchedLatency { .. } | TraceContent::PerfMem { .. } | TraceContent::PerfC2C { .. } |
+            TraceContent::PerfLive { .. } => Some("perf"),
+            TraceContent::SystemTap { .. } => Some("stap"),
+            TraceContent::BpfTrace { .. } => Some("bpftrace"),
+            TraceContent::Ftrace { .. } => Some("trace-cmd"),
+            TraceContent::Strace { .. } => Some("strace"),
+            TraceContent::OffCpu { .. } => Some("offcputime-bpfcc"),
+            TraceContent::Callgrind { .. } => Some("valgrind"),
+            TraceContent::Custom { command, .. } => Some(command.as_str()),
+            TraceContent::Sampler { tool: SamplerTool::PySpy, .. } => Some("py-spy"),
+            TraceContent::Sampler { tool: SamplerTool::RbSpy, .. } => Some("rbspy"),
+            TraceContent::AsyncProfiler { profiler_path, .. } => Some(profiler_path.as_str()),
+            TraceContent::Pcap { .. } => Some("tcpdump"),
+            TraceContent::Energy { .. } => None,
+            TraceContent::Gpu { tool: GpuTool::Nvidia, .. } => Some("nvidia-smi"),
+            TraceContent::Gpu { tool: GpuTool::Rocprof, .. } => Some("rocprof"),
+            TraceContent::Heaptrack { .. } => Some("heaptrack"),
+            TraceContent::Lttng { .. } => Some("lttng"),
+            TraceContent::DTrace { .. } => Some("dtrace"),
+            TraceContent::Etw { .. } => Some("wpr"),
+        }
+    }
+
+    pub fn check_path(&self) -> Result<()> {
+        match self {
+            TraceContent::PerfBranch { absolute_path, .. } => check_executable(absolute_path),
+            TraceContent::SystemTap { process, .. } if process.contains('/') => check_executable(process),
+            TraceContent::Strace { process, .. } if !process.is_empty() => check_executable(process),
+            TraceContent::IntelPt { absolute_path, .. } => check_executable(absolute_path),
+            TraceContent::Callgrind { program, .. } => check_executable(program),
+            TraceContent::PerfMem { absolute_path, .. } => check_executable(absolute_path),
+            TraceContent::PerfC2C { absolute_path, .. } => check_executable(absolute_path),
+            TraceContent::PerfLive { absolute_path, .. } => check_executable(absolute_path),
+            TraceContent::Heaptrack { program, attach: false, .. } => check_executable(program),
+            TraceContent::Gpu { tool: GpuTool::Rocprof, command, .. } => match command.first() {
+                Some(program) => check_executable(program),
+                None => Err(anyhow!("rocprof requires a command to profile"))
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Problems of the content that need no look at the target binaries.
+    fn problems(&self, problems: &mut Vec<Problem>) {
+        let path = match self {
+            TraceContent::PerfBranch { absolute_path, .. }
+            | TraceContent::IntelPt { absolute_path, .. }
+            | TraceContent::PerfMem { absolute_path, .. }
+            | TraceContent::PerfC2C { absolute_path, .. }
+            | TraceContent::PerfLive { absolute_path, .. } => Some(absolute_path),
+            _ => None
+        };
+        if let Some(path) = path {
+            if !Path::new(path).is_absolute() {
+                problems.push(Problem::new("content.absolute_path", format!("{:?} is not an absolute path", path)));
+            }
+        }
+        match self {
+            TraceContent::SystemTap { function_list, usdt_probes, .. } if function_list.is_empty() && usdt_probes.is_empty() =>
+                problems.push(Problem::new("content.function_list", "must name at least one function to probe")),
+            _ => ()
+        }
+        let rate = match self {
+            TraceContent::PerfBranch { frequency, .. } | TraceContent::PerfLive { frequency, .. } => match frequency {
+                Frequency::Specific(rate) | Frequency::Auto { target_samples_per_sec: rate } => Some(*rate),
+                _ => None
+            },
+            _ => None
+        };
+        // the limit of the kernel perf_event subsystem, when it can be read here
+        if let (Some(rate), Ok(max)) = (rate, crate::trace::max_sample_rate()) {
+            if rate > max {
+                problems.push(Problem::new("content.frequency",
+                                           format!("{} exceeds the max sample rate of {} set by the kernel", rate, max)));
+            }
+        }
+        if let Err(e) = self.check_frequency() {
+            problems.push(Problem::new("content.frequency", e.to_string()));
+        }
+    }
+
+    pub fn check_frequency(&self) -> Result<()> {
+        match self {
+            TraceContent::PerfBranch { frequency: Frequency::Specific(0), .. } |
+            TraceContent::PerfLive { frequency: Frequency::Specific(0), .. } =>
+                Err(anyhow!("specific frequency must be greater than zero")),
+            TraceContent::PerfBranch { frequency: Frequency::Auto { target_samples_per_sec: 0 }, .. } |
+            TraceContent::PerfLive { frequency: Frequency::Auto { target_samples_per_sec: 0 }, .. } =>
+                Err(anyhow!("target sample rate must be greater than zero")),
+            TraceContent::PerfBranch { max_overhead_pct: Some(pct), .. } if *pct <= 0.0 =>
+                Err(anyhow!("overhead budget must be greater than zero")),
+            TraceContent::PerfLive { flush_secs: 0, .. } =>
+                Err(anyhow!("perf live flush interval must be greater than zero")),
+            TraceContent::PerfStat { interval_ms, .. } if *interval_ms < 10 =>
+                Err(anyhow!("perf stat interval must be at least 10ms")),
+            _ => Ok(())
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(tag = "kind", content = "level")]
+pub enum CompressionKind {
+    Zstd(i32),
+}
+
+impl CompressionKind {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionKind::Zstd(_) => "zst",
+        }
+    }
+
+    /// the name carried in the message envelope
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            CompressionKind::Zstd(_) => "zstd",
+        }
+    }
+
+    pub fn compress(&self, content: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            CompressionKind::Zstd(level) => zstd::stream::encode_all(content, *level)
+                .map_err(|x| x.into()),
+        }
+    }
+
+    /// Compresses without holding the whole input in memory, returning the number of bytes read.
+    pub fn compress_stream<R: std::io::Read, W: std::io::Write>(&self, mut input: R, output: W) -> Result<u64> {
+        match self {
+            CompressionKind::Zstd(level) => {
+                let mut encoder = zstd::stream::Encoder::new(output, *level)?;
+                let copied = std::io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+                Ok(copied)
+            }
+        }
+    }
 }
 
-pub async fn insert_str<A: AsRef<str>, B: AsRef<str>>(key: A, content: B, db: &sled::Db)
-                                                      -> Result<()> {
-    match db.contains_key(key.as_ref())
-        .map_err(|e| e.into())
-        .and_then(|flag| if flag { Err(anyhow!("{} exists", key.as_ref())) } else { Ok(()) })
-        .and_then(|_| db.insert(key.as_ref(), content.as_ref()).map_err(|x| x.into())) {
-        Ok(_) => {
-            async_std::task::spawn(db.flush_async());
-            Ok(())
-        }
-        e => e.map(|_| ())
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct OutputCapture {
+    pub(crate) max_bytes: usize,
+    #[serde(default)]
+    pub(crate) tail: bool,
+    #[serde(default)]
+    pub(crate) stdout: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffAgainst {
+    PreviousRound,
+    Baseline,
+}
+
+impl Default for DiffAgainst {
+    fn default() -> Self {
+        DiffAgainst::PreviousRound
     }
 }
 
-pub async fn insert_obj<A: AsRef<str>, B: Serialize>(key: A, content: B, db: &sled::Db)
-                                                     -> Result<()> {
-    match db.contains_key(key.as_ref())
-        .map_err(|e| e.into())
-        .and_then(|flag| if flag { Err(anyhow!("{} exists", key.as_ref())) } else { Ok(()) })
-        .and_then(|_| simd_json::to_vec(&content).map_err(|x| x.into()))
-        .and_then(|obj| db.insert(key.as_ref(), obj).map_err(|x| x.into())) {
-        Ok(_) => {
-            async_std::task::spawn(db.flush_async());
-            Ok(())
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Diff {
+    #[serde(default)]
+    pub(crate) against: DiffAgainst,
+    /// growth of a function's share of samples, in percentage points, that counts as a regression
+    #[serde(default = "default_regression_pct")]
+    pub(crate) threshold_pct: f64,
+}
+
+fn default_regression_pct() -> f64 {
+    5.0
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct Symbolization {
+    #[serde(default)]
+    pub(crate) debuginfod_urls: Vec<String>,
+    #[serde(default)]
+    pub(crate) cache_dir: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct Affinity {
+    #[serde(default)]
+    pub(crate) target: Vec<usize>,
+    #[serde(default)]
+    pub(crate) profiler: Vec<usize>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, Default)]
+pub struct Limits {
+    pub(crate) max_memory_mb: Option<u64>,
+    pub(crate) max_cpu_pct: Option<u32>,
+    pub(crate) max_file_size_mb: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct RunAs {
+    pub(crate) user: String,
+    #[serde(default)]
+    pub(crate) group: Option<String>,
+}
+
+impl RunAs {
+    /// Looks up the numeric ids, falling back to the primary group of the user.
+    #[cfg(unix)]
+    pub(crate) fn ids(&self) -> Result<(u32, u32)> {
+        let user = nix::unistd::User::from_name(self.user.as_str())?
+            .ok_or_else(|| anyhow!("no such user: {}", self.user))?;
+        let gid = match &self.group {
+            Some(group) => nix::unistd::Group::from_name(group.as_str())?
+                .ok_or_else(|| anyhow!("no such group: {}", group))?
+                .gid,
+            None => user.gid
+        };
+        Ok((user.uid.as_raw(), gid.as_raw()))
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn ids(&self) -> Result<(u32, u32)> {
+        Err(anyhow!("cannot run as {}, switching users is only supported on unix", self.user))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Hook {
+    pub(crate) command: String,
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+    /// a failing fatal hook fails the round instead of only logging a warning
+    #[serde(default)]
+    pub(crate) fatal: bool,
+}
+
+/// What to do with rounds missed while the agent was down or the trace paused.
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUp {
+    Skip,
+    RunOnce,
+    RunAll,
+}
+
+impl Default for CatchUp {
+    fn default() -> Self {
+        CatchUp::Skip
+    }
+}
+
+impl CatchUp {
+    /// rounds to make up for, `run_all` is capped so that a long outage does not flood the host
+    pub(crate) fn owed(&self, missed: usize) -> usize {
+        match self {
+            CatchUp::Skip => 0,
+            CatchUp::RunOnce => missed.min(1),
+            CatchUp::RunAll => missed.min(MAX_CATCH_UP),
         }
-        e => e.map(|_| ())
     }
 }
 
-pub struct DataActor {
-    db: sled::Db
+const MAX_CATCH_UP: usize = 64;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct TimeWindow {
+    /// `HH:MM` in local time
+    pub(crate) start: String,
+    pub(crate) end: String,
 }
 
-impl DataActor {
-    pub fn new(db: sled::Db) -> Self {
-        DataActor {
-            db
+impl TimeWindow {
+    fn bounds(&self) -> Result<(chrono::NaiveTime, chrono::NaiveTime)> {
+        let parse = |x: &str| chrono::NaiveTime::parse_from_str(x, "%H:%M")
+            .map_err(|e| anyhow!("invalid time {}: {}", x, e));
+        Ok((parse(self.start.as_str())?, parse(self.end.as_str())?))
+    }
+
+    /// A window that ends before it starts wraps around midnight.
+    pub(crate) fn contains(&self, time: chrono::NaiveTime) -> Result<bool> {
+        let (start, end) = self.bounds()?;
+        Ok(if start <= end {
+            start <= time && time < end
+        } else {
+            time >= start || time < end
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(tag = "metric", rename_all = "snake_case")]
+pub enum TriggerMetric {
+    /// busy time of all cpus, in percent
+    CpuPercent,
+    /// resident memory of the processes running the binary, in MiB
+    RssMb { absolute_path: String },
+    CpuPressure,
+    MemoryPressure,
+    IoPressure,
+}
+
+impl TriggerMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriggerMetric::CpuPercent => "cpu_percent",
+            TriggerMetric::RssMb { .. } => "rss_mb",
+            TriggerMetric::CpuPressure => "cpu_pressure",
+            TriggerMetric::MemoryPressure => "memory_pressure",
+            TriggerMetric::IoPressure => "io_pressure",
         }
     }
 }
 
+/// Starts an unscheduled round once the metric stays above the threshold.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct TriggerRule {
+    #[serde(flatten)]
+    pub(crate) metric: TriggerMetric,
+    pub(crate) above: f64,
+    #[serde(default)]
+    pub(crate) for_secs: u64,
+}
 
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, Default)]
+pub struct LoadLimit {
+    /// one minute load average
+    #[serde(default)]
+    pub(crate) max_loadavg: Option<f32>,
+    /// percentage of time some tasks stall on cpu, over the last 10 seconds
+    #[serde(default)]
+    pub(crate) max_cpu_pressure: Option<f64>,
+    #[serde(default)]
+    pub(crate) max_memory_pressure: Option<f64>,
+}
 
-#[derive(serde::Serialize, serde::Deserialize)]
-#[serde(tag = "method", content = "content")]
-pub enum TraceContent {
-    SystemTap {
-        function_list: Vec<String>,
-        process: String,
-        args: Vec<String>,
-        envs: Vec<(String, String)>,
-    },
-    PerfBranch {
-        frequency: Frequency,
-        absolute_path: String,
-        additional_args: Vec<String>,
-    },
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+pub struct FailureBackoff {
+    /// consecutive failed rounds before the schedule backs off
+    pub(crate) after_failures: usize,
+    #[serde(default = "default_max_backoff")]
+    pub(crate) max_backoff_secs: u64,
+}
+
+fn default_max_backoff() -> u64 {
+    24 * 60 * 60
+}
+
+impl FailureBackoff {
+    /// the interval doubles with every failure from the threshold on
+    pub(crate) fn delay(&self, interval: u64, failures: usize) -> u64 {
+        let exponent = (failures + 1).saturating_sub(self.after_failures).min(32);
+        interval.saturating_mul(1 << exponent).min(self.max_backoff_secs)
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
-#[serde(tag = "frequency_mode", content = "value")]
-pub enum Frequency {
-    Max,
-    Default,
-    Specific(usize),
+pub struct Retry {
+    pub(crate) max_attempts: usize,
+    #[serde(default)]
+    pub(crate) backoff_secs: u64,
 }
 
-impl Default for Frequency {
-    fn default() -> Self {
-        Frequency::Default
+impl Retry {
+    /// backoff doubles with every failed attempt
+    pub(crate) fn backoff(&self, attempt: usize) -> u64 {
+        self.backoff_secs.saturating_mul(1 << attempt.min(16).saturating_sub(1))
     }
 }
 
-impl Default for TraceContent {
-    fn default() -> Self {
-        TraceContent::PerfBranch {
-            frequency: Frequency::Default,
-            absolute_path: String::new(),
-            additional_args: Vec::new(),
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct Retention {
+    pub(crate) max_files: Option<usize>,
+    pub(crate) max_age_secs: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+    Containerd,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ContainerTarget {
+    pub(crate) container_id: String,
+    pub(crate) runtime: ContainerRuntime,
+    #[serde(skip)]
+    pub(crate) init_pid: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct PodTarget {
+    pub(crate) namespace: String,
+    pub(crate) selector: Vec<String>,
+    pub(crate) container: Option<String>,
+    #[serde(skip)]
+    pub(crate) init_pid: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(tag = "mode", content = "pid")]
+pub enum TraceTarget {
+    AttachPid(u32),
+    Container(ContainerTarget),
+    Cgroup(String),
+    Pod(PodTarget),
+}
+
+impl TraceTarget {
+    pub fn pid(&self) -> Option<u32> {
+        match self {
+            TraceTarget::AttachPid(pid) => Some(*pid),
+            TraceTarget::Container(container) => container.init_pid,
+            TraceTarget::Cgroup(_) => None,
+            TraceTarget::Pod(pod) => pod.init_pid,
+        }
+    }
+
+    pub fn resolve(&mut self) -> Result<()> {
+        match self {
+            TraceTarget::AttachPid(_) | TraceTarget::Cgroup(_) => Ok(()),
+            TraceTarget::Container(container) => crate::container::resolve_init_pid(container)
+                .map(|pid| { container.init_pid.replace(pid); }),
+            #[cfg(feature = "k8s")]
+            TraceTarget::Pod(pod) => crate::k8s::resolve_init_pid(pod)
+                .map(|pid| { pod.init_pid.replace(pid); }),
+            #[cfg(not(feature = "k8s"))]
+            TraceTarget::Pod(_) => Err(anyhow!("pod targets require girasol to be built with the k8s feature")),
+        }
+    }
+}
+
+/// What is wrong with a field of a model.
+#[derive(Debug)]
+pub struct Problem {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl Problem {
+    fn new<S: Into<String>>(field: &'static str, message: S) -> Self {
+        Problem {
+            field,
+            message: message.into(),
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Default)]
+#[derive(Debug)]
+pub struct ValidationError {
+    pub name: String,
+    pub problems: Vec<Problem>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "model {:?} is invalid:", self.name)?;
+        for i in &self.problems {
+            write!(f, "\n  {}: {}", i.field, i.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct TraceModel {
     pub(crate) name: String,
     pub(crate) lasting: usize,
     pub(crate) interval: usize,
+    /// cron expression that takes the place of `interval`, e.g. `0 2 * * Mon-Fri`
+    #[serde(default)]
+    pub(crate) schedule: Option<String>,
     pub(crate) content: TraceContent,
+    #[serde(default)]
+    pub(crate) compression: Option<CompressionKind>,
+    #[serde(default = "enabled_by_default")]
+    pub(crate) enabled: bool,
+    /// a paused trace keeps its actor but skips rounds until resumed
+    #[serde(default)]
+    pub(crate) paused: bool,
+    /// when rounds are limited, higher priorities run first and preempt lower ones
+    #[serde(default)]
+    pub(crate) priority: i32,
+    #[serde(default)]
+    pub(crate) retention: Option<Retention>,
+    #[serde(default)]
+    pub(crate) target: Option<TraceTarget>,
+    #[serde(default)]
+    pub(crate) probes: Vec<DynamicProbe>,
+    #[serde(default)]
+    pub(crate) follow_children: bool,
+    #[serde(default)]
+    pub(crate) warmup_secs: u64,
+    /// every round starts at a random point within this window after its schedule
+    #[serde(default)]
+    pub(crate) jitter_secs: u64,
+    /// local time windows rounds may start in, any time if empty
+    #[serde(default)]
+    pub(crate) allowed_windows: Vec<TimeWindow>,
+    #[serde(default)]
+    pub(crate) catch_up: CatchUp,
+    #[serde(default)]
+    pub(crate) failure_backoff: Option<FailureBackoff>,
+    /// rounds are deferred while the host is loaded beyond these
+    #[serde(default)]
+    pub(crate) load_limit: Option<LoadLimit>,
+    #[serde(default)]
+    pub(crate) triggers: Vec<TriggerRule>,
+    /// regex of executables whose exec starts a round
+    #[serde(default)]
+    pub(crate) exec_watch: Option<String>,
+    /// models of the same group start their rounds together
+    #[serde(default)]
+    pub(crate) group: Option<String>,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    /// name of the model whose successful rounds start the ones of this model
+    #[serde(default)]
+    pub(crate) run_after: Option<String>,
+    #[serde(default)]
+    pub(crate) expires_after_rounds: Option<usize>,
+    /// unix time after which no more rounds start
+    #[serde(default)]
+    pub(crate) expires_at: Option<u64>,
+    /// whether an expired model is removed instead of disabled
+    #[serde(default)]
+    pub(crate) remove_on_expiry: bool,
+    #[serde(default = "default_grace")]
+    pub(crate) grace_secs: u64,
+    #[serde(default)]
+    pub(crate) capture: Option<OutputCapture>,
+    #[serde(default)]
+    pub(crate) retry: Option<Retry>,
+    #[serde(default)]
+    pub(crate) pre_hook: Option<Hook>,
+    #[serde(default)]
+    pub(crate) post_hook: Option<Hook>,
+    #[serde(default)]
+    pub(crate) run_as: Option<RunAs>,
+    #[serde(default)]
+    pub(crate) limits: Option<Limits>,
+    #[serde(default)]
+    pub(crate) affinity: Option<Affinity>,
+    #[serde(default)]
+    pub(crate) output_format: OutputFormat,
+    #[serde(default)]
+    pub(crate) symbolization: Option<Symbolization>,
+    #[serde(default)]
+    pub(crate) diff: Option<Diff>,
+    /// bumped by the database on every write, edits carry the one they started from
+    #[serde(default)]
+    pub(crate) revision: u64,
+    /// unix time the model was archived at, archived models keep their history but never run
+    #[serde(default)]
+    pub(crate) archived_at: Option<u64>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn default_grace() -> u64 {
+    10
+}
+
+impl TraceModel {
+    /// Checks the model as a whole, reporting every problem found rather than the first one.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push(Problem::new("name", "must not be empty"));
+        } else if self.name.chars().any(|x| x.is_control()) {
+            // records are keyed by the name followed by a NUL
+            problems.push(Problem::new("name", "must not contain control characters"));
+        }
+        if self.lasting == 0 {
+            problems.push(Problem::new("lasting", "a round must last at least one second"));
+        }
+        if self.schedule.is_none() && self.interval == 0 {
+            problems.push(Problem::new("interval", "must be greater than zero, unless a schedule is given"));
+        }
+        if let Err(e) = self.cron() {
+            problems.push(Problem::new("schedule", e.to_string()));
+        }
+        if let Err(e) = self.check_windows() {
+            problems.push(Problem::new("allowed_windows", e.to_string()));
+        }
+        if let Err(e) = self.exec_pattern() {
+            problems.push(Problem::new("exec_watch", e.to_string()));
+        }
+        if let Err(e) = self.check_chain() {
+            problems.push(Problem::new("run_after", e.to_string()));
+        }
+        if cfg!(not(target_os = "linux")) && self.affinity.is_some() {
+            problems.push(Problem::new("affinity", "cpu affinity is only supported on linux"));
+        }
+        if !self.content.supported() {
+            problems.push(Problem::new("content", format!("{} is not supported on this platform", self.content.method())));
+        }
+        self.content.problems(&mut problems);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                name: self.name.clone(),
+                problems,
+            })
+        }
+    }
+
+    /// Whether the model may be started at all.
+    pub fn runnable(&self) -> bool {
+        self.enabled && self.archived_at.is_none()
+    }
+
+    pub fn check_windows(&self) -> Result<()> {
+        for i in &self.allowed_windows {
+            i.bounds()?;
+        }
+        Ok(())
+    }
+
+    pub fn check_chain(&self) -> Result<()> {
+        match &self.run_after {
+            Some(name) if name == &self.name => Err(anyhow!("{} cannot run after itself", name)),
+            _ => Ok(())
+        }
+    }
+
+    pub fn exec_pattern(&self) -> Result<Option<regex::Regex>> {
+        match &self.exec_watch {
+            Some(pattern) => regex::Regex::new(pattern)
+                .map(Some)
+                .map_err(|e| anyhow!("invalid exec watch pattern {}: {}", pattern, e)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn in_window(&self, time: chrono::NaiveTime) -> Result<bool> {
+        if self.allowed_windows.is_empty() {
+            return Ok(true);
+        }
+        for i in &self.allowed_windows {
+            if i.contains(time)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Parses the cron schedule, the five-field form is taken to fire at second zero.
+    pub fn cron(&self) -> Result<Option<cron::Schedule>> {
+        use std::str::FromStr;
+        match &self.schedule {
+            None => Ok(None),
+            Some(expr) => {
+                let expr = if expr.split_ascii_whitespace().count() == 5 {
+                    format!("0 {}", expr)
+                } else {
+                    expr.clone()
+                };
+                cron::Schedule::from_str(expr.as_str())
+                    .map(Some)
+                    .map_err(|e| anyhow!("invalid schedule {}: {}", expr, e))
+            }
+        }
+    }
+}
+
+impl Default for TraceModel {
+    fn default() -> Self {
+        TraceModel {
+            name: String::new(),
+            lasting: 0,
+            interval: 0,
+            schedule: None,
+            content: TraceContent::default(),
+            compression: None,
+            enabled: true,
+            paused: false,
+            priority: 0,
+            retention: None,
+            target: None,
+            probes: Vec::new(),
+            follow_children: false,
+            warmup_secs: 0,
+            jitter_secs: 0,
+            allowed_windows: Vec::new(),
+            catch_up: CatchUp::Skip,
+            failure_backoff: None,
+            load_limit: None,
+            triggers: Vec::new(),
+            exec_watch: None,
+            group: None,
+            run_after: None,
+            tags: Vec::new(),
+            expires_after_rounds: None,
+            expires_at: None,
+            remove_on_expiry: false,
+            grace_secs: default_grace(),
+            capture: None,
+            retry: None,
+            pre_hook: None,
+            post_hook: None,
+            run_as: None,
+            limits: None,
+            affinity: None,
+            output_format: OutputFormat::Native,
+            symbolization: None,
+            diff: None,
+            revision: 0,
+            archived_at: None,
+        }
+    }
 }
 
 #[xactor::message(result = "anyhow::Result<DbReply>")]
 pub enum DbMsg {
     QueryAll,
+    /// at most `limit` models in name order, starting after the model named `after`
+    Page {
+        after: Option<String>,
+        limit: usize,
+    },
     Kill,
     Get(String),
-    Remove(String),
+    /// removes the model only if it is still at `revision`, if one is given
+    Remove {
+        name: String,
+        revision: Option<u64>,
+    },
     Add(TraceModel),
+    /// adds all of the models or none of them
+    AddMany(Vec<TraceModel>),
+    /// removes all of the models or none of them
+    RemoveMany(Vec<String>),
+    /// updates the model only if it is still at `revision`
+    Update {
+        model: TraceModel,
+        revision: u64,
+    },
+    Upsert(TraceModel),
+    Rename {
+        from: String,
+        to: String,
+    },
+    /// models carrying all of the `tags`
+    Search {
+        prefix: Option<String>,
+        contains: Option<String>,
+        tags: Vec<String>,
+    },
+    SetEnabled {
+        name: String,
+        enabled: bool,
+    },
+    SetPaused {
+        name: String,
+        paused: bool,
+    },
+    SetArchived {
+        name: String,
+        archived: bool,
+    },
+    AddRun(crate::trace::RunRecord),
+    /// the latest rounds first, at most `limit` of them
+    History {
+        name: String,
+        limit: Option<usize>,
+    },
+    AddProfile(crate::trace::FunctionProfile),
+    /// the profile of the given round, or the latest one
+    Profile {
+        name: String,
+        round: Option<usize>,
+    },
+    SetBaseline {
+        name: String,
+        round: Option<usize>,
+    },
+    Baseline(String),
+    SaveSchedule(crate::trace::ScheduleState),
+    Backup(PathBuf),
+    /// the current database is saved to `previous` before it is replaced
+    Restore {
+        input: PathBuf,
+        previous: PathBuf,
+    },
+    LoadSchedule(String),
+    /// follows the writes to the models from now on
+    Subscribe,
+    /// looks for entries that cannot be read or belong to no model, moving them aside if asked to
+    Verify {
+        quarantine: bool,
+    },
+}
+
+/// A write to the models, as seen by a subscriber.
+pub enum ModelChange {
+    Stored(TraceModel),
+    Removed(String),
 }
 
 pub enum DbReply {
     AllList(Vec<TraceModel>),
+    /// `next` is the cursor of the following page, if there may be one
+    Page {
+        models: Vec<TraceModel>,
+        next: Option<String>,
+    },
     GetResult(TraceModel),
+    RunList(Vec<crate::trace::RunRecord>),
+    ProfileResult(crate::trace::FunctionProfile),
+    ScheduleResult(crate::trace::ScheduleState),
+    Subscription(futures::stream::BoxStream<'static, ModelChange>),
+    Verified(Vec<BadEntry>),
     Success,
 }
 
+const RUN_TREE: &str = "runs";
+const RUN_HISTORY: usize = 32;
+const PROFILE_TREE: &str = "profiles";
+const BASELINE_TREE: &str = "baselines";
+const SCHEDULE_TREE: &str = "schedules";
+/// keyed by tag and model name, with nothing stored
+const TAG_TREE: &str = "tags";
+/// keyed by the tree and the key an entry was moved out of
+const QUARANTINE_TREE: &str = "quarantine";
+
+fn run_prefix(name: &str) -> String {
+    format!("{}\0", name)
+}
+
+/// Moves what is kept under a trace name, either as the key or as the key prefix.
+/// Returns the changes doing so in one tree, along with the changes undoing them.
+fn move_entries(db: &dyn Storage, tree: &str, from: &str, to: &str)
+                -> Result<(Vec<crate::storage::Change>, Vec<crate::storage::Change>)> {
+    use crate::storage::Change;
+    let mut moved = Vec::new();
+    if let Some(value) = db.get(tree, from.as_bytes())? {
+        moved.push((from.as_bytes().to_vec(), to.as_bytes().to_vec(), value));
+    }
+    let prefix = run_prefix(from);
+    for (key, value) in db.scan_prefix(tree, prefix.as_bytes())? {
+        let key_to = [run_prefix(to).as_bytes(), &key[prefix.len()..]].concat();
+        moved.push((key, key_to, value));
+    }
+    let mut forward = Vec::new();
+    let mut backward = Vec::new();
+    for (key, key_to, value) in moved {
+        forward.push(Change { key: key.clone(), value: None });
+        forward.push(Change { key: key_to.clone(), value: Some(value.clone()) });
+        backward.push(Change { key: key_to, value: None });
+        backward.push(Change { key, value: Some(value) });
+    }
+    Ok((forward, backward))
+}
+
+fn tag_key(tag: &str, name: &str) -> String {
+    format!("{}{}", run_prefix(tag), name)
+}
+
+fn find_profile(db: &dyn Storage, name: &str, round: Option<usize>) -> Result<crate::trace::FunctionProfile> {
+    let mut found = None;
+    for (_, mut value) in db.scan_prefix(PROFILE_TREE, run_prefix(name).as_bytes())? {
+        let profile: crate::trace::FunctionProfile = simd_json::from_slice(value.as_mut_slice())?;
+        // a round index may repeat after restarts, the latest one wins
+        if round.map(|x| x == profile.round).unwrap_or(true) {
+            found = Some(profile);
+        }
+    }
+    found.ok_or_else(|| match round {
+        Some(round) => anyhow!("no profile of round {} for {}", round, name),
+        None => anyhow!("no profile for {}", name)
+    })
+}
+
+/// Walks through the models a page at a time, so that only one page is held at once.
+pub fn model_pages(db: xactor::Addr<DataActor>, page_size: usize) -> impl Stream<Item=Result<Vec<TraceModel>>> {
+    futures::stream::unfold(Some((db, None)), move |state| async move {
+        let (mut db, after) = state?;
+        match db.call(DbMsg::Page { after, limit: page_size }).await
+            .map_err(|x| x.into())
+            .and_then(|x| x) {
+            Ok(DbReply::Page { models, next }) => {
+                let state = next.map(|x| (db, Some(x)));
+                Some((Ok(models), state))
+            }
+            Err(e) => Some((Err(e), None)),
+            _ => Some((Err(anyhow!("unexpected database reply")), None))
+        }
+    })
+}
+
+fn check_revision(stored: &TraceModel, revision: u64) -> Result<()> {
+    if stored.revision != revision {
+        return Err(anyhow!("{} was changed meanwhile, it is at revision {} instead of {}",
+                           stored.name, stored.revision, revision));
+    }
+    Ok(())
+}
+
+fn decode_model(sealer: &Sealer, value: &[u8]) -> Result<TraceModel> {
+    let mut value = sealer.open(value)?;
+    simd_json::from_slice(value.as_mut_slice()).map_err(|x| x.into())
+}
+
+/// Models that cannot be read are left out, so that one of them does not hide all others.
+fn collect_models<F>(entries: Vec<crate::storage::Entry>, sealer: &Sealer, filter: F) -> Result<Vec<TraceModel>>
+    where F: Fn(&TraceModel) -> bool {
+    let mut result = Vec::new();
+    for (key, value) in entries {
+        match decode_model(sealer, &value) {
+            Ok(model) => if filter(&model) {
+                result.push(model);
+            },
+            Err(e) => error!("skipped model {}: {}, see `girasol db verify`",
+                             String::from_utf8_lossy(&key), e)
+        }
+    }
+    Ok(result)
+}
+
+/// An entry found broken by a verification.
+#[derive(serde::Serialize)]
+pub struct BadEntry {
+    pub tree: String,
+    pub key: String,
+    pub problem: String,
+}
+
+impl BadEntry {
+    fn new<S: Into<String>>(tree: &str, key: &[u8], problem: S) -> Self {
+        BadEntry {
+            tree: tree.to_string(),
+            key: String::from_utf8_lossy(key).replace('\0', "/"),
+            problem: problem.into(),
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl Actor for DataActor {
-    async fn started(&mut self, _: &xactor::Context<Self>) {
+    async fn started(&mut self, ctx: &xactor::Context<Self>) {
         info!("database actor started");
+        ctx.send_interval_with(|| Prune, PRUNE_INTERVAL);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Prune> for DataActor {
+    async fn handle(&mut self, _: &xactor::Context<Self>, _: Prune) {
+        for name in &[RUN_TREE, PROFILE_TREE] {
+            match self.prune(name) {
+                Ok(0) => (),
+                Ok(removed) => info!("pruned {} entries from {}", removed, name),
+                Err(e) => error!("cannot prune {}: {}", name, e)
+            }
+        }
     }
 }
 
@@ -157,50 +2035,296 @@ impl Handler<DbMsg> for DataActor {
     async fn handle(&mut self, _ctx: &xactor::Context<Self>, msg: DbMsg) -> <DbMsg as Message>::Result {
         match msg {
             DbMsg::QueryAll => {
-                let mut result = Ok(Vec::new());
-                for i in self.db.iter() {
-                    result = result.and_then(|mut x| {
-                        i.map_err(|x| x.into())
-                            .map(|(_, y)| y)
-                            .map(|x| x.to_vec())
-                            .and_then(|mut x| simd_json::from_slice(x.as_mut_slice())
-                                .map_err(|x| x.into()))
-                            .map(|model| {
-                                x.push(model);
-                                x
-                            })
-                    })
-                }
+                collect_models(self.db.scan_prefix(MODEL_TREE, &[])?, &self.sealer, |_| true)
+                    .map(|x| DbReply::AllList(x))
+            }
+            DbMsg::Page { after, limit } => {
+                let entries = self.db.scan_after(MODEL_TREE, after.as_ref().map(|x| x.as_bytes()), limit)?;
+                let next = if entries.len() == limit {
+                    entries.last().map(|x| String::from_utf8_lossy(&x.0).to_string())
+                } else {
+                    None
+                };
+                collect_models(entries, &self.sealer, |_| true)
+                    .map(|models| DbReply::Page { models, next })
+            }
+            DbMsg::Search { prefix, contains, tags } => {
+                let matches = |model: &TraceModel| contains.as_ref()
+                    .map(|x| model.name.contains(x.as_str()))
+                    .unwrap_or(true)
+                    && tags.iter().all(|x| model.tags.contains(x));
+                let result = match (tags.first(), prefix) {
+                    (Some(tag), prefix) => {
+                        // the index narrows the models down to the ones with the first tag
+                        let tag_prefix = run_prefix(tag);
+                        let names = self.db.scan_prefix(TAG_TREE, tag_prefix.as_bytes())?
+                            .into_iter()
+                            .map(|(key, _)| String::from_utf8_lossy(&key[tag_prefix.len()..]).to_string())
+                            .collect::<Vec<_>>();
+                        let mut models = Vec::new();
+                        for name in names {
+                            if prefix.as_ref().map(|x| name.starts_with(x.as_str())).unwrap_or(true) {
+                                match self.load(name.as_str()) {
+                                    Ok(model) => if matches(&model) {
+                                        models.push(model);
+                                    },
+                                    Err(e) => warn!("tag {} indexes {}, which cannot be loaded: {}", tag, name, e)
+                                }
+                            }
+                        }
+                        Ok(models)
+                    }
+                    (None, Some(prefix)) => collect_models(self.db.scan_prefix(MODEL_TREE, prefix.as_bytes())?, &self.sealer, matches),
+                    (None, None) => collect_models(self.db.scan_prefix(MODEL_TREE, &[])?, &self.sealer, matches)
+                };
                 result.map(|x| DbReply::AllList(x))
             }
             DbMsg::Get(name) => {
-                query_json(name, &self.db).await
+                self.load(name.as_str())
                     .map(|x| DbReply::GetResult(x))
             }
             DbMsg::Kill => {
                 match self.db.flush() {
-                    Ok(e) => trace!("db finalized with {} bytes flushed", e),
+                    Ok(_) => trace!("db finalized"),
                     Err(e) => error!("{}", e)
                 }
                 _ctx.stop(None);
                 Ok(DbReply::Success)
             }
-            DbMsg::Remove(name) => {
-                match self.db.contains_key(&name) {
-                    Ok(true) => self.db.remove(name)
-                        .map(|_| async_std::task::spawn(self.db.flush_async()))
-                        .map(|_| DbReply::Success)
-                        .map_err(|x| x.into()),
-                    Ok(false) => Err(anyhow!("{} does not exist", name)),
-                    Err(e) => Err(e.into())
-                }
-            }
-            DbMsg::Add(model) => {
-                match self.db.contains_key(&model.name) {
-                    Ok(true) => Err(anyhow!("{} exists", model.name)),
-                    Ok(false) => insert_obj(model.name.clone(), model, &self.db).await
-                        .map(|_| DbReply::Success),
-                    Err(e) => Err(e.into())
+            DbMsg::Remove { name, revision } => {
+                let current = self.db.get(MODEL_TREE, name.as_bytes())?
+                    .ok_or_else(|| anyhow!("{} does not exist", name))?;
+                let mut stored = self.sealer.open(&current)?;
+                let stored: TraceModel = simd_json::from_slice(stored.as_mut_slice())?;
+                if let Some(revision) = revision {
+                    check_revision(&stored, revision)?;
+                }
+                let tags = self.tag_changes(name.as_str(), stored.tags.as_slice(), &[])?;
+                if self.db.swap_with(MODEL_TREE, name.as_bytes(), Some(current.as_slice()), None,
+                                     &[(TAG_TREE, tags.as_slice())])? {
+                    self.db.flush_later();
+                    Ok(DbReply::Success)
+                } else {
+                    Err(anyhow!("{} was changed meanwhile", name))
+                }
+            }
+            DbMsg::Add(mut model) => {
+                self.check(&model)?;
+                model.revision = 1;
+                let value = self.encode(&model)?;
+                let tags = self.tag_changes(model.name.as_str(), &[], model.tags.as_slice())?;
+                if self.db.swap_with(MODEL_TREE, model.name.as_bytes(), None, Some(value.as_slice()),
+                                     &[(TAG_TREE, tags.as_slice())])? {
+                    self.db.flush_later();
+                    Ok(DbReply::Success)
+                } else {
+                    Err(anyhow!("{} exists", model.name))
+                }
+            }
+            DbMsg::AddMany(mut models) => {
+                let mut changes = Vec::new();
+                let mut tags = Vec::new();
+                for model in models.iter_mut() {
+                    self.check(model).map_err(|e| anyhow!("{}: {}", model.name, e))?;
+                    if changes.iter().any(|x: &crate::storage::Change| x.key == model.name.as_bytes()) {
+                        return Err(anyhow!("{} is given more than once", model.name));
+                    }
+                    model.revision = 1;
+                    changes.push(crate::storage::Change {
+                        key: model.name.clone().into_bytes(),
+                        value: Some(self.encode(model)?),
+                    });
+                    tags.extend(self.tag_changes(model.name.as_str(), &[], model.tags.as_slice())?);
+                }
+                for model in &models {
+                    self.check_cycle(model, models.as_slice()).map_err(|e| anyhow!("{}: {}", model.name, e))?;
+                }
+                self.db.write_trees(&[(MODEL_TREE, changes.as_slice()), (TAG_TREE, tags.as_slice())])?;
+                self.db.flush_later();
+                Ok(DbReply::Success)
+            }
+            DbMsg::RemoveMany(names) => {
+                let mut tags = Vec::new();
+                for name in &names {
+                    let stored = self.load(name.as_str())?.tags;
+                    for i in self.tag_changes(name.as_str(), stored.as_slice(), &[])? {
+                        if tags.iter().all(|x: &crate::storage::Change| x.key != i.key) {
+                            tags.push(i);
+                        }
+                    }
+                }
+                let changes = names.iter()
+                    .map(|x| crate::storage::Change { key: x.clone().into_bytes(), value: None })
+                    .collect::<Vec<_>>();
+                self.db.write_trees(&[(MODEL_TREE, changes.as_slice()), (TAG_TREE, tags.as_slice())])?;
+                self.db.flush_later();
+                Ok(DbReply::Success)
+            }
+            DbMsg::Update { mut model, revision } => {
+                self.check(&model)?;
+                let current = self.db.get(MODEL_TREE, model.name.as_bytes())?
+                    .ok_or_else(|| anyhow!("{} does not exist", model.name))?;
+                let mut stored = self.sealer.open(&current)?;
+                let stored: TraceModel = simd_json::from_slice(stored.as_mut_slice())?;
+                check_revision(&stored, revision)?;
+                model.revision = stored.revision + 1;
+                let value = self.encode(&model)?;
+                let tags = self.tag_changes(model.name.as_str(), stored.tags.as_slice(), model.tags.as_slice())?;
+                if self.db.swap_with(MODEL_TREE, model.name.as_bytes(), Some(current.as_slice()), Some(value.as_slice()),
+                                     &[(TAG_TREE, tags.as_slice())])? {
+                    self.db.flush_later();
+                    Ok(DbReply::Success)
+                } else {
+                    Err(anyhow!("{} was changed meanwhile, edit it again", model.name))
+                }
+            }
+            DbMsg::Rename { from, to } => {
+                self.rename(from.as_str(), to.as_str())?;
+                Ok(DbReply::Success)
+            }
+            DbMsg::Backup(path) => {
+                let count = backup(self.db.as_ref(), path.as_path())?;
+                info!("backed up {} entries to {}", count, path.display());
+                Ok(DbReply::Success)
+            }
+            DbMsg::Restore { input, previous } => {
+                let count = restore(self.db.as_ref(), input.as_path(), previous.as_path(), &self.sealer)?;
+                info!("restored {} entries from {}", count, input.display());
+                Ok(DbReply::Success)
+            }
+            DbMsg::SetEnabled { name, enabled } => {
+                self.modify(name.as_str(), |model| {
+                    model.enabled = enabled;
+                    Ok(())
+                }).map(|_| DbReply::Success)
+            }
+            DbMsg::SetPaused { name, paused } => {
+                self.modify(name.as_str(), |model| {
+                    model.paused = paused;
+                    Ok(())
+                }).map(|_| DbReply::Success)
+            }
+            DbMsg::SetArchived { name, archived } => {
+                self.modify(name.as_str(), |model| {
+                    if model.archived_at.is_some() == archived {
+                        return Err(anyhow!("{} is {} archived", model.name, if archived { "already" } else { "not" }));
+                    }
+                    model.archived_at = if archived { Some(crate::utils::unix_now()) } else { None };
+                    Ok(())
+                }).map(|_| DbReply::Success)
+            }
+            DbMsg::AddRun(record) => {
+                let prefix = run_prefix(record.trace_name.as_str());
+                let key = format!("{}{:020}{:010}", prefix, record.started_at, record.round);
+                let value = self.redactor.to_json(&record)?.into_bytes();
+                self.db.insert(RUN_TREE, key.as_bytes(), &value)?;
+                // keys sort by start time, so the oldest records come first
+                let keys = self.db.scan_prefix(RUN_TREE, prefix.as_bytes())?;
+                for (key, _) in keys.iter().take(keys.len().saturating_sub(self.history.max_entries)) {
+                    self.db.remove(RUN_TREE, key)?;
+                }
+                Ok(DbReply::Success)
+            }
+            DbMsg::History { name, limit } => {
+                let mut result = Vec::new();
+                let records = self.db.scan_prefix(RUN_TREE, run_prefix(name.as_str()).as_bytes())?
+                    .into_iter()
+                    .rev()
+                    .take(limit.unwrap_or(RUN_HISTORY));
+                for (_, mut value) in records {
+                    result.push(simd_json::from_slice(value.as_mut_slice())?);
+                }
+                Ok(DbReply::RunList(result))
+            }
+            DbMsg::AddProfile(profile) => {
+                let prefix = run_prefix(profile.trace_name.as_str());
+                let key = format!("{}{:020}{:010}", prefix, profile.started_at, profile.round);
+                self.db.insert(PROFILE_TREE, key.as_bytes(), self.redactor.to_json(&profile)?.as_bytes())?;
+                let keys = self.db.scan_prefix(PROFILE_TREE, prefix.as_bytes())?;
+                for (key, _) in keys.iter().take(keys.len().saturating_sub(self.history.max_entries)) {
+                    self.db.remove(PROFILE_TREE, key)?;
+                }
+                Ok(DbReply::Success)
+            }
+            DbMsg::Profile { name, round } => {
+                find_profile(self.db.as_ref(), name.as_str(), round)
+                    .map(|x| DbReply::ProfileResult(x))
+            }
+            DbMsg::SetBaseline { name, round } => {
+                let profile = find_profile(self.db.as_ref(), name.as_str(), round)?;
+                // the baseline is copied, so it outlives the rotation of profiles
+                self.db.insert(BASELINE_TREE, name.as_bytes(), &simd_json::to_vec(&profile)?)?;
+                Ok(DbReply::Success)
+            }
+            DbMsg::Baseline(name) => {
+                match self.db.get(BASELINE_TREE, name.as_bytes())? {
+                    Some(mut value) => {
+                        simd_json::from_slice(value.as_mut_slice())
+                            .map(|x| DbReply::ProfileResult(x))
+                            .map_err(|x| x.into())
+                    }
+                    None => Err(anyhow!("no baseline for {}", name))
+                }
+            }
+            DbMsg::SaveSchedule(state) => {
+                self.db.insert(SCHEDULE_TREE, state.trace_name.as_bytes(), &simd_json::to_vec(&state)?)?;
+                Ok(DbReply::Success)
+            }
+            DbMsg::LoadSchedule(name) => {
+                match self.db.get(SCHEDULE_TREE, name.as_bytes())? {
+                    Some(mut value) => {
+                        simd_json::from_slice(value.as_mut_slice())
+                            .map(|x| DbReply::ScheduleResult(x))
+                            .map_err(|x| x.into())
+                    }
+                    None => Err(anyhow!("no schedule for {}", name))
+                }
+            }
+            DbMsg::Subscribe => {
+                let sealer = self.sealer.clone();
+                let changes = self.db.watch(MODEL_TREE)?
+                    .filter_map(move |change| {
+                        let name = String::from_utf8_lossy(&change.key).to_string();
+                        let result = match change.value {
+                            Some(value) => sealer.open(&value)
+                                .and_then(|mut x| simd_json::from_slice(x.as_mut_slice()).map_err(|x| x.into()))
+                                .map(ModelChange::Stored),
+                            None => Ok(ModelChange::Removed(name.clone()))
+                        };
+                        if let Err(e) = &result {
+                            error!("cannot decode changed model {}: {}", name, e);
+                        }
+                        ready(result.ok())
+                    });
+                Ok(DbReply::Subscription(changes.boxed()))
+            }
+            DbMsg::Verify { quarantine } => {
+                let bad = self.verify()?;
+                if quarantine {
+                    for (entry, key) in &bad {
+                        if let Some(value) = self.db.remove(entry.tree.as_str(), key)? {
+                            let moved = [run_prefix(entry.tree.as_str()).as_bytes(), key.as_slice()].concat();
+                            self.db.insert(QUARANTINE_TREE, &moved, &value)?;
+                        }
+                    }
+                    self.db.flush()?;
+                }
+                Ok(DbReply::Verified(bad.into_iter().map(|x| x.0).collect()))
+            }
+            DbMsg::Upsert(mut model) => {
+                self.check(&model)?;
+                let current = self.db.get(MODEL_TREE, model.name.as_bytes())?;
+                let stored = self.load(model.name.as_str()).ok();
+                let tags = stored.as_ref().map(|x| x.tags.clone()).unwrap_or_default();
+                model.revision = stored.map(|x| x.revision).unwrap_or(0) + 1;
+                let value = self.encode(&model)?;
+                let tags = self.tag_changes(model.name.as_str(), tags.as_slice(), model.tags.as_slice())?;
+                if self.db.swap_with(MODEL_TREE, model.name.as_bytes(), current.as_ref().map(|x| x.as_slice()),
+                                     Some(value.as_slice()), &[(TAG_TREE, tags.as_slice())])? {
+                    self.db.flush_later();
+                    Ok(DbReply::Success)
+                } else {
+                    Err(anyhow!("{} was changed meanwhile", model.name))
                 }
             }
         }
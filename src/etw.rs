@@ -0,0 +1,44 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::*;
+use systemstat::Duration;
+
+pub fn start_command(profiles: &[String]) -> Command {
+    let mut command = Command::new("wpr");
+    for i in profiles {
+        command.arg("-start").arg(i);
+    }
+    command.arg("-filemode");
+    command
+}
+
+pub fn stop_command<A: AsRef<Path>>(filename: A) -> Command {
+    let mut command = Command::new("wpr");
+    command.arg("-stop").arg(filename.as_ref());
+    command
+}
+
+fn cancel_command() -> Command {
+    let mut command = Command::new("wpr");
+    command.arg("-cancel");
+    command
+}
+
+fn run(mut command: Command) -> Result<()> {
+    let output = command.stdin(Stdio::null()).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{:?} failed: {}", command, String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+pub async fn record<A: AsRef<Path>>(profiles: &[String], lasting: usize, filename: A) -> Result<()> {
+    run(start_command(profiles))?;
+    async_std::task::sleep(Duration::from_secs(lasting as u64)).await;
+    run(stop_command(filename)).map_err(|e| {
+        run(cancel_command()).ok();
+        e
+    })
+}
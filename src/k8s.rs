@@ -0,0 +1,49 @@
+use std::process::Command;
+
+use anyhow::*;
+
+use crate::database::PodTarget;
+
+fn crictl(args: &[&str]) -> Result<String> {
+    let output = Command::new("crictl")
+        .args(args)
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(anyhow!("crictl {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+fn first_line(content: String) -> Option<String> {
+    content.lines()
+        .map(|x| x.trim())
+        .find(|x| !x.is_empty())
+        .map(|x| x.to_string())
+}
+
+pub fn resolve_init_pid(target: &PodTarget) -> Result<u32> {
+    let mut args = vec!["pods", "--state", "ready", "--namespace", target.namespace.as_str(), "-q"];
+    for i in target.selector.iter() {
+        args.push("--label");
+        args.push(i.as_str());
+    }
+    // a restarted pod gets a new sandbox, so the lookup is redone every round
+    let pod = crictl(args.as_slice())
+        .map(first_line)?
+        .ok_or_else(|| anyhow!("no ready pod matches {:?} in {}", target.selector, target.namespace))?;
+    let mut args = vec!["ps", "--state", "running", "--pod", pod.as_str(), "-q"];
+    if let Some(container) = &target.container {
+        args.push("--name");
+        args.push(container.as_str());
+    }
+    let container = crictl(args.as_slice())
+        .map(first_line)?
+        .ok_or_else(|| anyhow!("no running container in pod {}", pod))?;
+    match crictl(&["inspect", "--output", "go-template", "--template", "{{.info.pid}}", container.as_str()])?
+        .trim()
+        .parse::<u32>()? {
+        0 => Err(anyhow!("container {} is not running", container)),
+        pid => Ok(pid)
+    }
+}
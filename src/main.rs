@@ -22,9 +22,17 @@ use serde::de::Unexpected::Seq;
 
 mod database;
 mod config;
+mod container;
+#[cfg(feature = "k8s")]
+mod k8s;
 mod socket;
 mod status;
+mod storage;
 mod client;
+mod control;
+#[cfg(windows)]
+mod etw;
+mod pprof;
 mod trace;
 mod utils;
 
@@ -35,60 +43,217 @@ static GLOBAL: snmalloc_rs::SnMalloc = snmalloc_rs::SnMalloc;
 async fn main() -> Result<()> {
     pretty_env_logger::try_init_timed_custom_env("GIRASOL_LOG_LEVEL")?;
     let conf: Config = config::Config::from_args();
-    let db = database::init(&conf.home).await?;
-    let mut db_actor = database::DataActor::new(db).start().await;
+    // the endpoint holds the database, so these go through its control socket
+    match &conf.subcommand {
+        SubCommand::Status => {
+            config::handle_status(&conf.home).await;
+            return Ok(());
+        }
+        SubCommand::Pause { name } => {
+            config::handle_control(&conf.home, control::ControlRequest::Pause(name.clone())).await;
+            return Ok(());
+        }
+        SubCommand::Resume { name } => {
+            config::handle_control(&conf.home, control::ControlRequest::Resume(name.clone())).await;
+            return Ok(());
+        }
+        SubCommand::Backup { output } if control::endpoint_active(&conf.home).await => {
+            // the endpoint may run from another directory
+            let output = std::env::current_dir()?.join(output);
+            config::handle_control(&conf.home, control::ControlRequest::Backup(output)).await;
+            return Ok(());
+        }
+        SubCommand::Restore { .. } if control::endpoint_active(&conf.home).await => {
+            log::error!("stop the endpoint before restoring the database");
+            std::process::exit(1);
+        }
+        _ => ()
+    }
+    let redactor = utils::Redactor::new(conf.redact.as_slice())?;
+    let sealer = match &conf.db_key_file {
+        Some(path) => database::Sealer::from_key_file(path)?,
+        None => Default::default()
+    };
+    let db = database::init(&conf.home, conf.storage.as_str(), &sealer).await?;
+    let mut db_actor = database::DataActor::new(db, conf.check_path())
+        .with_sealer(sealer)
+        .with_redactor(redactor.clone())
+        .with_history(conf.history())
+        .start().await;
     match conf.subcommand {
-        SubCommand::Endpoint { server } => {
+        SubCommand::Endpoint { server, queue, compression, otlp, otlp_only, max_concurrent_traces, drain_timeout } => {
             let (mut rd, wt) = socket::create_sockets(&server).await?;
-            let mut send_client = client::SendClient::new(wt).start().await;
+            let compression = compression.map(database::CompressionKind::Zstd);
+            let mut send_client = client::SendClient::new(wt, server, queue, compression)
+                .with_redactor(redactor.clone());
+            if let Some(endpoint) = otlp {
+                let exporter = client::OtlpExporter::new(endpoint).start().await;
+                send_client = send_client.with_otlp(exporter, otlp_only);
+            }
+            let mut send_client = send_client.start().await;
             let mut keeper = trace::HouseKeeper {
                 running_pids: Arc::new(Default::default()),
                 send_client: send_client.clone(),
                 running_trace: HashMap::new(),
+                db: db_actor.clone(),
+                round_slots: trace::RoundSlots::new(max_concurrent_traces),
+                cpu_sample: None,
+                exec_watcher: None,
+                groups: HashMap::new(),
+                draining: false,
+                redactor,
             }.start().await;
-            let handle = std::cell::UnsafeCell::new(db_actor.clone());
-            ctrlc::set_handler(move || unsafe {
-                async_std::task::block_on((*handle.get()).call(DbMsg::Kill)).check_error();
-                std::process::exit(0);
-            })?;
-            rd.listen(db_actor.clone(), send_client.clone(), keeper.clone()).await;
-            keeper.stop(None)?;
-            send_client.stop(None)?;
+            control::serve(&conf.home, keeper.clone(), db_actor.clone()).await?;
+            {
+                let follow = trace::follow_models(db_actor.clone(), keeper.clone());
+                async_std::task::spawn(async move { follow.await.check_error() });
+            }
+            {
+                let control = control::socket_path(&conf.home);
+                let mut db_actor = db_actor.clone();
+                let mut keeper = keeper.clone();
+                let mut send_client = send_client.clone();
+                ctrlc::set_handler(move || {
+                    let timeout = std::time::Duration::from_secs(drain_timeout);
+                    async_std::task::block_on(async {
+                        log::info!("shutting down, waiting up to {}s for running rounds", drain_timeout);
+                        match async_std::future::timeout(timeout, keeper.call(trace::DrainAll(timeout))).await {
+                            Ok(result) => result.check_error(),
+                            Err(_) => log::warn!("drain timed out")
+                        }
+                        keeper.call(trace::KeeperMsg::StopAll).await.check_error();
+                        match async_std::future::timeout(timeout, send_client.call(client::Flush)).await {
+                            Ok(Ok(0)) => (),
+                            Ok(Ok(left)) => log::warn!("{} frames are not sent before shutdown", left),
+                            Ok(Err(e)) => log::error!("{}", e),
+                            Err(_) => log::warn!("send queue is not flushed before shutdown")
+                        }
+                    });
+                    keeper.stop(None).check_error();
+                    send_client.stop(None).check_error();
+                    async_std::task::block_on(db_actor.call(DbMsg::Kill)).check_error();
+                    if control.exists() {
+                        std::fs::remove_file(&control).map_err(|x| x.into()).check_error();
+                    }
+                    std::process::exit(0);
+                })?;
+            }
+            loop {
+                rd.listen(db_actor.clone(), send_client.clone(), keeper.clone()).await;
+                log::warn!("server connection lost, waiting for reconnection");
+                send_client.send(client::Disconnected)?;
+                rd = loop {
+                    match send_client.call(client::TakeReader).await? {
+                        Some(rd) => break rd,
+                        None => async_std::task::sleep(std::time::Duration::from_secs(1)).await
+                    }
+                };
+            }
+        }
+        SubCommand::List { detail, filter, tags, json, archived } => {
+            config::handle_list(db_actor.clone(), detail, filter, tags, json, archived).await;
+        }
+        SubCommand::Add { dir: Some(dir), .. } => {
+            config::handle_add_dir(db_actor.clone(), dir).await;
+        }
+        SubCommand::Add { editor, method, .. } => {
+            config::handle_add(db_actor.clone(), editor, method).await;
         }
-        SubCommand::List { detail } => {
-            config::handle_list(db_actor.clone(), detail).await;
+        SubCommand::Update { name, editor, .. } => {
+            config::handle_update(db_actor.clone(), name, editor).await;
         }
-        SubCommand::Add { editor } => {
-            config::handle_add(db_actor.clone(), editor).await;
+        SubCommand::Enable { name } => {
+            config::handle_enable(db_actor.clone(), name, true).await;
+        }
+        SubCommand::Disable { name } => {
+            config::handle_enable(db_actor.clone(), name, false).await;
+        }
+        SubCommand::Archive { name } => {
+            config::handle_archive(db_actor.clone(), name, true).await;
+        }
+        SubCommand::Unarchive { name } => {
+            config::handle_archive(db_actor.clone(), name, false).await;
         }
         SubCommand::Check { name } => {
             config::handle_check(db_actor.clone(), name).await;
         }
-        SubCommand::Remove { name } => {
-            config::handle_remove(db_actor.clone(), name).await;
+        SubCommand::Remove { mut name } => {
+            if name.len() == 1 {
+                config::handle_remove(db_actor.clone(), name.remove(0)).await;
+            } else {
+                config::handle_remove_many(db_actor.clone(), name).await;
+            }
+        }
+        SubCommand::Rename { from, to } => {
+            config::handle_rename(db_actor.clone(), from, to).await;
+        }
+        SubCommand::Backup { output } => {
+            config::handle_backup(db_actor.clone(), output).await;
+        }
+        SubCommand::Restore { input } => {
+            config::handle_restore(db_actor.clone(), &conf.home, input).await;
         }
-        SubCommand::Local { name, round, pattern } => {
+        SubCommand::Export { output, names } => {
+            config::handle_export(db_actor.clone(), output, names).await;
+        }
+        SubCommand::Import { input, overwrite, .. } => {
+            config::handle_import(db_actor.clone(), input, overwrite).await;
+        }
+        SubCommand::Status | SubCommand::Pause { .. } | SubCommand::Resume { .. } =>
+            unsafe { std::intrinsics::unreachable() },
+        SubCommand::Diff { name, from, to } => {
+            config::handle_diff(db_actor.clone(), name, from, to).await;
+        }
+        SubCommand::Baseline { name, round } => {
+            config::handle_baseline(db_actor.clone(), name, round).await;
+        }
+        SubCommand::History { name, limit, json } => {
+            config::handle_history(db_actor.clone(), name, limit, json).await;
+        }
+        SubCommand::Db(config::DbCommand::Verify { quarantine }) => {
+            config::handle_verify(db_actor.clone(), quarantine).await;
+        }
+        SubCommand::Local { name, round, pattern, dry_run } => {
             let written = Arc::new(
-                (async_std::sync::Condvar::new(), async_std::sync::Mutex::new(AtomicUsize::new(round))));
-            if let DbReply::GetResult(model) = db_actor.call(Get(name)).await?? {
-                let actor = TraceActor {
-                    running_pids: Arc::new(Default::default()),
-                    local_pids: Default::default(),
-                    house_keeper: None,
-                    send_client: None,
-                    model,
-                    file: None,
-                    child: None,
-                    written: written.clone(),
-                    pattern,
+                (async_std::sync::Condvar::new(),
+                 async_std::sync::Mutex::new(AtomicUsize::new(if dry_run { 0 } else { round }))));
+            if let DbReply::GetResult(mut model) = db_actor.call(Get(name)).await?? {
+                let addr = if dry_run {
+                    trace::dry_run(&mut model, pattern.as_str()).check_error();
+                    None
+                } else {
+                    let actor = TraceActor {
+                        running_pids: Arc::new(Default::default()),
+                        local_pids: Default::default(),
+                        house_keeper: None,
+                        send_client: None,
+                        model,
+                        file: None,
+                        child: None,
+                        written: written.clone(),
+                        pattern,
+                        db: Some(db_actor.clone()),
+                        round: Default::default(),
+                        tool_version: None,
+                        adjusted_frequency: None,
+                        one_shot: false,
+                        holding_slot: false,
+                        pending_round: None,
+                        draining: false,
+                        pinned: Vec::new(),
+                        redactor,
+                        progress: Default::default(),
+                    };
+                    log::debug!("starting actor");
+                    Some(actor.start().await)
                 };
-                log::debug!("starting actor");
-                let mut addr = actor.start().await;
                 let mut handle = written.1.lock().await;
                 while handle.load(SeqCst) != 0 {
                     handle = written.0.wait(handle).await;
                 }
-                addr.stop(None)?;
+                if let Some(mut addr) = addr {
+                    addr.stop(None)?;
+                }
             }
         }
     }
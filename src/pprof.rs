@@ -0,0 +1,141 @@
+use hashbrown::HashMap;
+
+// field numbers of profile.proto
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_BYTES: u64 = 2;
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    // proto3 leaves default values out
+    if value != 0 {
+        varint(buf, (field as u64) << 3 | WIRE_VARINT);
+        varint(buf, value);
+    }
+}
+
+fn bytes(buf: &mut Vec<u8>, field: u32, data: &[u8]) {
+    varint(buf, (field as u64) << 3 | WIRE_BYTES);
+    varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn packed(buf: &mut Vec<u8>, field: u32, values: &[u64]) {
+    let mut inner = Vec::new();
+    for i in values {
+        varint(&mut inner, *i);
+    }
+    bytes(buf, field, inner.as_slice());
+}
+
+/// Collects stacks into a pprof profile with one location per function.
+pub struct Profile {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u64>,
+    functions: HashMap<String, u64>,
+    samples: Vec<(Vec<u64>, u64)>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        let mut profile = Profile {
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            functions: HashMap::new(),
+            samples: Vec::new(),
+        };
+        // the string table has to start with the empty string
+        profile.string("");
+        profile
+    }
+
+    fn string(&mut self, value: &str) -> u64 {
+        if let Some(id) = self.string_ids.get(value) {
+            return *id;
+        }
+        let id = self.strings.len() as u64;
+        self.strings.push(value.to_string());
+        self.string_ids.insert(value.to_string(), id);
+        id
+    }
+
+    fn function(&mut self, name: &str) -> u64 {
+        if let Some(id) = self.functions.get(name) {
+            return *id;
+        }
+        // ids start from 1, as 0 means unset
+        let id = self.functions.len() as u64 + 1;
+        self.functions.insert(name.to_string(), id);
+        self.string(name);
+        id
+    }
+
+    /// `frames` go from the leaf to the root, as pprof expects.
+    pub fn add<S: AsRef<str>>(&mut self, frames: &[S], weight: usize) {
+        let locations = frames.iter()
+            .map(|x| self.function(x.as_ref()))
+            .collect();
+        self.samples.push((locations, weight as u64));
+    }
+
+    pub fn encode(mut self, sample_type: &str, unit: &str) -> Vec<u8> {
+        let sample_type = self.string(sample_type);
+        let unit = self.string(unit);
+        let mut buf = Vec::new();
+        let mut value_type = Vec::new();
+        uint(&mut value_type, VALUE_TYPE_TYPE, sample_type);
+        uint(&mut value_type, VALUE_TYPE_UNIT, unit);
+        bytes(&mut buf, PROFILE_SAMPLE_TYPE, value_type.as_slice());
+        for (locations, weight) in &self.samples {
+            let mut sample = Vec::new();
+            packed(&mut sample, SAMPLE_LOCATION_ID, locations.as_slice());
+            packed(&mut sample, SAMPLE_VALUE, &[*weight]);
+            bytes(&mut buf, PROFILE_SAMPLE, sample.as_slice());
+        }
+        let mut functions = self.functions.iter()
+            .map(|(name, id)| (*id, self.string_ids[name.as_str()]))
+            .collect::<Vec<_>>();
+        functions.sort();
+        for (id, _) in &functions {
+            let mut line = Vec::new();
+            uint(&mut line, LINE_FUNCTION_ID, *id);
+            let mut location = Vec::new();
+            uint(&mut location, LOCATION_ID, *id);
+            bytes(&mut location, LOCATION_LINE, line.as_slice());
+            bytes(&mut buf, PROFILE_LOCATION, location.as_slice());
+        }
+        for (id, name) in &functions {
+            let mut function = Vec::new();
+            uint(&mut function, FUNCTION_ID, *id);
+            uint(&mut function, FUNCTION_NAME, *name);
+            uint(&mut function, FUNCTION_SYSTEM_NAME, *name);
+            bytes(&mut buf, PROFILE_FUNCTION, function.as_slice());
+        }
+        for i in &self.strings {
+            bytes(&mut buf, PROFILE_STRING_TABLE, i.as_bytes());
+        }
+        buf
+    }
+}
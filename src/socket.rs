@@ -45,14 +45,46 @@ impl WriteSocket {
 pub enum ServerMsg {
     Reply(String),
     QueryAll,
+    /// at most `limit` models in name order, starting after the model named `after`
+    QueryPage {
+        after: Option<String>,
+        limit: usize,
+    },
+    /// all models, replied to in chunks of the given size
+    QueryStream(usize),
+    /// models carrying all of the given tags
+    QueryTagged(Vec<String>),
     Query(String),
     Add(TraceModel),
     Remove(String),
+    /// replaces the model only if it is still at `revision`
+    Update {
+        model: TraceModel,
+        revision: u64,
+    },
+    /// removes the model only if it is still at `revision`
+    RemoveAt {
+        name: String,
+        revision: u64,
+    },
     Start(String),
     Stop(String),
+    /// a single round of the model right away, outside of its schedule
+    RunNow(String),
     StartAll,
     QueryRunning,
-    StopAll
+    StopAll,
+    Ack {
+        artifact_id: String,
+        offset: u64,
+    },
+    Resume {
+        artifact_id: String,
+        offset: u64,
+    },
+    Compression(Option<i32>),
+    /// whether the server acknowledges artifact chunks, which are only kept for resuming if it does
+    Acks(bool),
 }
 
 #[xactor::message(result = "()")]
@@ -61,6 +93,16 @@ pub enum ServerMsg {
 pub enum ClientReply {
     QueryResult(TraceModel),
     QueryList(Vec<TraceModel>),
+    /// `next` is the cursor to ask the following page with, if there may be one
+    QueryPage {
+        models: Vec<TraceModel>,
+        next: Option<String>,
+    },
+    /// a part of a streamed query, the last one is marked
+    QueryChunk {
+        models: Vec<TraceModel>,
+        last: bool,
+    },
     Error(String),
     Success(String),
     Running(Vec<String>),
@@ -94,6 +136,27 @@ impl ReadSocket {
                                 ServerMsg::Reply(msg) => {
                                     debug!("server replied {} for handshake", msg)
                                 }
+                                ServerMsg::Ack { artifact_id, offset } => {
+                                    let mut client = client.clone();
+                                    client.send(crate::client::Acknowledge { artifact_id, offset, resume: false })
+                                        .check_error();
+                                }
+                                ServerMsg::Compression(level) => {
+                                    let mut client = client.clone();
+                                    client.send(crate::client::SetCompression(level))
+                                        .check_error();
+                                }
+                                ServerMsg::Acks(enabled) => {
+                                    let mut client = client.clone();
+                                    client.send(crate::client::SetAcks(enabled))
+                                        .check_error();
+                                }
+                                ServerMsg::Resume { artifact_id, offset } => {
+                                    info!("server requested artifact {} from offset {}", artifact_id, offset);
+                                    let mut client = client.clone();
+                                    client.send(crate::client::Acknowledge { artifact_id, offset, resume: true })
+                                        .check_error();
+                                }
                                 ServerMsg::Query(msg) => {
                                     let mut client = client.clone();
                                     let mut db = db.clone();
@@ -146,6 +209,70 @@ impl ReadSocket {
                                     });
                                     debug!("query all issued at task {}", handle.task().id())
                                 }
+                                ServerMsg::QueryPage { after, limit } => {
+                                    let mut client = client.clone();
+                                    let mut db = db.clone();
+                                    let handle = async_std::task::spawn(async move {
+                                        match db.call(DbMsg::Page { after, limit }).await
+                                            .map_err(|x| x.into())
+                                            .and_then(|x| x) {
+                                            Err(e) => {
+                                                error!("{}", e);
+                                                client.send(ClientReply::Error(e.to_string()))
+                                                    .check_error();
+                                            }
+                                            Ok(DbReply::Page { models, next }) => client.send(ClientReply::QueryPage { models, next })
+                                                .check_error(),
+                                            _ => client.send(ClientReply::Error(String::from("unexpected database reply")))
+                                                .check_error()
+                                        }
+                                    });
+                                    debug!("page query issued at task {}", handle.task().id())
+                                }
+                                ServerMsg::QueryStream(page_size) => {
+                                    let mut client = client.clone();
+                                    let db = db.clone();
+                                    let handle = async_std::task::spawn(async move {
+                                        let mut pages = Box::pin(crate::database::model_pages(db, page_size.max(1)));
+                                        let mut current = pages.next().await;
+                                        while let Some(page) = current {
+                                            // looked ahead, so that the last chunk can be marked
+                                            let next = pages.next().await;
+                                            match page {
+                                                Ok(models) => client.send(ClientReply::QueryChunk { models, last: next.is_none() })
+                                                    .check_error(),
+                                                Err(e) => {
+                                                    error!("{}", e);
+                                                    client.send(ClientReply::Error(e.to_string()))
+                                                        .check_error();
+                                                    break;
+                                                }
+                                            }
+                                            current = next;
+                                        }
+                                    });
+                                    debug!("streamed query issued at task {}", handle.task().id())
+                                }
+                                ServerMsg::QueryTagged(tags) => {
+                                    let mut client = client.clone();
+                                    let mut db = db.clone();
+                                    let handle = async_std::task::spawn(async move {
+                                        match db.call(DbMsg::Search { prefix: None, contains: None, tags }).await
+                                            .map_err(|x| x.into())
+                                            .and_then(|x| x) {
+                                            Err(e) => {
+                                                error!("{}", e);
+                                                client.send(ClientReply::Error(e.to_string()))
+                                                    .check_error();
+                                            }
+                                            Ok(DbReply::AllList(t)) => client.send(ClientReply::QueryList(t))
+                                                .check_error(),
+                                            _ => client.send(ClientReply::Error(String::from("unexpected database reply")))
+                                                .check_error()
+                                        }
+                                    });
+                                    debug!("tagged query issued at task {}", handle.task().id())
+                                }
                                 ServerMsg::Add(model) => {
                                     let mut client = client.clone();
                                     let mut db = db.clone();
@@ -175,7 +302,7 @@ impl ReadSocket {
                                     let handle = async_std::task::spawn(async move {
                                         keeper.send(KeeperMsg::Unregister(name.clone()))
                                             .check_error();
-                                        match db.call(DbMsg::Remove(name.clone())).await
+                                        match db.call(DbMsg::Remove { name: name.clone(), revision: None }).await
                                             .map_err(|x| x.into())
                                             .and_then(|x| x) {
                                             Err(e) => {
@@ -192,6 +319,55 @@ impl ReadSocket {
                                     });
                                     debug!("remove issued at task {}", handle.task().id())
                                 }
+                                ServerMsg::Update { model, revision } => {
+                                    let mut client = client.clone();
+                                    let mut db = db.clone();
+                                    let name = model.name.clone();
+                                    let handle = async_std::task::spawn(async move {
+                                        match db.call(DbMsg::Update { model, revision }).await
+                                            .map_err(|x| x.into())
+                                            .and_then(|x| x) {
+                                            Err(e) => {
+                                                error!("{}", e);
+                                                client.send(ClientReply::Error(e.to_string()))
+                                                    .check_error();
+                                            }
+                                            Ok(DbReply::Success) => {
+                                                client.send(ClientReply::Success(format!("{} updated", name)))
+                                                    .check_error();
+                                            }
+                                            _ => client.send(ClientReply::Error(String::from("unexpected database reply")))
+                                                .check_error()
+                                        }
+                                    });
+                                    debug!("update issued at task {}", handle.task().id())
+                                }
+                                ServerMsg::RemoveAt { name, revision } => {
+                                    let mut client = client.clone();
+                                    let mut db = db.clone();
+                                    let mut keeper = keeper.clone();
+                                    let handle = async_std::task::spawn(async move {
+                                        match db.call(DbMsg::Remove { name: name.clone(), revision: Some(revision) }).await
+                                            .map_err(|x| x.into())
+                                            .and_then(|x| x) {
+                                            Err(e) => {
+                                                error!("{}", e);
+                                                client.send(ClientReply::Error(e.to_string()))
+                                                    .check_error();
+                                            }
+                                            Ok(DbReply::Success) => {
+                                                // only stopped once the removal went through
+                                                keeper.send(KeeperMsg::Unregister(name.clone()))
+                                                    .check_error();
+                                                client.send(ClientReply::Success(format!("{} removed", name)))
+                                                    .check_error();
+                                            }
+                                            _ => client.send(ClientReply::Error(String::from("unexpected database reply")))
+                                                .check_error()
+                                        }
+                                    });
+                                    debug!("checked remove issued at task {}", handle.task().id())
+                                }
                                 ServerMsg::QueryRunning => {
                                     let mut keeper = keeper.clone();
                                     let mut client = client.clone();
@@ -276,6 +452,42 @@ impl ReadSocket {
                                     });
                                     debug!("start trace issued at task {}", handle.task().id())
                                 }
+                                ServerMsg::RunNow(name) => {
+                                    let mut client = client.clone();
+                                    let mut db = db.clone();
+                                    let mut keeper = keeper.clone();
+                                    let handle = async_std::task::spawn(async move {
+                                        let result = db.call(DbMsg::Get(name.clone()))
+                                            .await
+                                            .map_err(|x| x.into())
+                                            .and_then(|x| x)
+                                            .and_then(|x| match x {
+                                                DbReply::GetResult(t) => Ok(t),
+                                                _ => Err(anyhow!("unexpected database reply"))
+                                            })
+                                            .and_then(|t| if t.runnable() {
+                                                Ok(t)
+                                            } else if t.archived_at.is_some() {
+                                                Err(anyhow!("{} is archived", name))
+                                            } else {
+                                                Err(anyhow!("{} is disabled", name))
+                                            });
+                                        match result {
+                                            Err(e) => {
+                                                error!("{}", e);
+                                                client.send(ClientReply::Error(e.to_string()))
+                                                    .check_error();
+                                            }
+                                            Ok(t) => match keeper.call(KeeperMsg::RunNow(t)).await {
+                                                Err(e) => client.send(ClientReply::Error(format!("failed to trigger trace {}: {}", name, e)))
+                                                    .check_error(),
+                                                Ok(_) => client.send(ClientReply::Success(format!("triggered trace {}", name)))
+                                                    .check_error(),
+                                            }
+                                        }
+                                    });
+                                    debug!("run now issued at task {}", handle.task().id())
+                                }
                                 ServerMsg::StartAll => {
                                     let mut client = client.clone();
                                     let mut db = db.clone();
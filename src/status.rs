@@ -16,8 +16,9 @@ pub struct HeartbeatPacket {
 impl Message for HeartbeatPacket { type Result = (); }
 
 pub fn get_status() -> HeartbeatPacket {
-    let platform = systemstat::platform::linux::PlatformImpl::new();
-    let cpu_temp = platform.cpu_temp().unwrap();
+    let platform = systemstat::System::new();
+    // not every platform reports the temperature
+    let cpu_temp = platform.cpu_temp().unwrap_or(0.0);
     let mem_status = platform.memory().unwrap();
     let system_uptime = platform.uptime().unwrap();
     let cpu_load = platform.load_average().unwrap().one;
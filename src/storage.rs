@@ -0,0 +1,494 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::*;
+use rusqlite::{params, OptionalExtension};
+
+pub const BACKENDS: &[&str] = &["sled", "sqlite"];
+
+/// The unnamed tree of a backend, only read to move what older versions kept there.
+pub const DEFAULT_TREE: &str = "";
+
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// A write to a watched tree, `value` is `None` for a removal.
+pub struct Change {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Ordered key-value trees the database actor keeps its data in.
+pub trait Storage: Send + Sync {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()>;
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Entries whose key starts with `prefix`, in key order.
+    fn scan_prefix(&self, tree: &str, prefix: &[u8]) -> Result<Vec<Entry>>;
+
+    /// At most `limit` entries whose key comes after `after`, in key order.
+    fn scan_after(&self, tree: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<Entry>>;
+
+    fn contains(&self, tree: &str, key: &[u8]) -> Result<bool> {
+        self.get(tree, key).map(|x| x.is_some())
+    }
+
+    /// Writes `new` only if the stored value is still `old`, telling whether it did.
+    fn compare_and_swap(&self, tree: &str, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> Result<bool>;
+
+    /// Moves a value to another key at once, failing if `to` is taken or `from` is missing.
+    fn rename(&self, tree: &str, from: &[u8], to: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Applies all the changes or none of them, the latter when a key to insert is taken
+    /// or a key to remove is missing.
+    fn write_all(&self, tree: &str, changes: &[Change]) -> Result<()> {
+        self.write_trees(&[(tree, changes)])
+    }
+
+    /// Like `write_all`, with the changes spread over several trees, each given once.
+    fn write_trees(&self, changes: &[(&str, &[Change])]) -> Result<()>;
+
+    /// Like `compare_and_swap`, along with `changes` to other trees, which are only applied
+    /// if the swap is, and fail it as in `write_all`.
+    fn swap_with(&self, tree: &str, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>,
+                 changes: &[(&str, &[Change])]) -> Result<bool>;
+
+    /// Trees holding at least one entry.
+    fn tree_names(&self) -> Result<Vec<String>>;
+
+    fn clear(&self, tree: &str) -> Result<()>;
+
+    /// Changes to the tree as they are made, by this process and, where the backend allows, by others.
+    fn watch(&self, tree: &str) -> Result<futures::channel::mpsc::UnboundedReceiver<Change>>;
+
+    /// Makes the writes so far durable, blocking until they are.
+    fn flush(&self) -> Result<()>;
+
+    /// Makes the writes so far durable without waiting for it.
+    fn flush_later(&self) {}
+}
+
+pub fn open(backend: &str, home: &Path) -> Result<Box<dyn Storage>> {
+    match backend {
+        "sled" => Ok(Box::new(SledStorage::open(home.join("database"))?)),
+        "sqlite" => Ok(Box::new(SqliteStorage::open(home.join("database.sqlite"))?)),
+        _ => Err(anyhow!("unknown storage backend {}", backend))
+    }
+}
+
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+impl SledStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(SledStorage { db: sled::open(path)? })
+    }
+
+    fn tree(&self, name: &str) -> Result<sled::Tree> {
+        if name == DEFAULT_TREE {
+            Ok((*self.db).clone())
+        } else {
+            self.db.open_tree(name).map_err(|x| x.into())
+        }
+    }
+}
+
+fn apply_changes(tx: &sled::transaction::TransactionalTree, changes: &[Change])
+                 -> sled::transaction::ConflictableTransactionResult<(), String> {
+    use sled::transaction::ConflictableTransactionError;
+    for i in changes {
+        let key = String::from_utf8_lossy(&i.key);
+        match &i.value {
+            Some(value) => if tx.insert(i.key.as_slice(), value.as_slice())?.is_some() {
+                return Err(ConflictableTransactionError::Abort(format!("{} exists", key)));
+            },
+            None => if tx.remove(i.key.as_slice())?.is_none() {
+                return Err(ConflictableTransactionError::Abort(format!("{} does not exist", key)));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Storage for SledStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.tree(tree)?.get(key)?.map(|x| x.to_vec()))
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.tree(tree)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.tree(tree)?.remove(key)?.map(|x| x.to_vec()))
+    }
+
+    fn scan_prefix(&self, tree: &str, prefix: &[u8]) -> Result<Vec<Entry>> {
+        self.tree(tree)?
+            .scan_prefix(prefix)
+            .map(|x| x.map(|(key, value)| (key.to_vec(), value.to_vec())).map_err(|x| x.into()))
+            .collect()
+    }
+
+    fn scan_after(&self, tree: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<Entry>> {
+        use std::ops::Bound;
+        let tree = self.tree(tree)?;
+        let iter = match after {
+            Some(key) => tree.range::<&[u8], _>((Bound::Excluded(key), Bound::Unbounded)),
+            None => tree.iter()
+        };
+        iter.take(limit)
+            .map(|x| x.map(|(key, value)| (key.to_vec(), value.to_vec())).map_err(|x| x.into()))
+            .collect()
+    }
+
+    fn compare_and_swap(&self, tree: &str, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> Result<bool> {
+        Ok(self.tree(tree)?.compare_and_swap(key, old, new)?.is_ok())
+    }
+
+    fn rename(&self, tree: &str, from: &[u8], to: &[u8], value: &[u8]) -> Result<()> {
+        use sled::transaction::{ConflictableTransactionError, TransactionError};
+        let result: sled::transaction::TransactionResult<(), String> = self.tree(tree)?.transaction(|tx| {
+            if tx.get(to)?.is_some() {
+                return Err(ConflictableTransactionError::Abort(format!("{} exists", String::from_utf8_lossy(to))));
+            }
+            if tx.remove(from)?.is_none() {
+                return Err(ConflictableTransactionError::Abort(format!("{} does not exist", String::from_utf8_lossy(from))));
+            }
+            tx.insert(to, value)?;
+            Ok(())
+        });
+        result.map_err(|e| match e {
+            TransactionError::Abort(e) => anyhow!(e),
+            TransactionError::Storage(e) => e.into()
+        })
+    }
+
+    fn write_trees(&self, changes: &[(&str, &[Change])]) -> Result<()> {
+        use sled::Transactional;
+        use sled::transaction::TransactionError;
+        let trees = changes.iter()
+            .map(|(tree, _)| self.tree(tree))
+            .collect::<Result<Vec<_>>>()?;
+        let result: sled::transaction::TransactionResult<(), String> = trees.as_slice().transaction(|views| {
+            for ((_, changes), tx) in changes.iter().zip(views.iter()) {
+                apply_changes(tx, changes)?;
+            }
+            Ok(())
+        });
+        result.map_err(|e| match e {
+            TransactionError::Abort(e) => anyhow!(e),
+            TransactionError::Storage(e) => e.into()
+        })
+    }
+
+    fn swap_with(&self, tree: &str, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>,
+                 changes: &[(&str, &[Change])]) -> Result<bool> {
+        use sled::Transactional;
+        use sled::transaction::TransactionError;
+        let mut trees = vec![self.tree(tree)?];
+        for (tree, _) in changes {
+            trees.push(self.tree(tree)?);
+        }
+        let result: sled::transaction::TransactionResult<bool, String> = trees.as_slice().transaction(|views| {
+            if views[0].get(key)?.as_ref().map(|x| x.as_ref()) != old {
+                return Ok(false);
+            }
+            match new {
+                Some(value) => views[0].insert(key, value)?,
+                None => views[0].remove(key)?
+            };
+            for ((_, changes), tx) in changes.iter().zip(views[1..].iter()) {
+                apply_changes(tx, changes)?;
+            }
+            Ok(true)
+        });
+        result.map_err(|e| match e {
+            TransactionError::Abort(e) => anyhow!(e),
+            TransactionError::Storage(e) => e.into()
+        })
+    }
+
+    fn tree_names(&self) -> Result<Vec<String>> {
+        let default = self.db.name();
+        let mut names = Vec::new();
+        for i in self.db.tree_names() {
+            let name = if i == default {
+                DEFAULT_TREE.to_string()
+            } else {
+                String::from_utf8(i.to_vec())?
+            };
+            if !self.tree(name.as_str())?.is_empty() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn clear(&self, tree: &str) -> Result<()> {
+        self.tree(tree)?.clear().map_err(|x| x.into())
+    }
+
+    fn watch(&self, tree: &str) -> Result<futures::channel::mpsc::UnboundedReceiver<Change>> {
+        let subscriber = self.tree(tree)?.watch_prefix(vec![]);
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        std::thread::spawn(move || {
+            for event in subscriber {
+                let change = match event {
+                    sled::Event::Insert { key, value } => Change { key: key.to_vec(), value: Some(value.to_vec()) },
+                    sled::Event::Remove { key } => Change { key: key.to_vec(), value: None }
+                };
+                if sender.unbounded_send(change).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let bytes = self.db.flush()?;
+        log::trace!("sled flushed {} bytes", bytes);
+        Ok(())
+    }
+
+    fn flush_later(&self) {
+        let db = self.db.clone();
+        async_std::task::spawn(async move { db.flush_async().await });
+    }
+}
+
+/// Keeps all trees in one table of a SQLite file, which unlike sled copes with homes on NFS.
+pub struct SqliteStorage {
+    path: PathBuf,
+    conn: Mutex<rusqlite::Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path.as_ref())?;
+        // the rollback journal, as WAL needs shared memory that network filesystems do not offer
+        conn.execute_batch("PRAGMA journal_mode = DELETE;
+            PRAGMA synchronous = FULL;
+            CREATE TABLE IF NOT EXISTS entries (
+                tree TEXT NOT NULL,
+                key BLOB NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY (tree, key)
+            ) WITHOUT ROWID;")?;
+        Ok(SqliteStorage { path: path.as_ref().to_path_buf(), conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<rusqlite::Connection> {
+        self.conn.lock().unwrap_or_else(|x| x.into_inner())
+    }
+}
+
+fn snapshot(conn: &rusqlite::Connection, tree: &str) -> Result<hashbrown::HashMap<Vec<u8>, Vec<u8>>> {
+    let mut statement = conn.prepare("SELECT key, value FROM entries WHERE tree = ?1")?;
+    let rows = statement.query_map(params![tree], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.map(|x| x.map_err(|x| x.into())).collect()
+}
+
+fn data_version(conn: &rusqlite::Connection) -> Result<i64> {
+    conn.query_row("PRAGMA data_version", params![], |row| row.get(0))
+        .map_err(|x| x.into())
+}
+
+/// Sends what differs between the snapshots of a tree, returning false once nobody listens.
+fn send_changes(sender: &futures::channel::mpsc::UnboundedSender<Change>,
+                before: &hashbrown::HashMap<Vec<u8>, Vec<u8>>,
+                after: &hashbrown::HashMap<Vec<u8>, Vec<u8>>) -> bool {
+    let removed = before.keys()
+        .filter(|x| !after.contains_key(*x))
+        .map(|x| Change { key: x.clone(), value: None });
+    let stored = after.iter()
+        .filter(|(key, value)| before.get(*key) != Some(*value))
+        .map(|(key, value)| Change { key: key.clone(), value: Some(value.clone()) });
+    removed.chain(stored).all(|x| sender.unbounded_send(x).is_ok())
+}
+
+fn select(conn: &rusqlite::Connection, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    conn.query_row("SELECT value FROM entries WHERE tree = ?1 AND key = ?2",
+                   params![tree, key], |row| row.get(0))
+        .optional()
+        .map_err(|x| x.into())
+}
+
+fn upsert(conn: &rusqlite::Connection, tree: &str, key: &[u8], value: &[u8]) -> Result<()> {
+    conn.execute("INSERT OR REPLACE INTO entries (tree, key, value) VALUES (?1, ?2, ?3)",
+                 params![tree, key, value])?;
+    Ok(())
+}
+
+fn delete(conn: &rusqlite::Connection, tree: &str, key: &[u8]) -> Result<()> {
+    conn.execute("DELETE FROM entries WHERE tree = ?1 AND key = ?2", params![tree, key])?;
+    Ok(())
+}
+
+fn write_changes(conn: &rusqlite::Connection, tree: &str, changes: &[Change]) -> Result<()> {
+    for i in changes {
+        let exists = select(conn, tree, &i.key)?.is_some();
+        match &i.value {
+            Some(_) if exists => return Err(anyhow!("{} exists", String::from_utf8_lossy(&i.key))),
+            Some(value) => upsert(conn, tree, &i.key, value)?,
+            None if exists => delete(conn, tree, &i.key)?,
+            None => return Err(anyhow!("{} does not exist", String::from_utf8_lossy(&i.key)))
+        }
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        select(&self.conn(), tree, key)
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        upsert(&self.conn(), tree, key, value)
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let value = select(&tx, tree, key)?;
+        delete(&tx, tree, key)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    fn scan_prefix(&self, tree: &str, prefix: &[u8]) -> Result<Vec<Entry>> {
+        let conn = self.conn();
+        // blobs compare bytewise, so the order is the one of sled
+        let mut statement = conn.prepare("SELECT key, value FROM entries
+            WHERE tree = ?1 AND substr(key, 1, ?2) = ?3 ORDER BY key")?;
+        let rows = statement.query_map(params![tree, prefix.len() as i64, prefix],
+                                       |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.map(|x| x.map_err(|x| x.into())).collect()
+    }
+
+    fn scan_after(&self, tree: &str, after: Option<&[u8]>, limit: usize) -> Result<Vec<Entry>> {
+        let conn = self.conn();
+        // every key is at least the empty blob
+        let query = match after {
+            Some(_) => "SELECT key, value FROM entries WHERE tree = ?1 AND key > ?2 ORDER BY key LIMIT ?3",
+            None => "SELECT key, value FROM entries WHERE tree = ?1 AND key >= ?2 ORDER BY key LIMIT ?3"
+        };
+        let mut statement = conn.prepare(query)?;
+        let rows = statement.query_map(params![tree, after.unwrap_or(&[]), limit as i64],
+                                       |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.map(|x| x.map_err(|x| x.into())).collect()
+    }
+
+    fn compare_and_swap(&self, tree: &str, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> Result<bool> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        if select(&tx, tree, key)?.as_ref().map(|x| x.as_slice()) != old {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => upsert(&tx, tree, key, value)?,
+            None => delete(&tx, tree, key)?
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn rename(&self, tree: &str, from: &[u8], to: &[u8], value: &[u8]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        if select(&tx, tree, to)?.is_some() {
+            return Err(anyhow!("{} exists", String::from_utf8_lossy(to)));
+        }
+        if select(&tx, tree, from)?.is_none() {
+            return Err(anyhow!("{} does not exist", String::from_utf8_lossy(from)));
+        }
+        delete(&tx, tree, from)?;
+        upsert(&tx, tree, to, value)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn write_trees(&self, changes: &[(&str, &[Change])]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for (tree, changes) in changes {
+            write_changes(&tx, tree, changes)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn swap_with(&self, tree: &str, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>,
+                 changes: &[(&str, &[Change])]) -> Result<bool> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        if select(&tx, tree, key)?.as_ref().map(|x| x.as_slice()) != old {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => upsert(&tx, tree, key, value)?,
+            None => delete(&tx, tree, key)?
+        }
+        for (tree, changes) in changes {
+            write_changes(&tx, tree, changes)?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn tree_names(&self) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT DISTINCT tree FROM entries ORDER BY tree")?;
+        let rows = statement.query_map(params![], |row| row.get(0))?;
+        rows.map(|x| x.map_err(|x| x.into())).collect()
+    }
+
+    fn clear(&self, tree: &str) -> Result<()> {
+        self.conn().execute("DELETE FROM entries WHERE tree = ?1", params![tree])?;
+        Ok(())
+    }
+
+    fn watch(&self, tree: &str) -> Result<futures::channel::mpsc::UnboundedReceiver<Change>> {
+        // a connection of its own, whose data version moves with the commits of every other one,
+        // including the ones of other processes
+        let conn = rusqlite::Connection::open(&self.path)?;
+        let tree = tree.to_string();
+        let mut version = data_version(&conn)?;
+        let mut before = snapshot(&conn, tree.as_str())?;
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            if sender.is_closed() {
+                break;
+            }
+            let after = match data_version(&conn) {
+                Ok(current) if current == version => continue,
+                Ok(current) => snapshot(&conn, tree.as_str()).map(|x| (current, x)),
+                Err(e) => Err(e)
+            };
+            match after {
+                Ok((current, after)) => {
+                    version = current;
+                    if !send_changes(&sender, &before, &after) {
+                        break;
+                    }
+                    before = after;
+                }
+                Err(e) => log::error!("cannot watch tree {}: {}", tree, e)
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn flush(&self) -> Result<()> {
+        // every statement commits on its own, with synchronous writes
+        Ok(())
+    }
+}
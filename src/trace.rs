@@ -10,10 +10,11 @@ use tempfile::NamedTempFile;
 use typename::*;
 use xactor::{Actor, Addr, Context, Handler, Message};
 
-use crate::database::TraceModel;
+use crate::database::{CallGraphMode, CompressionKind, ContainerTarget, CustomParser, DbMsg, DbReply, DiffAgainst,
+                      DynamicProbe, FlamegraphOutput, LoadLimit, TriggerMetric, TriggerRule, FtraceEvent, Frequency, OutputFormat, GpuTool, Hook, MemMode, PodTarget, ProfilerEvent, ProfilerFormat,
+                      Retention, SamplerFormat, SamplerTool, TraceContent, TraceModel, TraceTarget, UsdtProbe};
 use crate::utils::CheckError;
-use nix::unistd::Pid;
-use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
 use std::sync::Arc;
 use std::sync::atomic::Ordering::SeqCst;
 
@@ -21,7 +22,7 @@ macro_rules! template {
     () => {
 r#"
 probe process("{}").function("{}").call {{
-    printf("probe: %s", ppfunc());
+{}    printf("probe: %s", ppfunc());
     print_usyms(ucallers(-1));
 }}
 "#
@@ -30,292 +31,4302 @@ probe process("{}").function("{}").call {{
 
 
 
-fn to_script(function_list: &Vec<String>, process: &str, lasting: usize) -> String {
-    let mut vec = function_list.iter()
-        .map(|x| format!(template!(), process, x))
-        .collect::<Vec<_>>();
+macro_rules! usdt_template {
+    () => {
+r#"
+probe process("{}").provider("{}").mark("{}") {{
+{}    printf("probe: %s", "{}:{}");
+    print_usyms(ucallers(-1));
+}}
+"#
+};
+}
+
+enum ScriptGuard {
+    Nothing,
+    TargetSet,
+    Pids(Vec<i32>),
+}
+
+fn script_guard(model: &TraceModel) -> Result<ScriptGuard> {
+    match &model.target {
+        Some(TraceTarget::Cgroup(cgroup)) => crate::utils::cgroup_pids(cgroup).map(ScriptGuard::Pids),
+        Some(_) if model.follow_children => Ok(ScriptGuard::TargetSet),
+        _ => Ok(ScriptGuard::Nothing)
+    }
+}
+
+fn to_script(function_list: &Vec<String>, usdt_probes: &Vec<UsdtProbe>, process: &str, lasting: usize,
+             guard: &ScriptGuard) -> String {
+    let mut vec = Vec::new();
+    let guard = match guard {
+        ScriptGuard::Nothing => "",
+        ScriptGuard::TargetSet => "    if (!target_set_pid(pid())) next;\n",
+        ScriptGuard::Pids(pids) => {
+            vec.push(format!("global girasol_pids\nprobe begin {{\n{}}}\n", pids.iter()
+                .map(|x| format!("    girasol_pids[{}] = 1;\n", x))
+                .collect::<String>()));
+            "    if (!(pid() in girasol_pids)) next;\n"
+        }
+    };
+    vec.extend(function_list.iter()
+        .map(|x| format!(template!(), process, x, guard)));
+    vec.extend(usdt_probes.iter()
+        .map(|x| format!(usdt_template!(), x.binary, x.provider, x.name, guard, x.provider, x.name)));
     vec.push(format!("probe timer.s({}) {{exit(); }}\n", lasting));
     vec.join("\n")
 }
 
-fn to_tempfile(m: &TraceModel) -> Result<tempfile::NamedTempFile> {
-    match &m.content {
-        crate::database::TraceContent::SystemTap { function_list, process, .. } => {
-            tempfile::NamedTempFile::new()
-                .and_then(|mut x| x.write_all(to_script(function_list, process, m.lasting).as_bytes())
-                    .map(|_| x))
-                .map_err(|x| x.into())
+fn to_bpf_program(script: &str, lasting: usize) -> String {
+    if script.contains('\n') {
+        format!("{}\ninterval:s:{} {{ exit(); }}\n", script, lasting)
+    } else {
+        format!("{} interval:s:{} {{ exit(); }}", script, lasting)
+    }
+}
+
+fn to_dtrace_program(script: &str, lasting: usize) -> String {
+    format!("{}\ntick-{}s {{ exit(0); }}\n", script, lasting)
+}
+
+#[cfg(target_os = "freebsd")]
+fn prepare_dtrace() -> Result<()> {
+    let status = std::process::Command::new("kldload")
+        .arg("-n")
+        .arg("dtraceall")
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("cannot load dtraceall kernel module"))
+    }
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn prepare_dtrace() -> Result<()> {
+    Ok(())
+}
+
+fn dtrace_command(model: &TraceModel, script: &std::path::Path, target: &Option<String>)
+                  -> Result<std::process::Command> {
+    let mut command = deadline_command(model, "dtrace");
+    command.arg("-q")
+        .arg("-s")
+        .arg(script)
+        .args(target_args(model, "-p"));
+    if let Some(target) = target {
+        command.arg("-c").arg(target_shell(model, target)?);
+    }
+    Ok(command)
+}
+
+pub(crate) fn max_sample_rate() -> Result<usize> {
+    std::fs::read_to_string("/proc/sys/kernel/perf_event_max_sample_rate")
+        .map_err(|x| x.into())
+        .and_then(|x| x.trim().parse().map_err(|x: std::num::ParseIntError| x.into()))
+}
+
+fn resolve_frequency(name: &str, frequency: Frequency) -> Result<Option<usize>> {
+    match frequency {
+        Frequency::Default => Ok(None),
+        Frequency::Max => max_sample_rate().map(Some),
+        // the actor tunes the rate after the first round, so this is only the starting point
+        Frequency::Auto { .. } => max_sample_rate().map(|max| Some(PERF_DEFAULT_FREQUENCY.min(max))),
+        Frequency::Specific(value) => max_sample_rate().map(|max| {
+            if value > max {
+                warn!("trace {} frequency {} exceeds the max sample rate, clamped to {}", name, value, max);
+                Some(max)
+            } else {
+                Some(value)
+            }
+        })
+    }
+}
+
+fn fold_perf_output<R: BufRead>(reader: R) -> Result<Vec<u8>> {
+    use inferno::collapse::Collapse;
+    let mut folded = Vec::new();
+    inferno::collapse::perf::Folder::from(inferno::collapse::perf::Options::default())
+        .collapse(reader, &mut folded)?;
+    Ok(folded)
+}
+
+fn debuginfod_env(model: &TraceModel, command: &mut std::process::Command) {
+    if let Some(symbolization) = &model.symbolization {
+        if !symbolization.debuginfod_urls.is_empty() {
+            command.env("DEBUGINFOD_URLS", symbolization.debuginfod_urls.join(" "));
+        }
+        if let Some(cache_dir) = &symbolization.cache_dir {
+            command.env("DEBUGINFOD_CACHE_PATH", cache_dir);
+        }
+    }
+}
+
+/// perf invocation for post processing, which resolves symbols through debuginfod if configured.
+fn perf_post_command(model: &TraceModel) -> std::process::Command {
+    let mut command = std::process::Command::new("perf");
+    debuginfod_env(model, &mut command);
+    command
+}
+
+/// Pulls the debuginfo of every binary hit in the recording into the perf buildid cache,
+/// so that stripped binaries still resolve to function names.
+fn fetch_debuginfo(model: &TraceModel, filename: &str) -> Result<usize> {
+    if model.symbolization.is_none() {
+        return Ok(0);
+    }
+    let output = std::process::Command::new("perf")
+        .arg("buildid-list")
+        .arg("-i")
+        .arg(filename)
+        .stderr(Stdio::null())
+        .output()?;
+    let mut fetched = 0;
+    for build_id in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|x| x.split_ascii_whitespace().next()) {
+        let mut find = std::process::Command::new("debuginfod-find");
+        debuginfod_env(model, &mut find);
+        let found = find.arg("debuginfo")
+            .arg(build_id)
+            .stderr(Stdio::null())
+            .output()?;
+        if !found.status.success() {
+            continue;
+        }
+        let path = String::from_utf8_lossy(&found.stdout).trim().to_string();
+        if std::process::Command::new("perf")
+            .arg("buildid-cache")
+            .arg("-a")
+            .arg(path)
+            .stderr(Stdio::null())
+            .status()?
+            .success() {
+            fetched += 1;
+        }
+    }
+    Ok(fetched)
+}
+
+fn fold_perf_script(model: &TraceModel, filename: &str) -> Result<Vec<u8>> {
+    let mut child = perf_post_command(model)
+        .arg("script")
+        .arg("-i")
+        .arg(filename)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let result = fold_perf_output(std::io::BufReader::new(child.stdout.take().unwrap()));
+    child.wait()?;
+    result
+}
+
+fn render_flamegraph(title: String, folded: &[u8]) -> Result<Vec<u8>> {
+    let mut options = inferno::flamegraph::Options::default();
+    options.title = title;
+    let mut svg = Vec::new();
+    inferno::flamegraph::from_reader(&mut options, folded, &mut svg)?;
+    Ok(svg)
+}
+
+fn perf_sample_count(filename: &str) -> Result<usize> {
+    let output = std::process::Command::new("perf")
+        .arg("report")
+        .arg("-i")
+        .arg(filename)
+        .arg("--stats")
+        .output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| x.starts_with("SAMPLE events:"))
+        .filter_map(|x| x["SAMPLE events:".len()..].split_ascii_whitespace().next())
+        .filter_map(|x| x.parse().ok())
+        .next()
+        .ok_or_else(|| anyhow!("no sample count found in {}", filename))
+}
+
+/// Returns why the host is too busy for a round, if it is.
+fn overloaded(limit: &LoadLimit) -> Option<String> {
+    if let Some(max) = limit.max_loadavg {
+        match crate::utils::load_average() {
+            Ok(load) if load > max => return Some(format!("load average {:.2} above {:.2}", load, max)),
+            Err(e) => warn!("cannot read load average: {}", e),
+            _ => ()
+        }
+    }
+    for (resource, max) in [("cpu", limit.max_cpu_pressure), ("memory", limit.max_memory_pressure)].iter() {
+        if let Some(max) = max {
+            match crate::utils::pressure(resource) {
+                Ok(pressure) if pressure > *max =>
+                    return Some(format!("{} pressure {:.2}% above {:.2}%", resource, pressure, max)),
+                Err(e) => warn!("cannot read {} pressure: {}", resource, e),
+                _ => ()
+            }
+        }
+    }
+    None
+}
+
+fn sample_metric(metric: &TriggerMetric, cpu_percent: Option<f64>) -> Result<f64> {
+    match metric {
+        TriggerMetric::CpuPercent => cpu_percent.ok_or_else(|| anyhow!("cpu usage is not sampled yet")),
+        TriggerMetric::RssMb { absolute_path } => crate::utils::find_running(absolute_path)?
+            .into_iter()
+            .map(crate::utils::rss_bytes)
+            .collect::<Result<Vec<_>>>()
+            .map(|x| x.into_iter().sum::<u64>() as f64 / (1024.0 * 1024.0)),
+        TriggerMetric::CpuPressure => crate::utils::pressure("cpu"),
+        TriggerMetric::MemoryPressure => crate::utils::pressure("memory"),
+        TriggerMetric::IoPressure => crate::utils::pressure("io"),
+    }
+}
+
+const EXEC_SCRIPT: &str = r#"tracepoint:sched:sched_process_exec { printf("%d %s\n", pid, str(args->filename)); }"#;
+
+/// Follows every exec on the host with bpftrace and reports them to the keeper.
+fn watch_exec(keeper: Addr<HouseKeeper>) -> Result<std::process::Child> {
+    let mut child = std::process::Command::new("bpftrace")
+        .arg("-e")
+        .arg(EXEC_SCRIPT)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    let out = child.stdout.take().unwrap();
+    async_std::task::spawn(async move {
+        let mut keeper = keeper;
+        for line in std::io::BufReader::new(out).lines().filter_map(Result::ok) {
+            let mut fields = line.splitn(2, ' ');
+            let pid = fields.next().and_then(|x| x.parse::<i32>().ok());
+            match (pid, fields.next()) {
+                (Some(pid), Some(filename)) => keeper.send(KeeperMsg::Exec {
+                    pid,
+                    filename: filename.to_string(),
+                }).check_error(),
+                // the banner of bpftrace
+                _ => ()
+            }
+        }
+        warn!("exec watcher exited");
+    });
+    Ok(child)
+}
+
+fn target_pids(model: &TraceModel, absolute_path: &str) -> Result<Vec<i32>> {
+    match &model.target {
+        Some(TraceTarget::AttachPid(pid)) if crate::utils::pid_alive(*pid) => Ok(vec![*pid as i32]),
+        Some(TraceTarget::AttachPid(pid)) => Err(anyhow!("target process {} has exited", pid)),
+        Some(TraceTarget::Cgroup(cgroup)) => crate::utils::cgroup_pids(cgroup)
+            .map(|x| if absolute_path.is_empty() {
+                x
+            } else {
+                x.into_iter()
+                    .filter(|x| std::fs::read_link(format!("/proc/{}/exe", x))
+                        .map(|x| x.as_os_str() == absolute_path)
+                        .unwrap_or(false))
+                    .collect()
+            }),
+        Some(TraceTarget::Container(container)) => match container.init_pid {
+            Some(pid) if absolute_path.is_empty() => Ok(vec![pid as i32]),
+            Some(pid) => crate::utils::find_running(absolute_path)
+                .map(|x| x.into_iter()
+                    .filter(|x| crate::container::same_pid_namespace(*x, pid))
+                    .collect()),
+            None => Err(anyhow!("container {} is not resolved", container.container_id))
+        },
+        Some(TraceTarget::Pod(pod)) => match pod.init_pid {
+            Some(pid) if absolute_path.is_empty() => Ok(vec![pid as i32]),
+            Some(pid) => crate::utils::find_running(absolute_path)
+                .map(|x| x.into_iter()
+                    .filter(|x| crate::container::same_pid_namespace(*x, pid))
+                    .collect()),
+            None => Err(anyhow!("pod {:?} in {} is not resolved", pod.selector, pod.namespace))
+        },
+        None => crate::utils::find_running(absolute_path)
+    }.and_then(|pids| if model.follow_children {
+        crate::utils::find_descendants(&pids)
+    } else {
+        Ok(pids)
+    })
+}
+
+#[cfg(unix)]
+fn own_process_group(command: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+    use nix::unistd::Pid;
+    unsafe {
+        command.pre_exec(|| nix::unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0))
+            .map_err(|_| std::io::Error::last_os_error()));
+    }
+}
+
+#[cfg(windows)]
+fn own_process_group(command: &mut std::process::Command) {
+    use std::os::windows::process::CommandExt;
+    // CREATE_NEW_PROCESS_GROUP
+    command.creation_flags(0x200);
+}
+
+/// The cpus a task may run on, kept to restore them after a round.
+#[cfg(target_os = "linux")]
+type CpuMask = nix::sched::CpuSet;
+#[cfg(not(target_os = "linux"))]
+type CpuMask = ();
+
+#[cfg(target_os = "linux")]
+fn cpu_set(cpus: &[usize]) -> Option<nix::sched::CpuSet> {
+    if cpus.is_empty() {
+        return None;
+    }
+    let mut set = nix::sched::CpuSet::new();
+    for i in cpus {
+        if let Err(e) = set.set(*i) {
+            warn!("cannot pin to cpu {}: {}", i, e);
+        }
+    }
+    Some(set)
+}
+
+#[cfg(target_os = "linux")]
+fn pin_command(cpus: &[usize], command: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+    if let Some(set) = cpu_set(cpus) {
+        unsafe {
+            command.pre_exec(move || nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), &set)
+                .map_err(|_| std::io::Error::last_os_error()));
+        }
+    }
+}
+
+/// Returns the tasks that were moved, along with the cpus they ran on before.
+#[cfg(target_os = "linux")]
+fn pin_targets(model: &TraceModel, pids: &[i32]) -> Vec<(i32, CpuMask)> {
+    let mut pinned = Vec::new();
+    let set = match model.affinity.as_ref().and_then(|x| cpu_set(&x.target)) {
+        Some(set) => set,
+        None => return pinned
+    };
+    // affinity is per thread, so every task of the target has to be moved
+    for pid in pids {
+        let tasks = std::fs::read_dir(format!("/proc/{}/task", pid))
+            .map(|x| x.filter_map(|x| x.ok())
+                .filter_map(|x| x.file_name().to_string_lossy().parse::<i32>().ok())
+                .collect())
+            .unwrap_or_else(|_| vec![*pid]);
+        for task in tasks {
+            let previous = match nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(task)) {
+                Ok(previous) => previous,
+                Err(e) => {
+                    warn!("cannot read the affinity of task {} of trace {}: {}", task, model.name, e);
+                    continue;
+                }
+            };
+            match nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(task), &set) {
+                Ok(()) => pinned.push((task, previous)),
+                Err(e) => warn!("cannot pin task {} of trace {}: {}", task, model.name, e)
+            }
+        }
+    }
+    pinned
+}
+
+#[cfg(target_os = "linux")]
+fn unpin_targets(pinned: Vec<(i32, CpuMask)>) {
+    for (task, previous) in pinned {
+        // the task may be gone by now, which is fine
+        if let Err(e) = nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(task), &previous) {
+            debug!("cannot restore the affinity of task {}: {}", task, e);
+        }
+    }
+}
+
+// models with an affinity are refused elsewhere, these only cover ones stored before
+#[cfg(not(target_os = "linux"))]
+fn pin_command(cpus: &[usize], _: &mut std::process::Command) {
+    if !cpus.is_empty() {
+        warn!("cpu affinity is only supported on linux, ignored");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_targets(model: &TraceModel, _: &[i32]) -> Vec<(i32, CpuMask)> {
+    if model.affinity.as_ref().map(|x| !x.target.is_empty()).unwrap_or(false) {
+        warn!("cpu affinity is only supported on linux, trace {} runs unpinned", model.name);
+    }
+    Vec::new()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unpin_targets(_: Vec<(i32, CpuMask)>) {}
+
+#[cfg(unix)]
+fn switch_user(command: &mut std::process::Command, uid: u32, gid: u32) {
+    use std::os::unix::process::CommandExt;
+    command.uid(uid).gid(gid);
+}
+
+// `RunAs::ids` refuses every user off unix, so there is nothing to switch to
+#[cfg(not(unix))]
+fn switch_user(_: &mut std::process::Command, _: u32, _: u32) {}
+
+fn target_process(model: &TraceModel, mut command: std::process::Command) -> Result<std::process::Command> {
+    if let Some(run_as) = &model.run_as {
+        let (uid, gid) = run_as.ids()?;
+        switch_user(&mut command, uid, gid);
+    }
+    if let Some(affinity) = &model.affinity {
+        // overrides the profiler cpus set up by the timeout wrapper
+        pin_command(&affinity.target, &mut command);
+    }
+    Ok(command)
+}
+
+fn target_shell(model: &TraceModel, target: &str) -> Result<String> {
+    // the tracer itself runs the target here, so only the target command may drop privileges
+    let mut target = target.to_string();
+    if let Some(run_as) = &model.run_as {
+        let (uid, gid) = run_as.ids()?;
+        target = format!("setpriv --reuid={} --regid={} --init-groups {}", uid, gid, target);
+    }
+    match &model.affinity {
+        Some(affinity) if !affinity.target.is_empty() => {
+            let cpus = affinity.target.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            Ok(format!("taskset -c {} {}", cpus, target))
+        }
+        _ => Ok(target)
+    }
+}
+
+fn stap_args(model: &TraceModel, args: &[String]) -> Result<Vec<String>> {
+    let mut result = Vec::with_capacity(args.len());
+    let mut command_next = false;
+    for i in args {
+        result.push(if command_next { target_shell(model, i)? } else { i.clone() });
+        command_next = i == "-c";
+    }
+    Ok(result)
+}
+
+fn target_args(model: &TraceModel, flag: &str) -> Vec<String> {
+    match model.target.as_ref().and_then(|x| x.pid()) {
+        Some(pid) => vec![flag.to_string(), pid.to_string()],
+        None => Vec::new()
+    }
+}
+
+fn target_command(model: &TraceModel, program: &str) -> std::process::Command {
+    match &model.target {
+        Some(TraceTarget::Container(ContainerTarget { init_pid: Some(pid), .. })) |
+        Some(TraceTarget::Pod(PodTarget { init_pid: Some(pid), .. })) => {
+            let mut command = timed_command(model, "nsenter");
+            command.args(crate::container::nsenter_args(*pid)).arg(program);
+            command
+        }
+        _ => timed_command(model, program)
+    }
+}
+
+#[cfg(unix)]
+fn apply_limits(model: &TraceModel, command: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+    let limits = match model.limits {
+        Some(limits) => limits,
+        None => return
+    };
+    // there is no rlimit for cpu share, so the child joins the cgroup the actor made for the round
+    let group = limits.max_cpu_pct
+        .and_then(|_| crate::utils::cpu_cgroup_procs(model.name.as_str()).ok());
+    unsafe {
+        command.pre_exec(move || {
+            use nix::sys::resource::{setrlimit, Resource};
+            if let Some(mb) = limits.max_memory_mb {
+                setrlimit(Resource::RLIMIT_AS, mb << 20, mb << 20)
+                    .map_err(|_| std::io::Error::last_os_error())?;
+            }
+            if let Some(mb) = limits.max_file_size_mb {
+                setrlimit(Resource::RLIMIT_FSIZE, mb << 20, mb << 20)
+                    .map_err(|_| std::io::Error::last_os_error())?;
+            }
+            if let Some(mut file) = group.as_ref() {
+                // writing 0 moves the writing process itself
+                file.write_all(b"0")?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn apply_limits(model: &TraceModel, _: &mut std::process::Command) {
+    if model.limits.is_some() {
+        warn!("resource limits are only supported on unix, trace {} runs unlimited", model.name);
+    }
+}
+
+fn timed_command(model: &TraceModel, program: &str) -> std::process::Command {
+    let mut command = std::process::Command::new("timeout");
+    command.arg("--signal=INT")
+        .arg(format!("--kill-after={}", model.grace_secs))
+        .arg(model.lasting.to_string())
+        .arg(program);
+    apply_limits(model, &mut command);
+    if let Some(affinity) = &model.affinity {
+        pin_command(&affinity.profiler, &mut command);
+    }
+    command
+}
+
+fn deadline_command(model: &TraceModel, program: &str) -> std::process::Command {
+    // tools that stop themselves only get killed once the grace period is over as well
+    let mut command = std::process::Command::new("timeout");
+    command.arg("--signal=TERM")
+        .arg(format!("--kill-after={}", model.grace_secs))
+        .arg((model.lasting as u64 + model.grace_secs).to_string())
+        .arg(program);
+    apply_limits(model, &mut command);
+    if let Some(affinity) = &model.affinity {
+        pin_command(&affinity.profiler, &mut command);
+    }
+    command
+}
+
+fn timed_out(status: std::process::ExitStatus) -> bool {
+    // timeout exits with 124 once the recording window is over
+    status.success() || status.code() == Some(124)
+}
+
+fn killed(status: std::process::ExitStatus) -> bool {
+    // timeout exits with 128 + SIGKILL when the grace period is exceeded
+    status.code() == Some(137)
+}
+
+fn round_failure(program: &str, output: &std::process::Output) -> Error {
+    if killed(output.status) {
+        anyhow!("{} exceeded its deadline and was killed", program)
+    } else {
+        anyhow!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr))
+    }
+}
+
+fn hook_command(model: &TraceModel, program: &str) -> std::process::Command {
+    // hooks are expected to be quick, so they only get the grace period
+    let mut command = std::process::Command::new("timeout");
+    command.arg("--signal=TERM")
+        .arg("--kill-after=1")
+        .arg(model.grace_secs.to_string())
+        .arg(program);
+    command
+}
+
+fn ftrace_command(model: &TraceModel, events: &[FtraceEvent], args: &[String], filename: &str)
+                  -> std::process::Command {
+    let mut command = timed_command(model, "trace-cmd");
+    command
+        .arg("record")
+        .arg("-o")
+        .arg(filename)
+        .args(target_args(model, "-P"));
+    for i in events {
+        command.arg("-e").arg(&i.event);
+        if let Some(filter) = &i.filter {
+            command.arg("-f").arg(filter);
+        }
+    }
+    command.args(args.iter());
+    command
+}
+
+fn strace_command(model: &TraceModel, pids: &[i32], syscalls: &[String], follow_forks: bool,
+                  summary_only: bool, args: &[String], filename: &str) -> std::process::Command {
+    let mut command = timed_command(model, "strace");
+    command.arg("-o").arg(filename);
+    if follow_forks {
+        command.arg("-f");
+    }
+    if summary_only {
+        command.arg("-c");
+    } else {
+        command.arg("-T");
+    }
+    if !syscalls.is_empty() {
+        command.arg("-e").arg(format!("trace={}", syscalls.join(",")));
+    }
+    for i in pids {
+        command.arg("-p").arg(i.to_string());
+    }
+    command.args(args.iter());
+    command
+}
+
+fn perf_stat_command(model: &TraceModel, events: &[String], interval_ms: u64) -> std::process::Command {
+    let mut command = timed_command(model, "perf");
+    command.arg("stat")
+        .arg("-x,")
+        .arg("-I")
+        .arg(interval_ms.to_string());
+    if !events.is_empty() {
+        command.arg("-e").arg(events.join(","));
+    }
+    match (&model.target, model.target.as_ref().and_then(|x| x.pid())) {
+        (Some(TraceTarget::Cgroup(cgroup)), _) => command.arg("-a").arg("-G").arg(cgroup),
+        (_, Some(pid)) => command.arg("-p").arg(pid.to_string()),
+        (_, None) => command.arg("-a")
+    };
+    command
+}
+
+fn offcpu_command(model: &TraceModel, min_block_us: u64) -> std::process::Command {
+    let mut command = std::process::Command::new("offcputime-bpfcc");
+    command.arg("-f")
+        .arg("-m")
+        .arg(min_block_us.to_string())
+        .args(target_args(model, "-p"))
+        .arg(model.lasting.to_string());
+    command
+}
+
+fn intel_pt_command(model: &TraceModel, pids: &str, snapshot: bool, size_limit_mb: Option<usize>,
+                    additional_args: &[String], filename: &str) -> std::process::Command {
+    let mut command = timed_command(model, "perf");
+    command.arg("record")
+        .arg("-e")
+        .arg("intel_pt//u")
+        .arg("-p")
+        .arg(pids)
+        .arg("-o")
+        .arg(filename);
+    if snapshot {
+        command.arg("-S");
+    }
+    if let Some(size) = size_limit_mb {
+        command.arg(format!("--max-size={}M", size));
+    }
+    command.args(additional_args.iter());
+    command
+}
+
+fn callgrind_command(model: &TraceModel, program: &str, args: &[String], envs: &[(String, String)],
+                     filename: &str) -> Result<std::process::Command> {
+    let mut command = timed_command(model, "valgrind");
+    command.arg("--tool=callgrind")
+        .arg(format!("--callgrind-out-file={}", filename))
+        .arg(program)
+        .args(args.iter())
+        .envs(envs.iter().cloned());
+    target_process(model, command)
+}
+
+fn heaptrack_command(model: &TraceModel, program: &str, args: &[String], attach: bool, filename: &str)
+                     -> Result<std::process::Command> {
+    let mut command = timed_command(model, "heaptrack");
+    command.arg("-o").arg(filename);
+    if attach {
+        let name = std::path::Path::new(program)
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|| program.to_string());
+        command.arg("-p").arg(sampler_pid(model, name.as_str())?.to_string());
+        Ok(command)
+    } else {
+        command.arg(program).args(args.iter());
+        target_process(model, command)
+    }
+}
+
+fn lttng_session(model: &TraceModel) -> String {
+    format!("girasol-{}", model.name)
+}
+
+fn lttng_setup(model: &TraceModel, events: &[String], context: &[String], directory: &str)
+               -> Vec<std::process::Command> {
+    let session = lttng_session(model);
+    let mut create = std::process::Command::new("lttng");
+    create.arg("create")
+        .arg(session.as_str())
+        .arg(format!("--output={}", directory));
+    let mut enable = std::process::Command::new("lttng");
+    enable.arg("enable-event")
+        .arg("--userspace")
+        .arg(format!("--session={}", session))
+        .arg(events.join(","));
+    let mut result = vec![create, enable];
+    for i in context {
+        let mut add = std::process::Command::new("lttng");
+        add.arg("add-context")
+            .arg("--userspace")
+            .arg(format!("--session={}", session))
+            .arg(format!("--type={}", i));
+        result.push(add);
+    }
+    let mut start = std::process::Command::new("lttng");
+    start.arg("start").arg(session.as_str());
+    result.push(start);
+    result
+}
+
+fn lttng_teardown(model: &TraceModel) -> Vec<std::process::Command> {
+    let session = lttng_session(model);
+    let mut stop = std::process::Command::new("lttng");
+    stop.arg("stop").arg(session.as_str());
+    let mut destroy = std::process::Command::new("lttng");
+    destroy.arg("destroy").arg(session.as_str());
+    vec![stop, destroy]
+}
+
+fn run_all(commands: Vec<std::process::Command>) -> Result<()> {
+    for mut command in commands {
+        let output = command.stdin(Stdio::null()).output()?;
+        if !output.status.success() {
+            return Err(anyhow!("{:?} failed: {}", command, String::from_utf8_lossy(&output.stderr)));
+        }
+    }
+    Ok(())
+}
+
+fn sched_record_command(model: &TraceModel, filename: &str) -> std::process::Command {
+    let mut command = timed_command(model, "perf");
+    command.arg("sched")
+        .arg("record")
+        .arg("-o")
+        .arg(filename);
+    if let TraceContent::SchedLatency { additional_args } = &model.content {
+        command.args(additional_args.iter());
+    }
+    command
+}
+
+fn perf_mem_command(model: &TraceModel, pids: &str, mode: MemMode, additional_args: &[String],
+                    filename: &str) -> std::process::Command {
+    let mut command = timed_command(model, "perf");
+    command.arg("mem");
+    match mode {
+        MemMode::Load => { command.arg("-t").arg("load"); }
+        MemMode::Store => { command.arg("-t").arg("store"); }
+        MemMode::All => ()
+    }
+    command.arg("record")
+        .arg("-p")
+        .arg(pids)
+        .arg("-o")
+        .arg(filename)
+        .args(additional_args.iter());
+    command
+}
+
+fn sampler_pid(model: &TraceModel, process_name: &str) -> Result<i32> {
+    let pids = match model.target {
+        Some(_) => target_pids(model, ""),
+        None => crate::utils::find_by_name(process_name)
+    };
+    pids.and_then(|x| x.into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no running process named {}", process_name)))
+}
+
+fn sampler_command(model: &TraceModel, tool: SamplerTool, pid: i32, format: SamplerFormat,
+                   rate: Option<usize>, filename: &str) -> std::process::Command {
+    let (program, output_flag) = match tool {
+        SamplerTool::PySpy => ("py-spy", "-o"),
+        SamplerTool::RbSpy => ("rbspy", "--file"),
+    };
+    let format = match (tool, format) {
+        (_, SamplerFormat::Speedscope) => "speedscope",
+        (_, SamplerFormat::Flamegraph) => "flamegraph",
+        (SamplerTool::PySpy, SamplerFormat::Collapsed) => "raw",
+        (SamplerTool::RbSpy, SamplerFormat::Collapsed) => "collapsed",
+    };
+    let mut command = std::process::Command::new(program);
+    command.arg("record")
+        .arg("--pid")
+        .arg(pid.to_string())
+        .arg("--duration")
+        .arg(model.lasting.to_string())
+        .arg("--format")
+        .arg(format)
+        .arg(output_flag)
+        .arg(filename);
+    if let Some(rate) = rate {
+        command.arg("--rate").arg(rate.to_string());
+    }
+    command
+}
+
+fn async_profiler_command(model: &TraceModel, profiler_path: &str, pid: i32, event: ProfilerEvent,
+                          format: ProfilerFormat, filename: &str) -> std::process::Command {
+    let mut command = std::process::Command::new(profiler_path);
+    command.arg("-d")
+        .arg(model.lasting.to_string())
+        .arg("-e")
+        .arg(event.as_arg())
+        .arg("-o")
+        .arg(format.as_arg())
+        .arg("-f")
+        .arg(filename)
+        .arg(pid.to_string());
+    command
+}
+
+fn pcap_command(interface: &str, filter: &str, snap_len: usize, filename: &str) -> std::process::Command {
+    let mut command = std::process::Command::new("tcpdump");
+    command.arg("-i")
+        .arg(interface)
+        .arg("-s")
+        .arg(snap_len.to_string())
+        .arg("-U")
+        .arg("-w")
+        .arg(filename);
+    if !filter.is_empty() {
+        command.arg(filter);
+    }
+    command
+}
+
+fn perf_c2c_command(model: &TraceModel, pids: &str, additional_args: &[String], filename: &str)
+                    -> std::process::Command {
+    let mut command = timed_command(model, "perf");
+    command.arg("c2c")
+        .arg("record")
+        .arg("-o")
+        .arg(filename)
+        .args(additional_args.iter())
+        .arg("-p")
+        .arg(pids);
+    command
+}
+
+fn gpu_command(model: &TraceModel, tool: GpuTool, devices: &[u32], metrics: &[String], command: &[String],
+               filename: &str) -> Result<std::process::Command> {
+    let devices = devices.iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    match tool {
+        GpuTool::Nvidia => {
+            let mut result = std::process::Command::new("nvidia-smi");
+            result.arg("dmon")
+                .arg("-d")
+                .arg("1")
+                .arg("-c")
+                .arg(model.lasting.to_string());
+            if !devices.is_empty() {
+                result.arg("-i").arg(devices);
+            }
+            if !metrics.is_empty() {
+                result.arg("-s").arg(metrics.concat());
+            }
+            Ok(result)
+        }
+        GpuTool::Rocprof => {
+            let mut result = timed_command(model, "rocprof");
+            result.arg("--stats")
+                .arg("-o")
+                .arg(filename);
+            if !metrics.is_empty() {
+                result.arg("--pmc").args(metrics.iter());
+            }
+            if !devices.is_empty() {
+                result.env("HIP_VISIBLE_DEVICES", devices);
+            }
+            result.args(command.iter());
+            if command.is_empty() {
+                Ok(result)
+            } else {
+                target_process(model, result)
+            }
+        }
+    }
+}
+
+fn probe_name(model: &TraceModel, index: usize) -> String {
+    let name = model.name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect::<String>();
+    format!("girasol_{}_{}", name, index)
+}
+
+fn probe_add_command(model: &TraceModel, index: usize, probe: &DynamicProbe) -> std::process::Command {
+    let mut definition = format!("girasol:{}={}", probe_name(model, index), probe.function);
+    if let Some(offset) = probe.offset {
+        definition.push_str(format!("+{}", offset).as_str());
+    }
+    for i in &probe.args {
+        definition.push(' ');
+        definition.push_str(i);
+    }
+    let mut command = std::process::Command::new("perf");
+    command.arg("probe");
+    if let Some(binary) = &probe.binary {
+        command.arg("-x").arg(binary);
+    }
+    command.arg("--add").arg(definition);
+    command
+}
+
+fn probe_del_command(model: &TraceModel, index: usize) -> std::process::Command {
+    let mut command = std::process::Command::new("perf");
+    command.arg("probe")
+        .arg("--del")
+        .arg(format!("girasol:{}", probe_name(model, index)));
+    command
+}
+
+fn join_ids(ids: &[u32]) -> String {
+    ids.iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn perf_command(model: &TraceModel, pids: &str, frequency: Option<usize>, call_graph: Option<CallGraphMode>,
+                cpus: &Option<Vec<u32>>, tid_filter: &Option<Vec<u32>>, additional_args: &[String])
+                -> std::process::Command {
+    let mut child = std::process::Command::new("perf");
+    child.arg("record")
+        .arg("--no-buffering")
+        .arg("--branch-filter=any_call,u")
+        .arg("-e")
+        .arg("branches:u");
+    match (tid_filter, &model.target) {
+        (Some(tids), _) => child.arg("-t").arg(join_ids(tids)),
+        (None, Some(TraceTarget::Cgroup(cgroup))) => child.arg("-a").arg("-G").arg(cgroup),
+        (None, _) => child.arg("-p").arg(pids)
+    };
+    if let Some(cpus) = cpus {
+        child.arg("-C").arg(join_ids(cpus));
+    }
+    child.arg("-o")
+        .arg(format!("/tmp/girasol-perf-{}.data", model.name))
+        .args(additional_args.iter());
+    if let Some(value) = frequency {
+        child.arg("-F").arg(value.to_string());
+    }
+    if let Some(mode) = call_graph {
+        child.arg("--call-graph").arg(mode.as_arg());
+    }
+    child
+}
+
+fn perf_live_command(model: &TraceModel, pids: &str, frequency: Option<usize>, additional_args: &[String])
+                     -> std::process::Command {
+    let mut command = timed_command(model, "perf");
+    command.arg("record")
+        .arg("-g")
+        .arg("-p")
+        .arg(pids)
+        .arg("-o")
+        .arg("-")
+        .args(additional_args.iter());
+    if let Some(value) = frequency {
+        command.arg("-F").arg(value.to_string());
+    }
+    command
+}
+
+fn perf_live_script_command(model: &TraceModel) -> std::process::Command {
+    let mut command = perf_post_command(model);
+    command.arg("script")
+        .arg("-i")
+        .arg("-")
+        .arg("-F")
+        .arg("comm,tid,ip,sym");
+    command
+}
+
+pub fn dry_run(model: &mut TraceModel, pattern: &str) -> Result<()> {
+    if let Some(target) = &mut model.target {
+        target.resolve()?;
+        match target {
+            TraceTarget::Container(container) =>
+                println!("container: {} at pid {}", container.container_id, container.init_pid.unwrap_or(0)),
+            TraceTarget::Pod(pod) =>
+                println!("pod: {:?} in {} at pid {}", pod.selector, pod.namespace, pod.init_pid.unwrap_or(0)),
+            _ => ()
+        }
+    }
+    let model = &*model;
+    let (program, command, extension) = match &model.content {
+        TraceContent::SystemTap { function_list, usdt_probes, process, args, envs } => {
+            model.content.check_path()?;
+            println!("script:\n{}", to_script(function_list, usdt_probes, process, model.lasting,
+                                                 &script_guard(model)?));
+            let mut command = deadline_command(model, "stap");
+            command.args(target_args(model, "-x"))
+                .arg("<script>")
+                .envs(envs.clone().into_iter())
+                .args(stap_args(model, args)?);
+            ("stap", command, None)
+        }
+        TraceContent::BpfTrace { script, args, envs } => {
+            let mut command = deadline_command(model, "bpftrace");
+            command.args(target_args(model, "-p"));
+            if script.contains('\n') {
+                println!("script:\n{}", to_bpf_program(script, model.lasting));
+                command.arg("<script>");
+            } else {
+                command.arg("-e").arg(to_bpf_program(script, model.lasting));
+            }
+            command.envs(envs.clone().into_iter())
+                .args(args.iter());
+            ("bpftrace", command, Some("txt"))
+        }
+        TraceContent::PerfBranch { frequency, absolute_path, additional_args, call_graph, cpus, tid_filter, .. } => {
+            model.content.check_path()?;
+            let frequency = resolve_frequency(model.name.as_str(), *frequency)?;
+            let pids = target_pids(model, absolute_path.as_str())?
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            if pids.is_empty() {
+                warn!("no running process of {}", absolute_path);
+            }
+            let command = perf_command(model, pids.as_str(), frequency, *call_graph,
+                                       cpus, tid_filter, additional_args);
+            ("perf", command, Some("json"))
+        }
+        TraceContent::Ftrace { events, args } => {
+            let filename = format!("/tmp/girasol-ftrace-{}.dat", model.name);
+            ("trace-cmd", ftrace_command(model, events, args, filename.as_str()), Some("json"))
+        }
+        TraceContent::Strace { process, syscalls, follow_forks, summary_only, args } => {
+            let pids = target_pids(model, process.as_str())?;
+            let filename = format!("/tmp/girasol-strace-{}.txt", model.name);
+            ("strace", strace_command(model, pids.as_slice(), syscalls, *follow_forks, *summary_only,
+                                      args, filename.as_str()), Some("json"))
+        }
+        TraceContent::PerfStat { events, interval_ms } => {
+            ("perf", perf_stat_command(model, events, *interval_ms), Some("json"))
+        }
+        TraceContent::OffCpu { min_block_us, .. } => {
+            ("offcputime-bpfcc", offcpu_command(model, *min_block_us), Some("json"))
+        }
+        TraceContent::IntelPt { absolute_path, snapshot, size_limit_mb, additional_args, .. } => {
+            let pids = target_pids(model, absolute_path.as_str())?
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let filename = format!("/tmp/girasol-pt-{}.data", model.name);
+            ("perf", intel_pt_command(model, pids.as_str(), *snapshot, *size_limit_mb, additional_args,
+                                      filename.as_str()), Some("data"))
+        }
+        TraceContent::Callgrind { program, args, envs } => {
+            let filename = format!("/tmp/girasol-callgrind-{}.out", model.name);
+            ("valgrind", callgrind_command(model, program, args, envs, filename.as_str())?, Some("callgrind"))
+        }
+        TraceContent::Custom { command, args, parser, .. } => {
+            let mut timed = target_command(model, command.as_str());
+            timed.args(args.iter());
+            let extension = match parser {
+                CustomParser::Raw => "out",
+                CustomParser::Lines => "json",
+                CustomParser::Folded => "json",
+            };
+            (command.as_str(), timed, Some(extension))
+        }
+        TraceContent::SchedLatency { .. } => {
+            let filename = format!("/tmp/girasol-sched-{}.data", model.name);
+            ("perf", sched_record_command(model, filename.as_str()), Some("json"))
+        }
+        TraceContent::PerfMem { absolute_path, mode, additional_args } => {
+            let pids = target_pids(model, absolute_path.as_str())?
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let filename = format!("/tmp/girasol-mem-{}.data", model.name);
+            ("perf", perf_mem_command(model, pids.as_str(), *mode, additional_args, filename.as_str()),
+             Some("json"))
+        }
+        TraceContent::Sampler { tool, process_name, format, rate } => {
+            let pid = sampler_pid(model, process_name.as_str())?;
+            let filename = format!("/tmp/girasol-sampler-{}.{}", model.name, format.extension());
+            let program = match tool {
+                SamplerTool::PySpy => "py-spy",
+                SamplerTool::RbSpy => "rbspy",
+            };
+            (program, sampler_command(model, *tool, pid, *format, *rate, filename.as_str()),
+             Some(format.extension()))
+        }
+        TraceContent::AsyncProfiler { profiler_path, process_name, event, format } => {
+            let pid = sampler_pid(model, process_name.as_str())?;
+            let filename = format!("/tmp/girasol-async-{}.{}", model.name, format.as_arg());
+            let extension = match format {
+                ProfilerFormat::Jfr => "jfr",
+                ProfilerFormat::Collapsed => "json",
+            };
+            (profiler_path.as_str(), async_profiler_command(model, profiler_path.as_str(), pid, *event, *format,
+                                                            filename.as_str()), Some(extension))
+        }
+        TraceContent::Pcap { interface, filter, snap_len, .. } => {
+            let filename = format!("/tmp/girasol-pcap-{}.pcap", model.name);
+            let extension = if model.compression.is_none() { "pcap.zst" } else { "pcap" };
+            ("tcpdump", pcap_command(interface, filter, *snap_len, filename.as_str()), Some(extension))
+        }
+        TraceContent::PerfC2C { absolute_path, additional_args } => {
+            let pids = target_pids(model, absolute_path.as_str())?
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let filename = format!("/tmp/girasol-c2c-{}.data", model.name);
+            ("perf", perf_c2c_command(model, pids.as_str(), additional_args, filename.as_str()), Some("json"))
+        }
+        TraceContent::Energy { domains } => {
+            for (domain, path) in rapl_domains(domains)? {
+                println!("domain: {} at {}", domain, path.display());
+            }
+            return Ok(());
+        }
+        TraceContent::Gpu { tool, devices, metrics, command } => {
+            model.content.check_path()?;
+            let filename = format!("/tmp/girasol-rocprof-{}.csv", model.name);
+            let (program, extension) = match tool {
+                GpuTool::Nvidia => ("nvidia-smi", "json"),
+                GpuTool::Rocprof => ("rocprof", "csv"),
+            };
+            (program, gpu_command(model, *tool, devices, metrics, command, filename.as_str())?, Some(extension))
+        }
+        TraceContent::Heaptrack { program, args, attach, summary_only } => {
+            model.content.check_path()?;
+            let filename = format!("/tmp/girasol-heaptrack-{}", model.name);
+            let extension = if *summary_only { "txt" } else { "gz" };
+            ("heaptrack", heaptrack_command(model, program, args, *attach, filename.as_str())?, Some(extension))
+        }
+        TraceContent::Lttng { events, context } => {
+            let directory = format!("/tmp/girasol-lttng-{}", model.name);
+            let mut setup = lttng_setup(model, events, context, directory.as_str());
+            let start = setup.pop().unwrap();
+            for i in setup {
+                println!("setup: {:?}", i);
+            }
+            for i in lttng_teardown(model) {
+                println!("teardown: {:?}", i);
+            }
+            ("lttng", start, Some("tar.gz"))
+        }
+        TraceContent::DTrace { script, target } => {
+            println!("script:\n{}", to_dtrace_program(script, model.lasting));
+            ("dtrace", dtrace_command(model, std::path::Path::new("<script>"), target)?, Some("txt"))
+        }
+        #[cfg(windows)]
+        TraceContent::Etw { profiles } => {
+            let filename = std::env::temp_dir().join(format!("girasol-etw-{}.etl", model.name));
+            println!("stop: {:?}", crate::etw::stop_command(filename));
+            ("wpr", crate::etw::start_command(profiles), Some("etl"))
+        }
+        #[cfg(not(windows))]
+        TraceContent::Etw { .. } => return Err(anyhow!("etw based trace is only available on windows")),
+        TraceContent::PerfLive { absolute_path, frequency, flush_secs, additional_args } => {
+            model.content.check_path()?;
+            let frequency = resolve_frequency(model.name.as_str(), *frequency)?;
+            let pids = target_pids(model, absolute_path.as_str())?
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            if pids.is_empty() {
+                warn!("no running process of {}", absolute_path);
+            }
+            println!("script: {:?}", perf_live_script_command(model));
+            println!("flush: every {}s", flush_secs);
+            ("perf", perf_live_command(model, pids.as_str(), frequency, additional_args), Some("json"))
+        }
+    };
+    crate::utils::find_in_path(program)
+        .ok_or_else(|| anyhow!("{} is not found in PATH", program))?;
+    for (index, probe) in model.probes.iter().enumerate() {
+        println!("probe: {:?}", probe_add_command(model, index, probe));
+    }
+    if model.warmup_secs > 0 {
+        println!("warmup: {}s", model.warmup_secs);
+    }
+    println!("command: {:?}", command);
+    if let Some(extension) = extension {
+        match model.compression {
+            Some(kind) => println!("output: {}-<round>.{}.{}", pattern, extension, kind.extension()),
+            None => println!("output: {}-<round>.{}", pattern, extension)
+        }
+    }
+    Ok(())
+}
+
+fn to_tempfile(m: &TraceModel) -> Result<tempfile::NamedTempFile> {
+    match &m.content {
+        crate::database::TraceContent::SystemTap { function_list, usdt_probes, process, .. } => {
+            let guard = script_guard(m)?;
+            tempfile::NamedTempFile::new()
+                .and_then(|mut x| x.write_all(to_script(function_list, usdt_probes, process, m.lasting,
+                                                          &guard).as_bytes())
+                    .map(|_| x))
+                .map_err(|x| x.into())
+        }
+        crate::database::TraceContent::BpfTrace { script, .. } => {
+            tempfile::NamedTempFile::new()
+                .and_then(|mut x| x.write_all(to_bpf_program(script, m.lasting).as_bytes())
+                    .map(|_| x))
+                .map_err(|x| x.into())
+        }
+        crate::database::TraceContent::DTrace { script, .. } => {
+            tempfile::NamedTempFile::new()
+                .and_then(|mut x| x.write_all(to_dtrace_program(script, m.lasting).as_bytes())
+                    .map(|_| x))
+                .map_err(|x| x.into())
+        }
+        content => {
+            Err(anyhow!("{} based trace cannot be translated into temp files", content.method()))
+        }
+    }
+}
+
+/// Whether a file is named the way `write_round_with` names rounds: `<pattern>-<number>.<extension>`.
+fn round_file(name: &str, prefix: &str) -> bool {
+    if !name.starts_with(prefix) {
+        return false;
+    }
+    let rest = &name[prefix.len()..];
+    let digits = rest.chars().take_while(|x| x.is_ascii_digit()).count();
+    let extension = &rest[digits..];
+    digits > 0
+        && extension.len() > 1
+        && extension.starts_with('.')
+        && extension.chars().all(|x| x.is_ascii_alphanumeric() || x == '.' || x == '_' || x == '-')
+}
+
+fn enforce_retention(pattern: &str, retention: &Retention) {
+    let pattern = std::path::Path::new(pattern);
+    let prefix = match pattern.file_name().and_then(|x| x.to_str()) {
+        Some(name) => format!("{}-", name),
+        None => return
+    };
+    let dir = match pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => std::path::PathBuf::from(".")
+    };
+    let mut files = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries.filter_map(Result::ok)
+            .filter(|x| x.file_name().to_str().map(|x| round_file(x, prefix.as_str())).unwrap_or(false))
+            .filter_map(|x| x.metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .map(|m| (m, x.path())))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            error!("cannot read output directory {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    // newest first
+    files.sort_by(|a, b| b.0.cmp(&a.0));
+    let now = std::time::SystemTime::now();
+    for (index, (modified, path)) in files.into_iter().enumerate() {
+        let too_many = retention.max_files.map(|x| index >= x).unwrap_or(false);
+        let too_old = retention.max_age_secs
+            .and_then(|x| now.duration_since(modified).ok().map(|age| age.as_secs() > x))
+            .unwrap_or(false);
+        if too_many || too_old {
+            match std::fs::remove_file(&path) {
+                Ok(_) => info!("retention removed {}", path.display()),
+                Err(e) => error!("retention cannot remove {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+pub struct HouseKeeper {
+    pub(crate) running_pids: Arc<crossbeam_skiplist::SkipSet<i32>>,
+    pub(crate) send_client: Addr<crate::client::SendClient>,
+    pub(crate) running_trace: HashMap<String, RunningTrace>,
+    pub(crate) db: Addr<crate::database::DataActor>,
+    pub(crate) round_slots: RoundSlots,
+    /// busy and total cpu jiffies at the last tick
+    pub(crate) cpu_sample: Option<(u64, u64)>,
+    pub(crate) exec_watcher: Option<std::process::Child>,
+    pub(crate) groups: HashMap<String, GroupRound>,
+    pub(crate) draining: bool,
+    pub(crate) redactor: crate::utils::Redactor,
+}
+
+/// The latest round of a trace group, with the members yet to finish it.
+pub struct GroupRound {
+    id: String,
+    pending: hashbrown::HashSet<String>,
+    /// whether the next round of the group is scheduled
+    armed: bool,
+}
+
+/// Rounds running at the same time, with the traces waiting for their turn.
+#[derive(Default)]
+pub struct RoundSlots {
+    limit: Option<usize>,
+    active: hashbrown::HashSet<String>,
+    /// ordered by priority, then by arrival
+    waiting: std::collections::VecDeque<String>,
+    preempting: hashbrown::HashSet<String>,
+    /// rounds held back until the host calms down
+    deferred: Vec<String>,
+}
+
+impl RoundSlots {
+    pub fn new(limit: Option<usize>) -> Self {
+        RoundSlots {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    fn available(&self) -> bool {
+        self.limit.map(|x| self.active.len() < x).unwrap_or(true)
+    }
+
+    /// Whether the trace is running a round, or asking to.
+    fn busy(&self, name: &str) -> bool {
+        self.active.contains(name)
+            || self.waiting.iter().any(|x| x == name)
+            || self.deferred.iter().any(|x| x == name)
+    }
+}
+
+pub struct RunningTrace {
+    addr: Addr<TraceActor>,
+    method: &'static str,
+    lasting: usize,
+    paused: bool,
+    priority: i32,
+    load_limit: Option<LoadLimit>,
+    progress: Arc<RoundProgress>,
+    schedule: Option<cron::Schedule>,
+    interval: usize,
+    jitter_secs: u64,
+    next_run: Option<chrono::DateTime<chrono::Local>>,
+    triggers: Vec<TriggerState>,
+    exec_watch: Option<regex::Regex>,
+    group: Option<String>,
+    run_after: Option<String>,
+    /// the model it was started with, see `setup_of`
+    setup: Vec<u8>,
+}
+
+/// The model as far as a running trace goes, leaving out what changes in place.
+fn setup_of(model: &TraceModel) -> Vec<u8> {
+    let mut value = serde_json::to_value(model).unwrap_or_default();
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("paused");
+        fields.remove("revision");
+    }
+    serde_json::to_vec(&value).unwrap_or_default()
+}
+
+struct TriggerState {
+    rule: TriggerRule,
+    /// since when the metric has been above the threshold
+    since: Option<std::time::Instant>,
+    /// fired in the current crossing, rearmed when the metric drops back
+    fired: bool,
+}
+
+impl RunningTrace {
+    fn next_run(&self, now: &chrono::DateTime<chrono::Local>) -> Option<chrono::DateTime<chrono::Local>> {
+        let jitter = chrono::Duration::from_std(crate::utils::jitter(self.jitter_secs))
+            .unwrap_or_else(|_| chrono::Duration::zero());
+        self.schedule.as_ref()
+            .and_then(|x| x.after(now).next())
+            .map(|x| x + jitter)
+    }
+}
+
+pub struct TraceActor {
+    pub(crate) running_pids: Arc<crossbeam_skiplist::SkipSet<i32>>,
+    pub(crate) local_pids: crossbeam_skiplist::SkipSet<i32>,
+    pub(crate) house_keeper: Option<Addr<HouseKeeper>>,
+    pub(crate) send_client: Option<Addr<crate::client::SendClient>>,
+    pub(crate) model: TraceModel,
+    pub(crate) file: Option<NamedTempFile>,
+    pub(crate) child: Option<std::process::Child>,
+    pub(crate) written: Arc<(async_std::sync::Condvar, async_std::sync::Mutex<AtomicUsize>)>,
+    pub(crate) pattern: String,
+    pub(crate) db: Option<Addr<crate::database::DataActor>>,
+    pub(crate) round: RoundState,
+    pub(crate) tool_version: Option<String>,
+    pub(crate) adjusted_frequency: Option<usize>,
+    /// spawned for a single triggered round, and stopped after it
+    pub(crate) one_shot: bool,
+    pub(crate) holding_slot: bool,
+    /// the round waiting for a slot from the house keeper
+    pub(crate) pending_round: Option<TraceEvent>,
+    /// set on shutdown, no more rounds start
+    pub(crate) draining: bool,
+    /// target tasks pinned for the current round, with the cpus they had before
+    pub(crate) pinned: Vec<(i32, CpuMask)>,
+    pub(crate) redactor: crate::utils::Redactor,
+    pub(crate) progress: Arc<RoundProgress>,
+}
+
+/// How far a trace has got, as shown by the status.
+#[derive(Default)]
+pub struct RoundProgress {
+    /// unix time the current round started at, zero in between rounds
+    started_at: AtomicU64,
+    /// bytes of records and artifacts shipped to the server
+    sent: AtomicU64,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName, Clone)]
+pub struct OverheadAdjustment {
+    pub(crate) trace_name: String,
+    pub(crate) overhead_pct: f64,
+    pub(crate) budget_pct: f64,
+    /// the lowered frequency, or none if the trace was aborted
+    pub(crate) frequency: Option<usize>,
+}
+
+/// perf samples at 4000Hz unless told otherwise
+const PERF_DEFAULT_FREQUENCY: usize = 4000;
+const PERF_MIN_FREQUENCY: usize = 10;
+
+#[derive(Default)]
+pub struct RoundState {
+    index: usize,
+    started_at: u64,
+    exit_code: Option<i32>,
+    output_bytes: usize,
+    records: usize,
+    attempt: usize,
+    errors: Vec<String>,
+    suppressed: usize,
+    /// endings of preempted perf rounds that are still on their way
+    stale_endings: usize,
+    /// rounds skipped while paused
+    missed: usize,
+    /// missed rounds still to run back to back
+    catch_up: std::cell::Cell<usize>,
+    failed: bool,
+    /// consecutive failed rounds
+    failures: usize,
+    /// id of the group round this one belongs to
+    group_round: Option<String>,
+    /// the last file written by the round
+    output: Option<String>,
+    /// self samples of every function so far, stored and diffed once the round is over
+    profile: HashMap<String, usize>,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName, Clone)]
+pub struct RunRecord {
+    pub(crate) trace_name: String,
+    pub(crate) round: usize,
+    pub(crate) started_at: u64,
+    pub(crate) ended_at: u64,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) output_bytes: usize,
+    pub(crate) records: usize,
+    pub(crate) tool_version: Option<String>,
+    #[serde(default)]
+    pub(crate) group_round: Option<String>,
+    #[serde(default)]
+    pub(crate) failed: bool,
+    /// starts from 1, retries count up from there
+    #[serde(default)]
+    pub(crate) attempt: usize,
+    #[serde(default)]
+    pub(crate) output: Option<String>,
+}
+
+/// Where the schedule of a trace stood, so that a restarted agent picks it up.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ScheduleState {
+    pub(crate) trace_name: String,
+    pub(crate) rounds: usize,
+    pub(crate) last_run: u64,
+    /// unix time of the next self-scheduled round, zero for cron schedules
+    pub(crate) next_run: u64,
+    /// the auto-tuned perf frequency, so that it survives a restart
+    #[serde(default)]
+    pub(crate) adjusted_frequency: Option<usize>,
+}
+
+fn tool_version(program: &str) -> Option<String> {
+    std::process::Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|x| String::from_utf8_lossy(&x.stdout).lines()
+            .chain(String::from_utf8_lossy(&x.stderr).lines())
+            .map(|x| x.trim())
+            .find(|x| !x.is_empty())
+            .map(|x| x.to_string()))
+}
+
+#[xactor::message(result = "()")]
+#[derive(Copy, Clone)]
+pub enum TraceEvent {
+    NextRound,
+    PerfEnding,
+    Retry,
+    /// a round requested out of schedule, which ignores pauses and allowed windows
+    Trigger,
+}
+
+#[xactor::message(result = "()")]
+pub enum KeeperMsg {
+    Unregister(String),
+    StartAll(Vec<TraceModel>),
+    Start(TraceModel),
+    /// the model was written to the database
+    Stored(TraceModel),
+    RunNow(TraceModel),
+    RequestSlot(String),
+    ReleaseSlot(String),
+    Expire {
+        name: String,
+        rounds: usize,
+        remove: bool,
+    },
+    /// a round has concluded, with no retry to follow
+    RoundDone {
+        name: String,
+        succeeded: bool,
+    },
+    /// the group is due for a round
+    GroupRound(String),
+    GroupDone {
+        group: String,
+        name: String,
+    },
+    /// a process has exec'd the given file
+    Exec {
+        pid: i32,
+        filename: String,
+    },
+    /// holds back a cron schedule until the given unix time
+    Backoff {
+        name: String,
+        until: u64,
+    },
+    StopAll,
+    Retain {
+        pattern: String,
+        retention: Retention,
+    },
+}
+
+#[xactor::message(result = "()")]
+struct SchedulerTick;
+
+#[xactor::message(result = "anyhow::Result<()>")]
+pub struct SetPaused {
+    pub(crate) name: String,
+    pub(crate) paused: bool,
+}
+
+#[xactor::message(result = "()")]
+struct Pause(bool);
+
+#[xactor::message(result = "()")]
+struct SlotGranted;
+
+/// Stops a trace from starting rounds, replying whether one is still running.
+#[xactor::message(result = "bool")]
+struct Drain;
+
+/// Lets running rounds finish, for at most the given time, with no new ones started.
+#[xactor::message(result = "()")]
+pub struct DrainAll(pub Duration);
+
+/// Starts a round as part of the group round with the given id.
+#[xactor::message(result = "()")]
+struct GroupStart(String);
+
+#[xactor::message(result = "()")]
+struct Preempt;
+
+#[xactor::message(result = "Vec<String>")]
+pub struct AllRunning;
+
+#[xactor::message(result = "Vec<TraceStatus>")]
+pub struct AllStatus;
+
+#[derive(Serialize, Deserialize)]
+pub struct TraceStatus {
+    pub(crate) name: String,
+    pub(crate) method: String,
+    pub(crate) running: u64,
+    pub(crate) lasting: usize,
+    pub(crate) sent: u64,
+    #[serde(default)]
+    pub(crate) paused: bool,
+}
+
+#[async_trait::async_trait]
+impl Actor for TraceActor {
+    async fn started(&mut self, ctx: &Context<Self>) {
+        for (index, probe) in self.model.probes.iter().enumerate() {
+            match probe_add_command(&self.model, index, probe).output() {
+                Ok(x) if x.status.success() => info!("trace {} added probe {}", self.model.name,
+                                                     probe_name(&self.model, index)),
+                Ok(x) => error!("trace {} cannot add probe {}: {}", self.model.name, probe.function,
+                                String::from_utf8_lossy(&x.stderr)),
+                Err(e) => error!("trace {} cannot add probe {}: {}", self.model.name, probe.function, e)
+            }
+        }
+        self.tool_version = self.model.content.tool().and_then(tool_version);
+        if self.one_shot {
+            ctx.address().send(TraceEvent::Trigger).check_error();
+            return;
+        }
+        if let (Some(after), true) = (&self.model.run_after, self.chained()) {
+            info!("trace {} runs after {}", self.model.name, after);
+            return;
+        }
+        if let (Some(group), true) = (&self.model.group, self.grouped()) {
+            info!("trace {} runs with group {}", self.model.name, group);
+            return;
+        }
+        let state = self.restore_schedule().await;
+        let missed = state.as_ref().map(|x| self.missed_rounds(x)).unwrap_or(0);
+        let owed = self.model.catch_up.owed(missed);
+        if owed > 0 {
+            info!("trace {} catches up on {} of {} missed rounds", self.model.name, owed, missed);
+            self.round.catch_up.set(owed - 1);
+            ctx.address().send(TraceEvent::NextRound).check_error();
+            return;
+        }
+        if self.model.schedule.is_some() {
+            info!("trace {} waits for its schedule", self.model.name);
+            self.schedule_next(ctx);
+            return;
+        }
+        if let Some(next_run) = state.map(|x| x.next_run).filter(|x| *x > 0) {
+            let now = crate::utils::unix_now();
+            let interval = self.model.interval.max(1) as u64;
+            // missed rounds are skipped up to the next slot ahead
+            let next_run = if next_run < now {
+                next_run + ((now - next_run) / interval + 1) * interval
+            } else {
+                next_run
+            };
+            info!("trace {} resumes its schedule in {}s", self.model.name, next_run - now);
+            ctx.send_later(TraceEvent::NextRound, Duration::from_secs(next_run - now));
+            return;
+        }
+        if self.model.warmup_secs > 0 || self.model.jitter_secs > 0 {
+            let delay = Duration::from_secs(self.model.warmup_secs) + crate::utils::jitter(self.model.jitter_secs);
+            info!("trace {} waits {:.1}s before the first round", self.model.name, delay.as_secs_f64());
+            ctx.send_later(TraceEvent::NextRound, delay);
+            return;
+        }
+        log::debug!("starting next round info");
+        if let Err(e) = ctx.address().send(TraceEvent::NextRound) {
+            error!("trace {} cannot start the event with err: {}, going to suicide!", self.model.name, e);
+            ctx.stop(None);
+        }
+    }
+
+    async fn stopped(&mut self, _: &Context<Self>) {
+        if let Some(mut c) = self.child.take() {
+            self.kill_child(&mut c);
+        }
+        for index in 0..self.model.probes.len() {
+            if let Err(e) = probe_del_command(&self.model, index).output() {
+                error!("trace {} cannot remove probe {}: {}", self.model.name, probe_name(&self.model, index), e);
+            }
+        }
+        unpin_targets(std::mem::replace(&mut self.pinned, Vec::new()));
+        self.remove_cgroup();
+        self.release_slot();
+        info!("trace {} actor stopped", self.model.name);
+    }
+}
+
+impl TraceActor {
+    fn kill_child(&self, c: &mut std::process::Child) {
+        if self.model.follow_children {
+            if let Err(e) = crate::utils::stop_process(c.id(), true, crate::utils::Stop::Kill) {
+                error!("cannot kill running perf group {}, pid: {}", e, c.id())
+            }
+            c.wait().ok();
+        } else if let Err(e) = c.kill() {
+            error!("cannot kill running perf {}, pid: {}", e, c.id())
+        }
+    }
+
+    fn release_slot(&mut self) {
+        if !self.holding_slot {
+            return;
+        }
+        self.holding_slot = false;
+        if let Some(keeper) = &mut self.house_keeper {
+            keeper.send(KeeperMsg::ReleaseSlot(self.model.name.clone())).check_error();
+        }
+    }
+
+    async fn commit_suicide(&mut self) {
+        if let Some(keeper) = &mut self.house_keeper {
+            keeper.send(KeeperMsg::Unregister(self.model.name.clone()))
+                .map_err(|x| x.into())
+                .check_error()
+        }
+    }
+
+    fn self_scheduled(&self) -> bool {
+        !self.one_shot && !self.chained() && !self.grouped()
+            && (self.model.schedule.is_none() || self.house_keeper.is_none())
+    }
+
+    /// Group members are started by the house keeper, which keeps one schedule for the whole group.
+    fn grouped(&self) -> bool {
+        self.model.group.is_some() && self.house_keeper.is_some()
+    }
+
+    /// Chained traces are started by the house keeper once their predecessor has finished.
+    fn chained(&self) -> bool {
+        self.model.run_after.is_some() && self.house_keeper.is_some()
+    }
+
+    /// Rounds on a cron schedule are fired by the house keeper, unless the actor runs on its own.
+    fn schedule_next(&self, ctx: &Context<Self>) {
+        if self.one_shot || self.chained() || self.grouped() {
+            return;
+        }
+        let owed = self.round.catch_up.get();
+        if owed > 0 {
+            self.round.catch_up.set(owed - 1);
+            ctx.send_later(TraceEvent::NextRound, Duration::from_secs(0));
+            return;
+        }
+        let delay = match (self.model.cron(), &self.house_keeper) {
+            (Ok(None), _) => Some(Duration::from_secs(self.model.interval as u64)
+                + crate::utils::jitter(self.model.jitter_secs)),
+            (Ok(Some(_)), Some(_)) => None,
+            (Ok(Some(schedule)), None) => {
+                let now = chrono::Local::now();
+                schedule.after(&now).next()
+                    .map(|next| (next - now).to_std().unwrap_or_default()
+                        + crate::utils::jitter(self.model.jitter_secs))
+            }
+            (Err(e), _) => {
+                error!("trace {} cannot be scheduled: {}", self.model.name, e);
+                None
+            }
+        };
+        if let Some(delay) = delay {
+            ctx.send_later(TraceEvent::NextRound, delay);
+        }
+        self.save_schedule(delay);
+    }
+
+    /// Local runs start over every time, so only the endpoint keeps its schedule.
+    fn save_schedule(&self, delay: Option<Duration>) {
+        if let (Some(mut db), Some(_)) = (self.db.clone(), &self.house_keeper) {
+            db.send(DbMsg::SaveSchedule(ScheduleState {
+                trace_name: self.model.name.clone(),
+                rounds: self.round.index,
+                last_run: self.round.started_at,
+                next_run: delay.map(|x| crate::utils::unix_now() + x.as_secs()).unwrap_or(0),
+                adjusted_frequency: self.adjusted_frequency,
+            })).check_error();
+        }
+    }
+
+    fn missed_rounds(&self, state: &ScheduleState) -> usize {
+        use chrono::TimeZone;
+        let now = crate::utils::unix_now();
+        match self.model.cron() {
+            Ok(Some(schedule)) if state.last_run > 0 => schedule
+                .after(&chrono::Local.timestamp(state.last_run as i64, 0))
+                .take_while(|x| x.timestamp() as u64 <= now)
+                .take(1024)
+                .count(),
+            Ok(None) if state.next_run > 0 && state.next_run <= now =>
+                1 + ((now - state.next_run) / self.model.interval.max(1) as u64) as usize,
+            _ => 0
+        }
+    }
+
+    async fn restore_schedule(&mut self) -> Option<ScheduleState> {
+        let db = match (&mut self.db, &self.house_keeper) {
+            (Some(db), Some(_)) => db,
+            _ => return None
+        };
+        match db.call(DbMsg::LoadSchedule(self.model.name.clone())).await
+            .map_err(|x| x.into())
+            .and_then(|x| x) {
+            Ok(DbReply::ScheduleResult(state)) => {
+                info!("trace {} resumes after round {}", self.model.name, state.rounds);
+                self.round.index = state.rounds;
+                self.round.started_at = state.last_run;
+                self.adjusted_frequency = state.adjusted_frequency;
+                Some(state)
+            }
+            Ok(_) => {
+                error!("unexpected database reply to the schedule of trace {}", self.model.name);
+                None
+            }
+            Err(e) => {
+                debug!("no schedule of trace {} to restore: {}", self.model.name, e);
+                None
+            }
+        }
+    }
+
+    fn report_error(&mut self, content: String) {
+        error!("trace {} error: {}", self.model.name, content);
+        if self.model.retry.is_some() {
+            // held back until the round is known to have exhausted its retries
+            self.round.errors.push(content);
+        } else {
+            self.round.failed = true;
+            self.send_error(content);
+        }
+    }
+
+    fn send_error(&mut self, content: String) {
+        if let Some(sender) = &mut self.send_client {
+            sender.send(TraceError {
+                trace_name: self.model.name.clone(),
+                content,
+            }).check_error();
+        }
+    }
+
+    fn forward_stderr(&self, err: std::process::ChildStderr) -> async_std::task::JoinHandle<()> {
+        let err_name = self.model.name.clone();
+        let mut err_client = self.send_client.clone();
+        async_std::task::spawn(async move {
+            for i in std::io::BufReader::new(err).lines() {
+                if let Ok(c) = i {
+                    error!("trace {} error: {}", err_name, c);
+                    if let Some(err_client) = &mut err_client {
+                        err_client.send(TraceError {
+                            trace_name: err_name.clone(),
+                            content: c,
+                        }).check_error();
+                    }
+                }
+            }
+        })
+    }
+
+    fn reap(&mut self, program: &str, mut child: std::process::Child) {
+        let status = child.wait();
+        self.round.exit_code = status.as_ref().ok().and_then(|x| x.code());
+        match status {
+            Ok(status) if killed(status) =>
+                self.report_error(format!("{} exceeded its deadline and was killed", program)),
+            Ok(_) => (),
+            Err(e) => self.report_error(e.to_string())
+        }
+    }
+
+    fn ship_captured(&mut self, captured: Captured) {
+        self.round.exit_code = captured.status;
+        match &mut self.send_client {
+            Some(sender) => for i in captured.outputs {
+                sender.send(i).check_error();
+            },
+            // local rounds only keep the payload, so the captured output goes to the log instead
+            None => for i in captured.outputs {
+                info!("trace {} {}: {}", self.model.name, i.stream, i.content);
+            }
+        }
+    }
+
+    async fn emit_records<T>(&mut self, records: Vec<T>)
+        where T: Message<Result=()> + Serialize + TypeName {
+        self.round.records += records.len();
+        let compression = self.model.compression;
+        match &mut self.send_client {
+            Some(sender) => for i in records {
+                let length = simd_json::to_vec(&i).map(|x| x.len()).unwrap_or(0);
+                self.progress.sent.fetch_add(length as u64, SeqCst);
+                match compression {
+                    Some(kind) => sender.send(crate::client::Compressed(i, kind)).check_error(),
+                    None => sender.send(i).check_error()
+                }
+            },
+            None => {
+                let json = simd_json::to_string_pretty(&records).map_err(|x| x.into());
+                match json {
+                    Ok(json) => self.write_round("json", json).await.check_error(),
+                    Err(e) => {
+                        error!("trace {} cannot serialize records: {}", self.model.name, e);
+                        self.write_round("json", String::from("[]")).await.check_error();
+                    }
+                }
+            }
+        }
+    }
+
+    /// `leaf_first` tells the order of the frames in the stacks.
+    async fn emit_stacks(&mut self, stacks: Vec<Stack>, leaf_first: bool) {
+        self.profile_round(stacks.as_slice(), leaf_first);
+        match self.model.output_format {
+            OutputFormat::Native => self.emit_records(stacks).await,
+            OutputFormat::Folded => {
+                let folded = stacks.into_iter()
+                    .map(|mut x| {
+                        if leaf_first {
+                            x.frames.reverse();
+                        }
+                        format!("{} {}", x.frames.join(";"), x.weight)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.ship_bytes(folded.into_bytes(), "folded").await;
+            }
+            OutputFormat::Pprof => {
+                let mut profile = crate::pprof::Profile::new();
+                for mut i in stacks {
+                    if !leaf_first {
+                        i.frames.reverse();
+                    }
+                    profile.add(i.frames.as_slice(), i.weight);
+                }
+                self.ship_bytes(profile.encode("samples", "count"), "pb").await;
+            }
+        }
+    }
+
+    /// Live traces emit stacks in batches, so the profile adds up over the whole round.
+    fn profile_round(&mut self, stacks: &[Stack], leaf_first: bool) {
+        if self.model.diff.is_none() {
+            return;
+        }
+        for i in stacks {
+            let leaf = if leaf_first { i.frames.first() } else { i.frames.last() };
+            if let Some(leaf) = leaf {
+                *self.round.profile.entry(leaf.clone()).or_insert(0) += i.weight;
+            }
+        }
+    }
+
+    async fn diff_round(&mut self) {
+        let functions = std::mem::take(&mut self.round.profile);
+        let diff = match &self.model.diff {
+            Some(diff) if !functions.is_empty() => diff.clone(),
+            _ => return
+        };
+        let db = match &mut self.db {
+            Some(db) => db,
+            None => return
+        };
+        let profile = FunctionProfile::new(&self.round, self.model.name.as_str(), functions);
+        let msg = match diff.against {
+            DiffAgainst::PreviousRound => DbMsg::Profile { name: self.model.name.clone(), round: None },
+            DiffAgainst::Baseline => DbMsg::Baseline(self.model.name.clone()),
+        };
+        // the current profile is stored after the lookup, so the previous round is the latest one
+        let before = db.call(msg).await
+            .map_err(|x| x.into())
+            .and_then(|x| x);
+        db.send(DbMsg::AddProfile(profile.clone())).check_error();
+        let before = match before {
+            Ok(DbReply::ProfileResult(before)) => before,
+            Ok(_) => {
+                error!("unexpected database reply to the profile of trace {}", self.model.name);
+                return;
+            }
+            Err(e) => {
+                debug!("nothing to diff round {} of trace {} against: {}", self.round.index, self.model.name, e);
+                return;
+            }
+        };
+        let mut deltas = diff_profiles(&before, &profile);
+        let regressions = deltas.iter()
+            .take_while(|x| x.delta_pct >= diff.threshold_pct)
+            .cloned()
+            .collect::<Vec<_>>();
+        for i in &regressions {
+            warn!("trace {} regressed in {}: {:.2}% -> {:.2}% of samples",
+                  self.model.name, i.function, i.before_pct, i.after_pct);
+        }
+        deltas.truncate(DIFF_REPORT_SIZE);
+        if let Some(sender) = &mut self.send_client {
+            sender.send(DiffReport {
+                trace_name: self.model.name.clone(),
+                round: self.round.index,
+                against: diff.against,
+                against_round: before.round,
+                regressions,
+                deltas,
+            }).check_error();
+        }
+    }
+
+    fn begin_round(&mut self) {
+        if let Some(pct) = self.model.limits.and_then(|x| x.max_cpu_pct) {
+            if let Err(e) = crate::utils::create_cpu_cgroup(self.model.name.as_str(), pct) {
+                warn!("cannot limit cpu usage of trace {}: {}", self.model.name, e);
+            }
+        }
+        self.round.index += 1;
+        self.round.started_at = crate::utils::unix_now();
+        self.progress.started_at.store(self.round.started_at, SeqCst);
+        self.round.exit_code = None;
+        self.round.output_bytes = 0;
+        self.round.records = 0;
+        self.round.output = None;
+        self.round.profile.clear();
+    }
+
+    /// Returns whether the round may go on.
+    fn run_hook(&mut self, stage: &str, hook: Option<Hook>) -> bool {
+        let hook = match hook {
+            Some(hook) => hook,
+            None => return true
+        };
+        let result: Result<()> = hook_command(&self.model, hook.command.as_str())
+            .args(hook.args.iter())
+            .output()
+            .map_err(|x| x.into())
+            .and_then(|x| if x.status.success() {
+                Ok(())
+            } else {
+                Err(round_failure(hook.command.as_str(), &x))
+            });
+        match result {
+            Ok(()) => true,
+            Err(e) if hook.fatal => {
+                self.report_error(format!("{} hook: {}", stage, e));
+                false
+            }
+            Err(e) => {
+                warn!("{} hook of trace {} failed: {}", stage, self.model.name, e);
+                true
+            }
+        }
+    }
+
+    /// `pending` tells whether the regular next round has already been scheduled.
+    /// Returns whether a retry of the round is on its way.
+    fn retry_round(&mut self, ctx: &Context<Self>, pending: bool) -> bool {
+        let retry = match self.model.retry {
+            Some(retry) => retry,
+            None => return false
+        };
+        if self.round.errors.is_empty() {
+            self.round.attempt = 0;
+            return false;
+        }
+        if self.round.attempt + 1 >= retry.max_attempts {
+            self.round.attempt = 0;
+            self.round.failed = true;
+            for content in std::mem::take(&mut self.round.errors) {
+                self.send_error(content);
+            }
+            return false;
+        }
+        self.round.attempt += 1;
+        self.round.errors.clear();
+        let backoff = retry.backoff(self.round.attempt);
+        warn!("round {} of trace {} failed, retrying in {}s (attempt {}/{})",
+              self.round.index, self.model.name, backoff, self.round.attempt + 1, retry.max_attempts);
+        if pending {
+            // the retry takes the place of the round that is already scheduled
+            self.round.suppressed += 1;
+        }
+        ctx.send_later(TraceEvent::Retry, Duration::from_secs(backoff));
+        true
+    }
+
+    /// Returns whether another round has been scheduled in place of the regular one.
+    fn conclude_round(&mut self, ctx: &Context<Self>, pending: bool) -> bool {
+        if self.retry_round(ctx, pending) {
+            return true;
+        }
+        if let Some(keeper) = &mut self.house_keeper {
+            keeper.send(KeeperMsg::RoundDone {
+                name: self.model.name.clone(),
+                succeeded: !self.round.failed,
+            }).check_error();
+        }
+        self.back_off(ctx, pending)
+    }
+
+    /// Local runs go for the rounds they are asked for, regardless of expiry.
+    fn expired(&self) -> bool {
+        if self.house_keeper.is_none() {
+            return false;
+        }
+        self.model.expires_after_rounds.map(|x| self.round.index >= x).unwrap_or(false)
+            || self.model.expires_at.map(|x| crate::utils::unix_now() >= x).unwrap_or(false)
+    }
+
+    /// Hands the model over to the house keeper, which parks it and stops this actor.
+    fn expire(&mut self) {
+        if let Some(keeper) = &mut self.house_keeper {
+            info!("trace {} expired after {} rounds", self.model.name, self.round.index);
+            keeper.send(KeeperMsg::Expire {
+                name: self.model.name.clone(),
+                rounds: self.round.index,
+                remove: self.model.remove_on_expiry,
+            }).check_error();
+        }
+    }
+
+    /// Stretches the schedule of a trace that keeps failing, returning whether the next round is delayed.
+    fn back_off(&mut self, ctx: &Context<Self>, pending: bool) -> bool {
+        if !std::mem::replace(&mut self.round.failed, false) {
+            self.round.failures = 0;
+            return false;
+        }
+        self.round.failures += 1;
+        let backoff = match self.model.failure_backoff {
+            Some(backoff) if self.round.failures >= backoff.after_failures => backoff,
+            _ => return false
+        };
+        let delay = backoff.delay(self.model.interval.max(1) as u64, self.round.failures);
+        warn!("trace {} failed {} rounds in a row, backing off for {}s", self.model.name, self.round.failures, delay);
+        if let Some(sender) = &mut self.send_client {
+            sender.send(TraceBackoff {
+                trace_name: self.model.name.clone(),
+                failures: self.round.failures,
+                delay_secs: delay,
+            }).check_error();
+        }
+        match (&self.model.schedule, &mut self.house_keeper) {
+            (Some(_), Some(keeper)) => keeper.send(KeeperMsg::Backoff {
+                name: self.model.name.clone(),
+                until: crate::utils::unix_now() + delay,
+            }).check_error(),
+            _ if self.one_shot => return false,
+            _ => {
+                if pending {
+                    // the backed off round takes the place of the one already scheduled
+                    self.round.suppressed += 1;
+                }
+                ctx.send_later(TraceEvent::NextRound, Duration::from_secs(delay));
+            }
+        }
+        true
+    }
+
+    /// Resolves the targets of this round, and pins them if the model asks for it.
+    fn round_pids(&mut self, absolute_path: &str) -> Result<Vec<i32>> {
+        let pids = target_pids(&self.model, absolute_path)?;
+        self.pin_round(&pids);
+        Ok(pids)
+    }
+
+    fn pin_round(&mut self, pids: &[i32]) {
+        let pinned = pin_targets(&self.model, pids);
+        self.pinned.extend(pinned);
+    }
+
+    fn remove_cgroup(&self) {
+        if self.model.limits.and_then(|x| x.max_cpu_pct).is_some() {
+            if let Err(e) = crate::utils::remove_cpu_cgroup(self.model.name.as_str()) {
+                warn!("cannot remove the cgroup of trace {}: {}", self.model.name, e);
+            }
+        }
+    }
+
+    async fn finish_round(&mut self) {
+        self.progress.started_at.store(0, SeqCst);
+        self.diff_round().await;
+        unpin_targets(std::mem::replace(&mut self.pinned, Vec::new()));
+        self.remove_cgroup();
+        let record = RunRecord {
+            trace_name: self.model.name.clone(),
+            round: self.round.index,
+            started_at: self.round.started_at,
+            ended_at: crate::utils::unix_now(),
+            exit_code: self.round.exit_code,
+            output_bytes: self.round.output_bytes,
+            records: self.round.records,
+            tool_version: self.tool_version.clone(),
+            group_round: self.round.group_round.take(),
+            failed: self.round.failed || !self.round.errors.is_empty(),
+            attempt: self.round.attempt + 1,
+            output: self.round.output.take(),
+        };
+        if let (Some(group), Some(keeper), true) = (&self.model.group, &mut self.house_keeper, record.group_round.is_some()) {
+            keeper.send(KeeperMsg::GroupDone {
+                group: group.clone(),
+                name: self.model.name.clone(),
+            }).check_error();
+        }
+        if let Some(sender) = &mut self.send_client {
+            sender.send(record.clone()).check_error();
+        }
+        if let Some(db) = &mut self.db {
+            db.send(DbMsg::AddRun(record)).check_error();
+        }
+    }
+
+    /// Counts a finished round towards the ones a local run waits for, however many files it wrote.
+    async fn count_round(&mut self) {
+        if self.house_keeper.is_some() && !self.one_shot {
+            return;
+        }
+        let handle = self.written.1.lock().await;
+        if handle.load(SeqCst) > 0 {
+            handle.fetch_sub(1, SeqCst);
+        }
+        self.written.0.notify_one();
+    }
+
+    async fn release_written(&mut self) {
+        let handle = self.written.1.lock().await;
+        handle.store(0, SeqCst);
+        self.written.0.notify_one();
+    }
+
+    async fn write_round<C: AsRef<[u8]>>(&mut self, extension: &str, content: C) -> Result<()> {
+        let compression = self.model.compression;
+        let redactor = self.redactor.clone();
+        self.write_round_with(extension, move |path| {
+            let content = redactor.redact_bytes(content.as_ref().to_vec())?;
+            write_content(path, compression, content.as_slice())
+        }).await
+    }
+
+    async fn write_round_file(&mut self, extension: &str, source: &str) -> Result<()> {
+        let compression = self.model.compression;
+        let redactor = self.redactor.clone();
+        let source = source.to_string();
+        self.write_round_with(extension, move |path| {
+            if !redactor.is_empty() {
+                // the rules match across the whole text, so the file cannot be streamed
+                let content = redactor.redact_bytes(std::fs::read(&source)?)?;
+                return write_content(path, compression, content.as_slice());
+            }
+            let input = std::fs::File::open(&source)?;
+            match compression {
+                Some(kind) => std::fs::File::create(format!("{}.{}", path, kind.extension()))
+                    .map_err(|x| x.into())
+                    .and_then(|output| kind.compress_stream(input, output)),
+                None => std::fs::File::create(path)
+                    .and_then(|mut output| std::io::copy(&mut &input, &mut output))
+                    .map_err(|x| x.into())
+            }.map(|x| x as usize)
+        }).await
+    }
+
+    /// `write` stores the round at the given path and returns the uncompressed size,
+    /// all files of a round share its index.
+    async fn write_round_with<F>(&mut self, extension: &str, write: F) -> Result<()>
+        where F: FnOnce(&str) -> Result<usize> {
+        let path = format!("{}-{}.{}", self.pattern, self.round.index, extension);
+        let result = match write(path.as_str()) {
+            Ok(size) => {
+                self.round.output_bytes += size;
+                self.round.output = Some(path.clone());
+                Ok(())
+            }
+            Err(e) => Err(e)
+        };
+        if let Some(retention) = &self.model.retention {
+            match &mut self.house_keeper {
+                Some(keeper) => keeper.send(KeeperMsg::Retain {
+                    pattern: self.pattern.clone(),
+                    retention: retention.clone(),
+                }).check_error(),
+                None => enforce_retention(self.pattern.as_str(), retention)
+            }
+        }
+        result
+    }
+
+    async fn ship_file(&mut self, path: &str, extension: &str) {
+        use async_std::io::ReadExt;
+        if self.send_client.is_none() {
+            let result = self.write_round_file(extension, path).await;
+            if let Err(e) = result {
+                self.report_error(format!("cannot read {}: {}", path, e));
+            }
+            return;
+        }
+        if !self.redactor.is_empty() {
+            match async_std::fs::read(path).await {
+                Ok(content) => self.ship_bytes(content, extension).await,
+                Err(e) => self.report_error(format!("cannot read {}: {}", path, e))
+            }
+            return;
+        }
+        let mut file = match async_std::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(e) => {
+                self.report_error(format!("cannot read {}: {}", path, e));
+                return;
+            }
+        };
+        let length = file.metadata().await.map(|x| x.len() as usize).unwrap_or(0);
+        let total = (length + ARTIFACT_CHUNK - 1) / ARTIFACT_CHUNK;
+        let file_name = match self.model.compression {
+            Some(kind) => format!("{}.{}", extension, kind.extension()),
+            None => extension.to_string()
+        };
+        let id = artifact_id(self.model.name.as_str(), &self.round, file_name.as_str());
+        // chunks are read one at a time, so large artifacts never sit in memory as a whole
+        let mut buffer = vec![0u8; ARTIFACT_CHUNK];
+        let mut index = 0;
+        let mut offset = 0;
+        loop {
+            let mut filled = 0;
+            while filled < buffer.len() {
+                match file.read(&mut buffer[filled..]).await {
+                    Ok(0) => break,
+                    Ok(read) => filled += read,
+                    Err(e) => {
+                        self.report_error(format!("cannot read {}: {}", path, e));
+                        return;
+                    }
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            // compressed frames concatenate into a valid stream
+            let chunk = match self.model.compression {
+                Some(kind) => match kind.compress(&buffer[..filled]) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        self.report_error(format!("cannot compress {}: {}", path, e));
+                        return;
+                    }
+                },
+                None => buffer[..filled].to_vec()
+            };
+            self.round.output_bytes += chunk.len();
+            self.progress.sent.fetch_add(chunk.len() as u64, SeqCst);
+            if let Some(sender) = &mut self.send_client {
+                sender.send(crate::client::ArtifactFrame(TraceArtifact {
+                    trace_name: self.model.name.clone(),
+                    file_name: file_name.clone(),
+                    chunk: index,
+                    total,
+                    content: base64::encode(&chunk),
+                    artifact_id: id.clone(),
+                    offset,
+                    length: chunk.len(),
+                    checksum: crate::utils::adler32(&chunk),
+                    last: index + 1 >= total,
+                })).check_error();
+            }
+            offset += chunk.len() as u64;
+            index += 1;
+        }
+    }
+
+    async fn ship_bytes(&mut self, content: Vec<u8>, extension: &str) {
+        // what stays local is redacted by write_round
+        let content = match (&self.send_client, self.redactor.is_empty()) {
+            (Some(_), false) => match self.redactor.redact_bytes(content) {
+                Ok(x) => x,
+                Err(e) => {
+                    self.report_error(format!("{} artifact is not shipped: {}", extension, e));
+                    return;
+                }
+            },
+            _ => content
+        };
+        match &mut self.send_client {
+            Some(sender) => {
+                let (file_name, content) = match self.model.compression {
+                    Some(kind) => match kind.compress(content.as_slice()) {
+                        Ok(x) => (format!("{}.{}", extension, kind.extension()), x),
+                        Err(e) => {
+                            error!("trace {} cannot compress artifact: {}", self.model.name, e);
+                            (extension.to_string(), content)
+                        }
+                    },
+                    None => (extension.to_string(), content)
+                };
+                self.round.output_bytes += content.len();
+                self.progress.sent.fetch_add(content.len() as u64, SeqCst);
+                let total = (content.len() + ARTIFACT_CHUNK - 1) / ARTIFACT_CHUNK;
+                let id = artifact_id(self.model.name.as_str(), &self.round, file_name.as_str());
+                for (index, chunk) in content.chunks(ARTIFACT_CHUNK).enumerate() {
+                    sender.send(crate::client::ArtifactFrame(TraceArtifact {
+                        trace_name: self.model.name.clone(),
+                        file_name: file_name.clone(),
+                        chunk: index,
+                        total,
+                        content: base64::encode(chunk),
+                        artifact_id: id.clone(),
+                        offset: (index * ARTIFACT_CHUNK) as u64,
+                        length: chunk.len(),
+                        checksum: crate::utils::adler32(chunk),
+                        last: index + 1 == total,
+                    })).check_error();
+                }
+            }
+            None => self.write_round(extension, content).await.check_error()
+        }
+    }
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct Connect {
+    trace_name: String,
+    callee: String,
+    caller: String,
+    weight: usize,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct TraceExpired {
+    trace_name: String,
+    rounds: usize,
+    removed: bool,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct TraceTriggered {
+    trace_name: String,
+    metric: String,
+    value: f64,
+    threshold: f64,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct TraceBackoff {
+    trace_name: String,
+    failures: usize,
+    delay_secs: u64,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct RoundSkipped {
+    trace_name: String,
+    time: u64,
+    reason: String,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct TraceError {
+    trace_name: String,
+    content: String,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct TraceOutput {
+    trace_name: String,
+    content: String,
+}
+
+const ARTIFACT_CHUNK: usize = 1 << 20;
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct TraceArtifact {
+    trace_name: String,
+    file_name: String,
+    chunk: usize,
+    total: usize,
+    content: String,
+    pub(crate) artifact_id: String,
+    /// byte offset of this chunk within the transferred (possibly compressed) artifact
+    pub(crate) offset: u64,
+    pub(crate) length: usize,
+    checksum: u32,
+    last: bool,
+}
+
+fn write_content(path: &str, compression: Option<CompressionKind>, content: &[u8]) -> Result<usize> {
+    match compression {
+        Some(kind) => kind.compress(content)
+            .and_then(|x| std::fs::write(format!("{}.{}", path, kind.extension()), x)
+                .map_err(|x| x.into())),
+        None => std::fs::write(path, content).map_err(|x| x.into())
+    }.map(|_| content.len())
+}
+
+fn artifact_id(name: &str, round: &RoundState, file_name: &str) -> String {
+    format!("{}-{}-{}-{}", name, round.started_at, round.index, file_name)
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct FtraceRecord {
+    trace_name: String,
+    task: String,
+    pid: i32,
+    cpu: usize,
+    timestamp: f64,
+    event: String,
+    detail: String,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct StraceSummary {
+    trace_name: String,
+    syscall: String,
+    calls: usize,
+    errors: usize,
+    seconds: f64,
+    max_seconds: Option<f64>,
+}
+
+fn parse_strace_summary(name: &str, content: &str) -> Vec<StraceSummary> {
+    content.lines()
+        .filter_map(|line| {
+            let words = line.split_ascii_whitespace().collect::<Vec<_>>();
+            // % time, seconds, usecs/call, calls, [errors,] syscall
+            let (calls, errors, syscall) = match words.len() {
+                5 => (words[3], "0", words[4]),
+                6 => (words[3], words[4], words[5]),
+                _ => return None
+            };
+            if syscall == "total" {
+                return None;
+            }
+            Some(StraceSummary {
+                trace_name: name.to_string(),
+                syscall: syscall.to_string(),
+                calls: calls.parse().ok()?,
+                errors: errors.parse().ok()?,
+                seconds: words[1].parse().ok()?,
+                max_seconds: None,
+            })
+        })
+        .collect()
+}
+
+fn parse_strace_calls(name: &str, content: &str) -> Vec<StraceSummary> {
+    let mut summary: HashMap<String, StraceSummary> = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim_start_matches("[pid").trim_start();
+        let line = line.trim_start_matches(|x: char| x.is_ascii_digit() || x == ']').trim_start();
+        if line.contains("<unfinished ...>") || line.contains("resumed>") {
+            continue;
+        }
+        let syscall = match line.find('(') {
+            Some(index) if index > 0 => &line[..index],
+            _ => continue
+        };
+        let seconds = match line.rfind('<') {
+            Some(index) if line.ends_with('>') => match line[index + 1..line.len() - 1].parse::<f64>() {
+                Ok(seconds) => seconds,
+                Err(_) => continue
+            },
+            _ => continue
+        };
+        let entry = summary.entry(syscall.to_string())
+            .or_insert_with(|| StraceSummary {
+                trace_name: name.to_string(),
+                syscall: syscall.to_string(),
+                calls: 0,
+                errors: 0,
+                seconds: 0.0,
+                max_seconds: Some(0.0),
+            });
+        entry.calls += 1;
+        entry.seconds += seconds;
+        if line.contains(" = -1 ") {
+            entry.errors += 1;
+        }
+        entry.max_seconds = entry.max_seconds.map(|x| x.max(seconds));
+    }
+    summary.into_iter().map(|(_, x)| x).collect()
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct PerfCounter {
+    trace_name: String,
+    time: f64,
+    event: String,
+    value: Option<f64>,
+    unit: String,
+}
+
+fn parse_perf_stat_line(name: &str, line: &str) -> Option<PerfCounter> {
+    let mut fields = line.split(',');
+    let time = fields.next()?.trim().parse().ok()?;
+    let value = fields.next()?.trim().parse().ok();
+    let unit = fields.next()?.trim().to_string();
+    let event = fields.next()?.trim().to_string();
+    Some(PerfCounter {
+        trace_name: name.to_string(),
+        time,
+        event,
+        value,
+        unit,
+    })
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct Stack {
+    trace_name: String,
+    frames: Vec<String>,
+    weight: usize,
+}
+
+/// Self samples of every function in a round, kept on the agent for diffs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FunctionProfile {
+    pub(crate) trace_name: String,
+    pub(crate) round: usize,
+    pub(crate) started_at: u64,
+    pub(crate) total: usize,
+    pub(crate) functions: Vec<(String, usize)>,
+}
+
+impl FunctionProfile {
+    fn new(round: &RoundState, trace_name: &str, functions: HashMap<String, usize>) -> Self {
+        let total = functions.values().sum::<usize>();
+        FunctionProfile {
+            trace_name: trace_name.to_string(),
+            round: round.index,
+            started_at: round.started_at,
+            total,
+            functions: functions.into_iter().collect(),
+        }
+    }
+
+    fn share(&self, samples: usize) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            samples as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FunctionDelta {
+    pub(crate) function: String,
+    pub(crate) before_pct: f64,
+    pub(crate) after_pct: f64,
+    pub(crate) delta_pct: f64,
+}
+
+/// Compares the share of samples of each function, the largest growth comes first.
+pub fn diff_profiles(before: &FunctionProfile, after: &FunctionProfile) -> Vec<FunctionDelta> {
+    let mut shares: HashMap<&str, (f64, f64)> = HashMap::new();
+    for (name, samples) in &before.functions {
+        shares.entry(name.as_str()).or_default().0 = before.share(*samples);
+    }
+    for (name, samples) in &after.functions {
+        shares.entry(name.as_str()).or_default().1 = after.share(*samples);
+    }
+    let mut deltas = shares.into_iter()
+        .map(|(function, (before_pct, after_pct))| FunctionDelta {
+            function: function.to_string(),
+            before_pct,
+            after_pct,
+            delta_pct: after_pct - before_pct,
+        })
+        .collect::<Vec<_>>();
+    deltas.sort_by(|x, y| y.delta_pct.partial_cmp(&x.delta_pct).unwrap_or(std::cmp::Ordering::Equal));
+    deltas
+}
+
+/// functions beyond this are left out of the reports sent upstream
+const DIFF_REPORT_SIZE: usize = 32;
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct DiffReport {
+    trace_name: String,
+    round: usize,
+    against: DiffAgainst,
+    against_round: usize,
+    regressions: Vec<FunctionDelta>,
+    deltas: Vec<FunctionDelta>,
+}
+
+#[derive(Default)]
+struct PerfScriptParser {
+    stacks: HashMap<Vec<String>, usize>,
+    frames: Vec<String>,
+    in_sample: bool,
+}
+
+impl PerfScriptParser {
+    fn feed(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            if !self.frames.is_empty() {
+                *self.stacks.entry(std::mem::replace(&mut self.frames, Vec::new())).or_insert(0) += 1;
+            }
+            self.in_sample = false;
+        } else if !self.in_sample {
+            self.in_sample = true;
+        } else {
+            let mut words = line.split_ascii_whitespace();
+            words.next();
+            if let Some(symbol) = words.next()
+                .filter(|x| *x != "[unknown]")
+                .map(|x| x.split('+').next().unwrap_or(x)) {
+                self.frames.push(symbol.to_string());
+            }
+        }
+    }
+
+    fn finish(mut self, name: &str) -> Vec<Stack> {
+        self.feed("");
+        self.stacks.into_iter()
+            .map(|(frames, weight)| Stack {
+                trace_name: name.to_string(),
+                frames,
+                weight,
+            })
+            .collect()
+    }
+}
+
+fn parse_perf_script(name: &str, content: &str) -> Vec<Stack> {
+    let mut parser = PerfScriptParser::default();
+    for line in content.lines() {
+        parser.feed(line);
+    }
+    parser.finish(name)
+}
+
+fn parse_folded(name: &str, content: &str, depth: usize) -> Vec<Stack> {
+    content.lines()
+        .filter_map(|line| {
+            let split = line.trim().rfind(' ')?;
+            let weight = line.trim()[split + 1..].parse().ok()?;
+            let mut frames = line.trim()[..split].split(';')
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            if depth > 0 && frames.len() > depth {
+                frames.drain(..frames.len() - depth);
+            }
+            Some(Stack {
+                trace_name: name.to_string(),
+                frames,
+                weight,
+            })
+        })
+        .collect()
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct SchedLatency {
+    trace_name: String,
+    task: String,
+    runtime_ms: f64,
+    switches: usize,
+    avg_delay_ms: f64,
+    max_delay_ms: f64,
+}
+
+fn parse_sched_latency(name: &str, content: &str) -> Vec<SchedLatency> {
+    content.lines()
+        .filter_map(|line| {
+            // Task | Runtime ms | Switches | Avg delay ms | Max delay ms | ...
+            let columns = line.split('|').map(|x| x.trim()).collect::<Vec<_>>();
+            if columns.len() < 5 {
+                return None;
+            }
+            let number = |x: &str| x.split_ascii_whitespace()
+                .find_map(|x| x.trim_start_matches("avg:").trim_start_matches("max:").parse::<f64>().ok());
+            Some(SchedLatency {
+                trace_name: name.to_string(),
+                task: columns[0].to_string(),
+                runtime_ms: number(columns[1])?,
+                switches: columns[2].parse().ok()?,
+                avg_delay_ms: number(columns[3])?,
+                max_delay_ms: number(columns[4])?,
+            })
+        })
+        .collect()
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct MemAccess {
+    trace_name: String,
+    overhead: f64,
+    samples: usize,
+    access: String,
+    symbol: String,
+    data_symbol: String,
+}
+
+fn parse_mem_report(name: &str, content: &str) -> Vec<MemAccess> {
+    content.lines()
+        .filter(|x| !x.trim().starts_with('#') && !x.trim().is_empty())
+        .filter_map(|line| {
+            let columns = line.split(',').map(|x| x.trim()).collect::<Vec<_>>();
+            if columns.len() < 5 {
+                return None;
+            }
+            Some(MemAccess {
+                trace_name: name.to_string(),
+                overhead: columns[0].trim_end_matches('%').parse().ok()?,
+                samples: columns[1].parse().ok()?,
+                access: columns[2].to_string(),
+                symbol: columns[3].to_string(),
+                data_symbol: columns[4].to_string(),
+            })
+        })
+        .collect()
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct CacheLine {
+    trace_name: String,
+    index: usize,
+    address: String,
+    fields: Vec<String>,
+}
+
+fn parse_c2c_report(name: &str, content: &str) -> Vec<CacheLine> {
+    content.lines()
+        .skip_while(|x| !x.contains("Shared Data Cache Line Table"))
+        .skip(1)
+        .take_while(|x| !x.contains("Shared Cache Line Distribution"))
+        .filter_map(|line| {
+            let mut words = line.split_ascii_whitespace();
+            let index = words.next()?.parse().ok()?;
+            let address = words.next().filter(|x| x.starts_with("0x"))?.to_string();
+            Some(CacheLine {
+                trace_name: name.to_string(),
+                index,
+                address,
+                fields: words.map(|x| x.to_string()).collect(),
+            })
+        })
+        .collect()
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct EnergySample {
+    trace_name: String,
+    domain: String,
+    joules: f64,
+    watts: f64,
+    seconds: f64,
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct GpuMetric {
+    trace_name: String,
+    device: String,
+    metric: String,
+    value: Option<f64>,
+}
+
+fn parse_dmon(name: &str, content: &str) -> Vec<GpuMetric> {
+    let mut header: Vec<&str> = Vec::new();
+    let mut result = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('#') {
+            if header.is_empty() {
+                header = line.trim_start_matches('#').split_whitespace().collect();
+            }
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let device = match fields.next() {
+            Some(device) => device,
+            None => continue
+        };
+        for (metric, value) in header.iter().skip(1).zip(fields) {
+            result.push(GpuMetric {
+                trace_name: name.to_string(),
+                device: device.to_string(),
+                metric: metric.to_string(),
+                value: value.parse().ok(),
+            });
+        }
+    }
+    result
+}
+
+#[xactor::message(result = "()")]
+#[derive(Serialize, Deserialize, TypeName)]
+pub struct CapturedOutput {
+    trace_name: String,
+    stream: String,
+    content: String,
+    truncated: bool,
+}
+
+#[derive(Default)]
+struct Captured {
+    status: Option<i32>,
+    outputs: Vec<CapturedOutput>,
+}
+
+fn capture_output(model: &TraceModel, output: &std::process::Output) -> Captured {
+    let status = output.status.code();
+    let capture = match &model.capture {
+        Some(capture) => capture,
+        None => return Captured { status, outputs: Vec::new() }
+    };
+    let mut streams = vec![("stderr", &output.stderr)];
+    if capture.stdout {
+        streams.push(("stdout", &output.stdout));
+    }
+    let outputs = streams.into_iter()
+        .filter(|(_, content)| !content.is_empty())
+        .map(|(stream, content)| {
+            let truncated = content.len() > capture.max_bytes;
+            let content = match (truncated, capture.tail) {
+                (false, _) => content.as_slice(),
+                (true, true) => &content[content.len() - capture.max_bytes..],
+                (true, false) => &content[..capture.max_bytes],
+            };
+            CapturedOutput {
+                trace_name: model.name.clone(),
+                stream: stream.to_string(),
+                content: String::from_utf8_lossy(content).to_string(),
+                truncated,
+            }
+        })
+        .collect();
+    Captured { status, outputs }
+}
+
+const RAPL_ROOT: &str = "/sys/class/powercap";
+
+fn read_u64(path: std::path::PathBuf) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn rapl_domains(domains: &[String]) -> Result<Vec<(String, std::path::PathBuf)>> {
+    let mut result = Vec::new();
+    for entry in std::fs::read_dir(RAPL_ROOT)?.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = match std::fs::read_to_string(path.join("name")) {
+            Ok(name) => name.trim().to_string(),
+            Err(_) => continue
+        };
+        if !path.join("energy_uj").exists() {
+            continue;
+        }
+        let label = format!("{}:{}", entry.file_name().to_string_lossy(), name);
+        if domains.is_empty() || domains.iter().any(|x| *x == name || *x == label) {
+            result.push((label, path));
+        }
+    }
+    if result.is_empty() {
+        Err(anyhow!("no matching RAPL domain under {}", RAPL_ROOT))
+    } else {
+        Ok(result)
+    }
+}
+
+fn parse_ftrace_line(name: &str, line: &str) -> Option<FtraceRecord> {
+    let open = line.find('[')?;
+    let close = open + line[open..].find(']')?;
+    let task = line[..open].trim();
+    let dash = task.rfind('-')?;
+    let pid = task[dash + 1..].parse().ok()?;
+    let cpu = line[open + 1..close].trim().parse().ok()?;
+    let mut rest = line[close + 1..].splitn(3, ": ");
+    let timestamp = rest.next()?
+        .split_ascii_whitespace()
+        .last()?
+        .parse()
+        .ok()?;
+    let event = rest.next()?.trim().to_string();
+    let detail = rest.next().unwrap_or("").trim().to_string();
+    Some(FtraceRecord {
+        trace_name: name.to_string(),
+        task: task[..dash].to_string(),
+        pid,
+        cpu,
+        timestamp,
+        event,
+        detail,
+    })
+}
+
+impl TraceActor {
+    async fn handle_stap(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            crate::database::TraceContent::SystemTap {
+                envs,
+                args,
+                ..
+            } => {
+                // the pids of a cgroup change over time, so its guard is written anew for every round
+                let stale = match &self.model.target {
+                    Some(TraceTarget::Cgroup(_)) => true,
+                    _ => false
+                };
+                if self.file.is_none() || stale
+                {
+                    match to_tempfile(&self.model) {
+                        Ok(e) => { self.file.replace(e); }
+                        Err(e) => {
+                            error!("trace {} cannot create script file with error {}, going to suicide"
+                                   , self.model.name, e);
+                            self.commit_suicide().await;
+                            return;
+                        }
+                    }
+                }
+                let args = match stap_args(&self.model, args) {
+                    Ok(args) => args,
+                    Err(e) => {
+                        error!("trace {} cannot resolve run_as with error {}, going to suicide"
+                               , self.model.name, e);
+                        self.commit_suicide().await;
+                        return;
+                    }
+                };
+                let file = self.file.as_ref().unwrap();
+                match deadline_command(&self.model, "stap")
+                    .args(target_args(&self.model, "-x"))
+                    .arg(file.path())
+                    .envs(envs.clone().into_iter())
+                    .args(args.iter())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .map(|mut x| (x.stdout.take().unwrap(), x.stderr.take().unwrap(), x)) {
+                    Err(e) => {
+                        error!("trace {} cannot create script file with error {}, going to suicide"
+                               , self.model.name, e);
+                        self.commit_suicide().await;
+                        return;
+                    }
+                    Ok((out, err, child)) => {
+                        let mut callee = None;
+                        let err_name = self.model.name.clone();
+                        let mut err_client = self.send_client.clone();
+                        let err_handle = async_std::task::spawn(async move {
+                            for i in std::io::BufReader::new(err).lines() {
+                                if let Ok(c) = i {
+                                    error!("trace {} error: {}", err_name, c);
+                                    if let Some(err_client) = &mut err_client {
+                                        err_client.send(TraceError {
+                                            trace_name: err_name.clone(),
+                                            content: c,
+                                        }).check_error();
+                                    }
+                                }
+                            }
+                        });
+                        for i in std::io::BufReader::new(out).lines() {
+                            if let Ok(line) = i {
+                                if let Some(t) = callee.take() {
+                                    if line.contains(" : ") {
+                                        let mut split = line.split(" : ");
+                                        split.next();
+                                        if let Some(e) = split.next()
+                                            .and_then(|x| x.split("+")
+                                                .next())
+                                            .filter(|x| !x.starts_with("0x")) {
+                                            if let Some(send_client) = &mut self.send_client {
+                                                send_client.send(Connect {
+                                                    trace_name: self.model.name.clone(),
+                                                    callee: t,
+                                                    caller: String::from(e),
+                                                    weight: 1,
+                                                })
+                                                    .map_err(|x| x.into())
+                                                    .check_error()
+                                            }
+                                        }
+                                    }
+                                } else {
+                                    if line.starts_with("probe:") {
+                                        let mut iter = line.split_ascii_whitespace();
+                                        iter.next();
+                                        callee = iter.next().map(|x| String::from(x))
+                                    }
+                                }
+                            } else {
+                                callee = None;
+                            }
+                        }
+                        err_handle.await;
+                        self.reap("stap", child);
+                    }
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_bpftrace(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            crate::database::TraceContent::BpfTrace {
+                script,
+                args,
+                envs,
+            } => {
+                let mut command = deadline_command(&self.model, "bpftrace");
+                command.args(target_args(&self.model, "-p"));
+                if script.contains('\n') {
+                    if self.file.is_none()
+                    {
+                        match to_tempfile(&self.model) {
+                            Ok(e) => { self.file.replace(e); }
+                            Err(e) => {
+                                error!("trace {} cannot create script file with error {}, going to suicide"
+                                       , self.model.name, e);
+                                self.commit_suicide().await;
+                                return;
+                            }
+                        }
+                    }
+                    command.arg(self.file.as_ref().unwrap().path());
+                } else {
+                    command.arg("-e").arg(to_bpf_program(script, self.model.lasting));
+                }
+                match command
+                    .envs(envs.clone().into_iter())
+                    .args(args.iter())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .map(|mut x| (x.stdout.take().unwrap(), x.stderr.take().unwrap(), x)) {
+                    Err(e) => {
+                        error!("trace {} cannot start bpftrace with error {}, going to suicide"
+                               , self.model.name, e);
+                        self.commit_suicide().await;
+                        return;
+                    }
+                    Ok((out, err, child)) => {
+                        let err_name = self.model.name.clone();
+                        let mut err_client = self.send_client.clone();
+                        let err_handle = async_std::task::spawn(async move {
+                            for i in std::io::BufReader::new(err).lines() {
+                                if let Ok(c) = i {
+                                    error!("trace {} error: {}", err_name, c);
+                                    if let Some(err_client) = &mut err_client {
+                                        err_client.send(TraceError {
+                                            trace_name: err_name.clone(),
+                                            content: c,
+                                        }).check_error();
+                                    }
+                                }
+                            }
+                        });
+                        let mut data = Vec::new();
+                        for i in std::io::BufReader::new(out).lines() {
+                            if let Ok(line) = i {
+                                if let Some(send_client) = &mut self.send_client {
+                                    send_client.send(TraceOutput {
+                                        trace_name: self.model.name.clone(),
+                                        content: line,
+                                    }).check_error();
+                                } else {
+                                    data.push(line);
+                                }
+                            }
+                        }
+                        err_handle.await;
+                        self.reap("bpftrace", child);
+                        if self.send_client.is_none() {
+                            self.write_round("txt", data.join("\n")).await.check_error();
+                        }
+                    }
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_ftrace(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Ftrace { events, args } => {
+                let filename = format!("/tmp/girasol-ftrace-{}.dat", self.model.name);
+                let mut captured = Captured::default();
+                let result = ftrace_command(&self.model, events, args, filename.as_str())
+                    .output()
+                    .map(|x| { captured = capture_output(&self.model, &x); x })
+                    .map_err(|x| x.into())
+                    .and_then(|x| {
+                        if timed_out(x.status) {
+                            Ok(())
+                        } else {
+                            Err(round_failure("trace-cmd record", &x))
+                        }
+                    })
+                    .and_then(|_| std::process::Command::new("trace-cmd")
+                        .arg("report")
+                        .arg("-i")
+                        .arg(&filename)
+                        .output()
+                        .map_err(|x| x.into()));
+                self.ship_captured(captured);
+                match result {
+                    Ok(output) => {
+                        let name = self.model.name.clone();
+                        let records = output.stdout.lines()
+                            .filter_map(Result::ok)
+                            .filter_map(|x| parse_ftrace_line(name.as_str(), x.as_str()))
+                            .collect();
+                        self.emit_records::<FtraceRecord>(records).await;
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_strace(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Strace { process, syscalls, follow_forks, summary_only, args } => {
+                let filename = format!("/tmp/girasol-strace-{}.txt", self.model.name);
+                let summary_only = *summary_only;
+                let mut captured = Captured::default();
+                let result = self.round_pids(process.as_str())
+                    .and_then(|pids| if pids.is_empty() {
+                        Err(anyhow!("no running process of {}", process))
+                    } else {
+                        Ok(pids)
+                    })
+                    .and_then(|pids| strace_command(&self.model, pids.as_slice(), syscalls, *follow_forks,
+                                                    summary_only, args, filename.as_str())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()))
+                    .and_then(|x| if timed_out(x.status) {
+                        std::fs::read_to_string(&filename).map_err(|x| x.into())
+                    } else {
+                        Err(round_failure("strace", &x))
+                    });
+                self.ship_captured(captured);
+                match result {
+                    Ok(content) => {
+                        let records = if summary_only {
+                            parse_strace_summary(self.model.name.as_str(), content.as_str())
+                        } else {
+                            parse_strace_calls(self.model.name.as_str(), content.as_str())
+                        };
+                        self.emit_records(records).await;
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_perf_stat(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::PerfStat { events, interval_ms } => {
+                match perf_stat_command(&self.model, events, *interval_ms)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .spawn() {
+                    Ok(mut child) => {
+                        let mut data = Vec::new();
+                        for i in std::io::BufReader::new(child.stderr.take().unwrap()).lines() {
+                            if let Ok(line) = i {
+                                match parse_perf_stat_line(self.model.name.as_str(), line.as_str()) {
+                                    Some(counter) => if let Some(sender) = &mut self.send_client {
+                                        sender.send(counter).check_error();
+                                    } else {
+                                        data.push(counter);
+                                    },
+                                    None if !line.trim().is_empty() && !line.starts_with('#') =>
+                                        self.report_error(line),
+                                    None => ()
+                                }
+                            }
+                        }
+                        child.wait().map_err(|x| x.into()).check_error();
+                        if self.send_client.is_none() {
+                            self.emit_records(data).await;
+                        }
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn report_stacks(&mut self, filename: &str) {
+        if self.model.output_format == OutputFormat::Folded {
+            match fold_perf_script(&self.model, filename) {
+                Ok(folded) => self.ship_bytes(folded, "folded").await,
+                Err(e) => self.report_error(e.to_string())
+            }
+            return;
+        }
+        match perf_post_command(&self.model)
+            .arg("script")
+            .arg("-i")
+            .arg(filename)
+            .arg("-F")
+            .arg("comm,tid,ip,sym")
+            .stdout(Stdio::piped())
+            .spawn() {
+            Ok(mut child) => {
+                // only the aggregated stacks are kept, not the whole script output
+                let mut parser = PerfScriptParser::default();
+                let mut reader = std::io::BufReader::new(child.stdout.take().unwrap());
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    match reader.read_until(b'\n', &mut line) {
+                        Ok(0) => break,
+                        Ok(_) => parser.feed(String::from_utf8_lossy(&line).as_ref()),
+                        Err(e) => {
+                            warn!("trace {} cannot read perf script output: {}", self.model.name, e);
+                            break;
+                        }
+                    }
+                }
+                child.wait().map_err(|x| x.into()).check_error();
+                let stacks = parser.finish(self.model.name.as_str());
+                self.emit_stacks(stacks, true).await;
+            }
+            Err(e) => self.report_error(e.to_string())
+        }
+    }
+    async fn handle_offcpu(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::OffCpu { min_block_us, stack_depth } => {
+                let stack_depth = *stack_depth;
+                match offcpu_command(&self.model, *min_block_us)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn() {
+                    Ok(mut child) => {
+                        let err_handle = self.forward_stderr(child.stderr.take().unwrap());
+                        let mut content = String::new();
+                        std::io::Read::read_to_string(&mut child.stdout.take().unwrap(), &mut content)
+                            .map_err(|x| x.into())
+                            .check_error();
+                        child.wait().map_err(|x| x.into()).check_error();
+                        err_handle.await;
+                        let stacks = parse_folded(self.model.name.as_str(), content.as_str(), stack_depth);
+                        self.emit_stacks(stacks, false).await;
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_intel_pt(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::IntelPt { absolute_path, snapshot, size_limit_mb, itrace, additional_args } => {
+                let filename = format!("/tmp/girasol-pt-{}.data", self.model.name);
+                let itrace = itrace.clone();
+                let mut captured = Captured::default();
+                let result = self.round_pids(absolute_path.as_str())
+                    .and_then(|pids| if pids.is_empty() {
+                        Err(anyhow!("no running process of {}", absolute_path))
+                    } else {
+                        Ok(pids.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(","))
+                    })
+                    .and_then(|pids| intel_pt_command(&self.model, pids.as_str(), *snapshot, *size_limit_mb,
+                                                      additional_args, filename.as_str())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()))
+                    .and_then(|x| if timed_out(x.status) {
+                        Ok(())
+                    } else {
+                        Err(round_failure("perf record", &x))
+                    });
+                self.ship_captured(captured);
+                match (result, itrace) {
+                    (Err(e), _) => self.report_error(e.to_string()),
+                    (Ok(_), None) => self.ship_file(filename.as_str(), "data").await,
+                    (Ok(_), Some(itrace)) => {
+                        self.symbolize(filename.as_str());
+                        let decoded = format!("/tmp/girasol-pt-{}.txt", self.model.name);
+                        match std::fs::File::create(&decoded)
+                            .and_then(|file| perf_post_command(&self.model)
+                                .arg("script")
+                                .arg("-i")
+                                .arg(&filename)
+                                .arg(format!("--itrace={}", itrace))
+                                .stdout(file)
+                                .status()) {
+                            Ok(status) if !status.success() =>
+                                self.report_error(format!("perf script cannot decode the trace, it exited with {}", status)),
+                            Ok(_) if self.model.output_format == OutputFormat::Folded => {
+                                let folded = std::fs::File::open(&decoded)
+                                    .map_err(|x| x.into())
+                                    .and_then(|x| fold_perf_output(std::io::BufReader::new(x)));
+                                match folded {
+                                    Ok(folded) => self.ship_bytes(folded, "folded").await,
+                                    Err(e) => self.report_error(e.to_string())
+                                }
+                            }
+                            Ok(_) => self.ship_file(decoded.as_str(), "txt").await,
+                            Err(e) => self.report_error(e.to_string())
+                        }
+                        std::fs::remove_file(&decoded).map_err(|x| x.into()).check_error();
+                    }
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_callgrind(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Callgrind { program, args, envs } => {
+                let filename = format!("/tmp/girasol-callgrind-{}.out", self.model.name);
+                match callgrind_command(&self.model, program, args, envs, filename.as_str())
+                    .and_then(|mut x| x.stdout(Stdio::null())
+                        .stderr(Stdio::piped())
+                        .spawn()
+                        .map_err(|x| x.into())) {
+                    Ok(mut child) => {
+                        let err_handle = self.forward_stderr(child.stderr.take().unwrap());
+                        child.wait().map_err(|x| x.into()).check_error();
+                        err_handle.await;
+                        self.ship_file(filename.as_str(), "callgrind").await;
+                        std::fs::remove_file(&filename).ok();
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_custom(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Custom { command, args, output_path, parser } => {
+                let output_path = output_path.clone();
+                let parser = *parser;
+                let mut captured = Captured::default();
+                let launched = std::time::SystemTime::now();
+                let result = target_command(&self.model, command.as_str())
+                    .args(args.iter())
+                    .output()
+                    .map(|x| { captured = capture_output(&self.model, &x); x })
+                    .map_err(|x| x.into())
+                    .and_then(|x| if timed_out(x.status) {
+                        Ok(x.stdout)
+                    } else {
+                        Err(round_failure(command.as_str(), &x))
+                    })
+                    .and_then(|stdout| match &output_path {
+                        // the file belongs to the user and is kept, so one left from an earlier round is refused
+                        Some(path) => match std::fs::metadata(path).and_then(|x| x.modified()) {
+                            Ok(modified) if modified < launched =>
+                                Err(anyhow!("{} was not written by {}", path, command)),
+                            _ => std::fs::read(path).map_err(|x| x.into())
+                        },
+                        None => Ok(stdout)
+                    });
+                self.ship_captured(captured);
+                match result {
+                    Ok(content) => match parser {
+                        CustomParser::Raw => self.ship_bytes(content, "out").await,
+                        CustomParser::Lines => {
+                            let name = self.model.name.clone();
+                            let lines = String::from_utf8_lossy(&content)
+                                .lines()
+                                .map(|x| TraceOutput {
+                                    trace_name: name.clone(),
+                                    content: x.to_string(),
+                                })
+                                .collect();
+                            self.emit_records::<TraceOutput>(lines).await;
+                        }
+                        CustomParser::Folded => {
+                            let stacks = parse_folded(self.model.name.as_str(),
+                                                      String::from_utf8_lossy(&content).as_ref(), 0);
+                            self.emit_stacks(stacks, false).await;
+                        }
+                    },
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_sched_latency(&mut self, ctx: &Context<Self>) {
+        let filename = format!("/tmp/girasol-sched-{}.data", self.model.name);
+        let mut captured = Captured::default();
+        let result = sched_record_command(&self.model, filename.as_str())
+            .output()
+            .map(|x| { captured = capture_output(&self.model, &x); x })
+            .map_err(|x| x.into())
+            .and_then(|x| if timed_out(x.status) {
+                Ok(())
+            } else {
+                Err(round_failure("perf sched record", &x))
+            })
+            .and_then(|_| std::process::Command::new("perf")
+                .arg("sched")
+                .arg("latency")
+                .arg("-i")
+                .arg(&filename)
+                .output()
+                .map_err(|x| x.into()));
+        self.ship_captured(captured);
+        match result {
+            Ok(output) => {
+                let records = parse_sched_latency(self.model.name.as_str(),
+                                                  String::from_utf8_lossy(&output.stdout).as_ref());
+                self.emit_records(records).await;
+            }
+            Err(e) => self.report_error(e.to_string())
+        }
+        std::fs::remove_file(&filename).ok();
+        self.schedule_next(ctx);
+    }
+    async fn handle_perf_mem(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::PerfMem { absolute_path, mode, additional_args } => {
+                let filename = format!("/tmp/girasol-mem-{}.data", self.model.name);
+                let mut captured = Captured::default();
+                let result = self.round_pids(absolute_path.as_str())
+                    .and_then(|pids| if pids.is_empty() {
+                        Err(anyhow!("no running process of {}", absolute_path))
+                    } else {
+                        Ok(pids.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(","))
+                    })
+                    .and_then(|pids| perf_mem_command(&self.model, pids.as_str(), *mode, additional_args,
+                                                      filename.as_str())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()))
+                    .and_then(|x| if timed_out(x.status) {
+                        Ok(())
+                    } else {
+                        Err(round_failure("perf mem record", &x))
+                    })
+                    .and_then(|_| {
+                        self.symbolize(filename.as_str());
+                        perf_post_command(&self.model)
+                            .arg("mem")
+                            .arg("report")
+                            .arg("-i")
+                            .arg(&filename)
+                            .arg("--stdio")
+                            .arg("-t")
+                            .arg(",")
+                            .arg("--sort=mem,sym,symbol_daddr")
+                            .output()
+                            .map_err(|x| x.into())
+                    });
+                self.ship_captured(captured);
+                match result {
+                    Ok(output) => {
+                        let records = parse_mem_report(self.model.name.as_str(),
+                                                       String::from_utf8_lossy(&output.stdout).as_ref());
+                        self.emit_records(records).await;
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_sampler(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Sampler { tool, process_name, format, rate } => {
+                let filename = format!("/tmp/girasol-sampler-{}.{}", self.model.name, format.extension());
+                let extension = format.extension();
+                let mut captured = Captured::default();
+                let result = sampler_pid(&self.model, process_name.as_str())
+                    .map(|pid| { self.pin_round(&[pid]); pid })
+                    .and_then(|pid| sampler_command(&self.model, *tool, pid, *format, *rate, filename.as_str())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()))
+                    .and_then(|x| if x.status.success() {
+                        Ok(())
+                    } else {
+                        Err(round_failure("sampler", &x))
+                    });
+                self.ship_captured(captured);
+                match result {
+                    Ok(_) => self.ship_file(filename.as_str(), extension).await,
+                    Err(e) => self.report_error(e.to_string())
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_async_profiler(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::AsyncProfiler { profiler_path, process_name, event, format } => {
+                let format = *format;
+                let filename = format!("/tmp/girasol-async-{}.{}", self.model.name, format.as_arg());
+                let mut captured = Captured::default();
+                let result = sampler_pid(&self.model, process_name.as_str())
+                    .map(|pid| { self.pin_round(&[pid]); pid })
+                    .and_then(|pid| async_profiler_command(&self.model, profiler_path.as_str(), pid, *event,
+                                                           format, filename.as_str())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()))
+                    .and_then(|x| if x.status.success() {
+                        Ok(())
+                    } else {
+                        Err(round_failure("async-profiler", &x))
+                    });
+                self.ship_captured(captured);
+                match (result, format) {
+                    (Err(e), _) => self.report_error(e.to_string()),
+                    (Ok(_), ProfilerFormat::Jfr) => self.ship_file(filename.as_str(), "jfr").await,
+                    (Ok(_), ProfilerFormat::Collapsed) => match std::fs::read_to_string(&filename) {
+                        Ok(content) => {
+                            let stacks = parse_folded(self.model.name.as_str(), content.as_str(), 0);
+                            self.emit_stacks(stacks, false).await;
+                        }
+                        Err(e) => self.report_error(e.to_string())
+                    }
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_pcap(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Pcap { interface, filter, snap_len, max_bytes_per_round } => {
+                let filename = format!("/tmp/girasol-pcap-{}.pcap", self.model.name);
+                let max_bytes = *max_bytes_per_round;
+                match pcap_command(interface, filter, *snap_len, filename.as_str())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn() {
+                    Ok(mut child) => {
+                        let deadline = std::time::Instant::now() + Duration::from_secs(self.model.lasting as u64);
+                        while std::time::Instant::now() < deadline {
+                            let size = std::fs::metadata(&filename).map(|x| x.len()).unwrap_or(0);
+                            if max_bytes.map(|x| size >= x).unwrap_or(false) {
+                                warn!("trace {} reached {} bytes, stopping capture early", self.model.name, size);
+                                break;
+                            }
+                            if let Ok(Some(_)) = child.try_wait() {
+                                break;
+                            }
+                            async_std::task::sleep(Duration::from_millis(200)).await;
+                        }
+                        crate::utils::stop_process(child.id(), false, crate::utils::Stop::Interrupt).ok();
+                        child.wait().map_err(|x| x.into()).check_error();
+                        match std::fs::read(&filename) {
+                            Ok(content) if self.model.compression.is_none() => {
+                                match CompressionKind::Zstd(3).compress(content.as_slice()) {
+                                    Ok(content) => self.ship_bytes(content, "pcap.zst").await,
+                                    Err(e) => self.report_error(e.to_string())
+                                }
+                            }
+                            Ok(content) => self.ship_bytes(content, "pcap").await,
+                            Err(e) => self.report_error(e.to_string())
+                        }
+                        std::fs::remove_file(&filename).ok();
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_perf_c2c(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::PerfC2C { absolute_path, additional_args } => {
+                let filename = format!("/tmp/girasol-c2c-{}.data", self.model.name);
+                let mut captured = Captured::default();
+                let result = self.round_pids(absolute_path.as_str())
+                    .and_then(|pids| if pids.is_empty() {
+                        Err(anyhow!("no running process of {}", absolute_path))
+                    } else {
+                        Ok(pids.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(","))
+                    })
+                    .and_then(|pids| perf_c2c_command(&self.model, pids.as_str(), additional_args,
+                                                      filename.as_str())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()))
+                    .and_then(|x| if timed_out(x.status) {
+                        Ok(())
+                    } else {
+                        Err(round_failure("perf c2c record", &x))
+                    })
+                    .and_then(|_| {
+                        self.symbolize(filename.as_str());
+                        perf_post_command(&self.model)
+                            .arg("c2c")
+                            .arg("report")
+                            .arg("-i")
+                            .arg(&filename)
+                            .arg("--stdio")
+                            .output()
+                            .map_err(|x| x.into())
+                    });
+                self.ship_captured(captured);
+                match result {
+                    Ok(output) => {
+                        let records = parse_c2c_report(self.model.name.as_str(),
+                                                       String::from_utf8_lossy(&output.stdout).as_ref());
+                        self.emit_records(records).await;
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
         }
-        crate::database::TraceContent::PerfBranch { .. } => {
-            Err(anyhow!("perf based trace cannot be translated into temp files"))
+    }
+    async fn handle_energy(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Energy { domains } => {
+                match rapl_domains(domains) {
+                    Ok(domains) => {
+                        let start = std::time::Instant::now();
+                        let before = domains.iter()
+                            .map(|(_, path)| read_u64(path.join("energy_uj")))
+                            .collect::<Vec<_>>();
+                        async_std::task::sleep(Duration::from_secs(self.model.lasting as u64)).await;
+                        let seconds = start.elapsed().as_secs_f64();
+                        let mut samples = Vec::new();
+                        for ((domain, path), before) in domains.into_iter().zip(before) {
+                            let (before, after) = match (before, read_u64(path.join("energy_uj"))) {
+                                (Some(before), Some(after)) => (before, after),
+                                _ => {
+                                    warn!("trace {} cannot read energy of {}", self.model.name, domain);
+                                    continue;
+                                }
+                            };
+                            let delta = if after >= before {
+                                Some(after - before)
+                            } else {
+                                // the counter wrapped around, which only adds up with a known range
+                                read_u64(path.join("max_energy_range_uj"))
+                                    .and_then(|max| max.checked_sub(before))
+                                    .and_then(|x| x.checked_add(after))
+                            };
+                            let delta = match delta {
+                                Some(delta) => delta,
+                                None => {
+                                    self.report_error(format!("energy counter of {} wrapped around an unknown range", domain));
+                                    continue;
+                                }
+                            };
+                            let joules = delta as f64 / 1e6;
+                            samples.push(EnergySample {
+                                trace_name: self.model.name.clone(),
+                                domain,
+                                joules,
+                                watts: if seconds > 0.0 { joules / seconds } else { 0.0 },
+                                seconds,
+                            });
+                        }
+                        self.emit_records(samples).await;
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
         }
     }
-}
-
-pub struct HouseKeeper {
-    pub(crate) running_pids: Arc<crossbeam_skiplist::SkipSet<i32>>,
-    pub(crate) send_client: Addr<crate::client::SendClient>,
-    pub(crate) running_trace: HashMap<String, Addr<TraceActor>>,
-}
-
-pub struct TraceActor {
-    pub(crate) running_pids: Arc<crossbeam_skiplist::SkipSet<i32>>,
-    pub(crate) local_pids: crossbeam_skiplist::SkipSet<i32>,
-    pub(crate) house_keeper: Option<Addr<HouseKeeper>>,
-    pub(crate) send_client: Option<Addr<crate::client::SendClient>>,
-    pub(crate) model: TraceModel,
-    pub(crate) file: Option<NamedTempFile>,
-    pub(crate) child: Option<std::process::Child>,
-    pub(crate) written: Arc<(async_std::sync::Condvar, async_std::sync::Mutex<AtomicUsize>)>,
-    pub(crate) pattern: String,
-}
-
-#[xactor::message(result = "()")]
-pub enum TraceEvent {
-    NextRound,
-    PerfEnding,
-}
-
-#[xactor::message(result = "()")]
-pub enum KeeperMsg {
-    Unregister(String),
-    StartAll(Vec<TraceModel>),
-    Start(TraceModel),
-    StopAll,
-}
-
-#[xactor::message(result = "Vec<String>")]
-pub struct AllRunning;
-
-#[async_trait::async_trait]
-impl Actor for TraceActor {
-    async fn started(&mut self, ctx: &Context<Self>) {
-        log::debug!("starting next round info");
-        if let Err(e) = ctx.address().send(TraceEvent::NextRound) {
-            error!("trace {} cannot start the event with err: {}, going to suicide!", self.model.name, e);
-            ctx.stop(None);
+    async fn handle_gpu(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Gpu { tool, devices, metrics, command } => {
+                let tool = *tool;
+                let filename = format!("/tmp/girasol-rocprof-{}.csv", self.model.name);
+                let mut captured = Captured::default();
+                let output = gpu_command(&self.model, tool, devices, metrics, command, filename.as_str())
+                    .and_then(|mut x| x.stdin(Stdio::null())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()));
+                self.ship_captured(captured);
+                match output {
+                    Ok(output) if tool == GpuTool::Nvidia && output.status.success() => {
+                        let metrics = parse_dmon(self.model.name.as_str(),
+                                                 String::from_utf8_lossy(&output.stdout).as_ref());
+                        self.emit_records(metrics).await;
+                    }
+                    Ok(output) if tool == GpuTool::Rocprof && timed_out(output.status) => {
+                        let stats = format!("/tmp/girasol-rocprof-{}.stats.csv", self.model.name);
+                        self.ship_file(stats.as_str(), "csv").await;
+                        std::fs::remove_file(&stats).ok();
+                    }
+                    Ok(output) => self.report_error(String::from_utf8_lossy(&output.stderr).to_string()),
+                    Err(e) => self.report_error(e.to_string())
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
         }
     }
-
-    async fn stopped(&mut self, _: &Context<Self>) {
-        if let Some(mut c) = self.child.take() {
-            if let Err(e) = c.kill() {
-                error!("cannot kill running perf {}, pid: {}", e, c.id())
+    async fn handle_heaptrack(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Heaptrack { program, args, attach, summary_only } => {
+                let filename = format!("/tmp/girasol-heaptrack-{}", self.model.name);
+                let mut captured = Captured::default();
+                let result = heaptrack_command(&self.model, program, args, *attach, filename.as_str())
+                    .and_then(|mut x| x.stdin(Stdio::null())
+                        .stdout(Stdio::null())
+                        .output()
+                        .map(|x| { captured = capture_output(&self.model, &x); x })
+                        .map_err(|x| x.into()));
+                let summary_only = *summary_only;
+                let data = format!("{}.gz", filename);
+                self.ship_captured(captured);
+                match result {
+                    Ok(output) if !timed_out(output.status) =>
+                        self.report_error(round_failure("heaptrack", &output).to_string()),
+                    Ok(_) if summary_only => match std::process::Command::new("heaptrack_print")
+                        .arg(data.as_str())
+                        .output() {
+                        Ok(output) if output.status.success() => self.ship_bytes(output.stdout, "txt").await,
+                        Ok(output) => self.report_error(String::from_utf8_lossy(&output.stderr).to_string()),
+                        Err(e) => self.report_error(e.to_string())
+                    },
+                    Ok(_) => self.ship_file(data.as_str(), "gz").await,
+                    Err(e) => self.report_error(e.to_string())
+                }
+                std::fs::remove_file(&data).ok();
+                self.schedule_next(ctx);
             }
+            _ => unsafe { std::intrinsics::unreachable() }
         }
-        info!("trace {} actor stopped", self.model.name);
     }
-}
-
-impl TraceActor {
-    async fn commit_suicide(&mut self) {
-        if let Some(keeper) = &mut self.house_keeper {
-            keeper.send(KeeperMsg::Unregister(self.model.name.clone()))
-                .map_err(|x| x.into())
-                .check_error()
+    async fn handle_lttng(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Lttng { events, context } => {
+                let directory = format!("/tmp/girasol-lttng-{}", self.model.name);
+                let archive = format!("{}.tar.gz", directory);
+                std::fs::remove_dir_all(&directory).ok();
+                match run_all(lttng_setup(&self.model, events, context, directory.as_str())) {
+                    Ok(_) => {
+                        async_std::task::sleep(Duration::from_secs(self.model.lasting as u64)).await;
+                        let result = run_all(lttng_teardown(&self.model))
+                            .and_then(|_| run_all(vec![{
+                                let mut tar = std::process::Command::new("tar");
+                                tar.arg("-C")
+                                    .arg(directory.as_str())
+                                    .arg("-czf")
+                                    .arg(archive.as_str())
+                                    .arg(".");
+                                tar
+                            }]));
+                        match result {
+                            Ok(_) => self.ship_file(archive.as_str(), "tar.gz").await,
+                            Err(e) => self.report_error(e.to_string())
+                        }
+                    }
+                    Err(e) => {
+                        run_all(lttng_teardown(&self.model)).ok();
+                        self.report_error(e.to_string())
+                    }
+                }
+                std::fs::remove_dir_all(&directory).ok();
+                std::fs::remove_file(&archive).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
         }
     }
-}
-
-#[xactor::message(result = "()")]
-#[derive(Serialize, Deserialize, TypeName)]
-pub struct Connect {
-    trace_name: String,
-    callee: String,
-    caller: String,
-    weight: usize,
-}
-
-#[xactor::message(result = "()")]
-#[derive(Serialize, Deserialize, TypeName)]
-pub struct TraceError {
-    trace_name: String,
-    content: String,
-}
-
-impl TraceActor {
-    async fn handle_stap(&mut self, ctx: &Context<Self>) {
+    async fn handle_dtrace(&mut self, ctx: &Context<Self>) {
         match &self.model.content {
-            crate::database::TraceContent::SystemTap {
-                envs,
-                args,
-                ..
-            } => {
-                if self.file.is_none()
-                {
-                    match to_tempfile(&self.model) {
+            TraceContent::DTrace { target, .. } => {
+                if self.file.is_none() {
+                    match prepare_dtrace().and_then(|_| to_tempfile(&self.model)) {
                         Ok(e) => { self.file.replace(e); }
                         Err(e) => {
-                            error!("trace {} cannot create script file with error {}, going to suicide"
-                                   , self.model.name, e);
+                            error!("trace {} cannot prepare dtrace with error {}, going to suicide",
+                                   self.model.name, e);
                             self.commit_suicide().await;
                             return;
                         }
                     }
                 }
-                let file = self.file.as_ref().unwrap();
-                match std::process::Command::new("stap")
-                    .arg(file.path())
-                    .envs(envs.clone().into_iter())
-                    .args(args.iter())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .map(|x| (x.stdout.unwrap(), x.stderr.unwrap())) {
-                    Err(e) => {
-                        error!("trace {} cannot create script file with error {}, going to suicide"
-                               , self.model.name, e);
-                        self.commit_suicide().await;
-                        return;
-                    }
-                    Ok((out, err)) => {
-                        let mut callee = None;
-                        let err_name = self.model.name.clone();
-                        let mut err_client = self.send_client.clone();
-                        let err_handle = async_std::task::spawn(async move {
-                            for i in std::io::BufReader::new(err).lines() {
-                                if let Ok(c) = i {
-                                    error!("trace {} error: {}", err_name, c);
-                                    if let Some(err_client) = &mut err_client {
-                                        err_client.send(TraceError {
-                                            trace_name: err_name.clone(),
-                                            content: c,
-                                        }).check_error();
-                                    }
+                match dtrace_command(&self.model, self.file.as_ref().unwrap().path(), target)
+                    .and_then(|mut x| x.stdout(Stdio::piped())
+                        .stderr(Stdio::piped())
+                        .spawn()
+                        .map_err(|x| x.into())) {
+                    Ok(mut child) => {
+                        let err_handle = self.forward_stderr(child.stderr.take().unwrap());
+                        let mut data = Vec::new();
+                        for i in std::io::BufReader::new(child.stdout.take().unwrap()).lines() {
+                            if let Ok(line) = i {
+                                if let Some(send_client) = &mut self.send_client {
+                                    send_client.send(TraceOutput {
+                                        trace_name: self.model.name.clone(),
+                                        content: line,
+                                    }).check_error();
+                                } else {
+                                    data.push(line);
                                 }
                             }
-                        });
-                        for i in std::io::BufReader::new(out).lines() {
+                        }
+                        err_handle.await;
+                        self.reap("dtrace", child);
+                        if self.send_client.is_none() {
+                            self.write_round("txt", data.join("\n")).await.check_error();
+                        }
+                    }
+                    Err(e) => self.report_error(e.to_string())
+                }
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_etw(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::Etw { profiles } => {
+                let filename = std::env::temp_dir().join(format!("girasol-etw-{}.etl", self.model.name));
+                #[cfg(windows)]
+                let result = crate::etw::record(profiles, self.model.lasting, &filename).await;
+                #[cfg(not(windows))]
+                let result: Result<()> = {
+                    let _ = profiles;
+                    Err(anyhow!("etw based trace is only available on windows"))
+                };
+                match result {
+                    Ok(_) => self.ship_file(filename.to_string_lossy().as_ref(), "etl").await,
+                    Err(e) => self.report_error(e.to_string())
+                }
+                std::fs::remove_file(&filename).ok();
+                self.schedule_next(ctx);
+            }
+            _ => unsafe { std::intrinsics::unreachable() }
+        }
+    }
+    async fn handle_perf_live(&mut self, ctx: &Context<Self>) {
+        match &self.model.content {
+            TraceContent::PerfLive { absolute_path, frequency, flush_secs, additional_args } => {
+                let flush = Duration::from_secs(*flush_secs);
+                let result = resolve_frequency(self.model.name.as_str(), *frequency)
+                    .and_then(|frequency| self.round_pids(absolute_path.as_str())
+                        .map(|pids| (frequency, pids)))
+                    .and_then(|(frequency, pids)| if pids.is_empty() {
+                        Err(anyhow!("no running process of {}", absolute_path))
+                    } else {
+                        let pids = pids.into_iter()
+                            .map(|x| x.to_string())
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        perf_live_command(&self.model, pids.as_str(), frequency, additional_args)
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::piped())
+                            .spawn()
+                            .map_err(|x| x.into())
+                    })
+                    .and_then(|mut record| perf_live_script_command(&self.model)
+                        .stdin(Stdio::from(record.stdout.take().unwrap()))
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::null())
+                        .spawn()
+                        .map(|script| (record, script))
+                        .map_err(|x| x.into()));
+                match result {
+                    Ok((mut record, mut script)) => {
+                        let err_handle = self.forward_stderr(record.stderr.take().unwrap());
+                        let mut batch = String::new();
+                        let mut last_flush = std::time::Instant::now();
+                        // local rounds write one file per round, so the batches are held until it ends
+                        let local = self.send_client.is_none();
+                        let mut held = Vec::new();
+                        for i in std::io::BufReader::new(script.stdout.take().unwrap()).lines() {
                             if let Ok(line) = i {
-                                if let Some(t) = callee.take() {
-                                    if line.contains(" : ") {
-                                        let mut split = line.split(" : ");
-                                        split.next();
-                                        if let Some(e) = split.next()
-                                            .and_then(|x| x.split("+")
-                                                .next())
-                                            .filter(|x| !x.starts_with("0x")) {
-                                            if let Some(send_client) = &mut self.send_client {
-                                                send_client.send(Connect {
-                                                    trace_name: self.model.name.clone(),
-                                                    callee: t,
-                                                    caller: String::from(e),
-                                                    weight: 1,
-                                                })
-                                                    .map_err(|x| x.into())
-                                                    .check_error()
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    if line.starts_with("probe:") {
-                                        let mut iter = line.split_ascii_whitespace();
-                                        iter.next();
-                                        callee = iter.next().map(|x| String::from(x))
+                                let boundary = line.trim().is_empty();
+                                batch.push_str(line.as_str());
+                                batch.push('\n');
+                                // only flush on sample boundaries so that no stack is split
+                                if boundary && last_flush.elapsed() >= flush {
+                                    let stacks = parse_perf_script(self.model.name.as_str(), batch.as_str());
+                                    if local {
+                                        held.extend(stacks);
+                                    } else {
+                                        self.emit_stacks(stacks, true).await;
                                     }
+                                    batch.clear();
+                                    last_flush = std::time::Instant::now();
                                 }
-                            } else {
-                                callee = None;
                             }
                         }
+                        if !batch.trim().is_empty() {
+                            held.extend(parse_perf_script(self.model.name.as_str(), batch.as_str()));
+                        }
+                        if !held.is_empty() {
+                            self.emit_stacks(held, true).await;
+                        }
+                        record.wait().map_err(|x| x.into()).check_error();
+                        script.wait().map_err(|x| x.into()).check_error();
                         err_handle.await;
                     }
+                    Err(e) => self.report_error(e.to_string())
                 }
-                ctx.send_later(TraceEvent::NextRound, Duration::from_secs(self.model.interval as u64));
+                self.schedule_next(ctx);
             }
             _ => unsafe { std::intrinsics::unreachable() }
         }
     }
+    async fn stop_perf(&mut self, mut child: std::process::Child) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(self.model.grace_secs);
+        while let Ok(None) = child.try_wait() {
+            if std::time::Instant::now() >= deadline {
+                for stop in &[crate::utils::Stop::Terminate, crate::utils::Stop::Kill] {
+                    crate::utils::stop_process(child.id(), self.model.follow_children, *stop).ok();
+                    async_std::task::sleep(Duration::from_secs(1)).await;
+                    if let Ok(Some(_)) = child.try_wait() {
+                        break;
+                    }
+                }
+                self.reap("perf", child);
+                self.report_error(format!("perf exceeded its deadline of {}s and was killed", self.model.grace_secs));
+                return;
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    /// Returns whether the trace may go on within its overhead budget.
+    fn enforce_overhead(&mut self, overhead: f64) -> bool {
+        let (frequency, budget) = match &self.model.content {
+            TraceContent::PerfBranch { frequency, max_overhead_pct: Some(budget), .. } => (*frequency, *budget),
+            _ => return true
+        };
+        if overhead <= budget {
+            return true;
+        }
+        let current = self.adjusted_frequency
+            .or_else(|| resolve_frequency(self.model.name.as_str(), frequency).unwrap_or(None))
+            .unwrap_or(PERF_DEFAULT_FREQUENCY);
+        let lowered = (current as f64 * budget / overhead) as usize;
+        let adjustment = if lowered < PERF_MIN_FREQUENCY {
+            error!("perf of trace {} used {:.1}% cpu over its {:.1}% budget, aborting",
+                   self.model.name, overhead, budget);
+            None
+        } else {
+            warn!("perf of trace {} used {:.1}% cpu over its {:.1}% budget, lowering frequency to {}",
+                  self.model.name, overhead, budget, lowered);
+            self.adjusted_frequency = Some(lowered);
+            Some(lowered)
+        };
+        if let Some(sender) = &mut self.send_client {
+            sender.send(OverheadAdjustment {
+                trace_name: self.model.name.clone(),
+                overhead_pct: overhead,
+                budget_pct: budget,
+                frequency: adjustment,
+            }).check_error();
+        }
+        adjustment.is_some()
+    }
+
+    fn tune_frequency(&mut self, filename: &str) {
+        let frequency = match &self.model.content {
+            TraceContent::PerfBranch { frequency, .. } => *frequency,
+            _ => return
+        };
+        let target = match frequency {
+            Frequency::Auto { target_samples_per_sec } => target_samples_per_sec,
+            _ => return
+        };
+        // rounds are inspected until one gets tuned, and a throttled rate is never raised again
+        if self.adjusted_frequency.is_some() {
+            return;
+        }
+        let result = perf_sample_count(filename)
+            .and_then(|samples| {
+                resolve_frequency(self.model.name.as_str(), frequency)
+                    .map(|current| (samples, current.unwrap_or(PERF_DEFAULT_FREQUENCY)))
+            })
+            .and_then(|(samples, current)| max_sample_rate().map(|max| (samples, current, max)));
+        match result {
+            Ok((0, _, _)) => warn!("perf of trace {} recorded no samples, keeping its frequency", self.model.name),
+            Ok((samples, current, max)) => {
+                let rate = samples as f64 / self.model.lasting.max(1) as f64;
+                let tuned = ((current as f64 * target as f64 / rate) as usize).max(PERF_MIN_FREQUENCY).min(max);
+                info!("perf of trace {} recorded {:.1} samples/s at {}Hz, tuning to {}Hz",
+                      self.model.name, rate, current, tuned);
+                self.adjusted_frequency = Some(tuned);
+            }
+            Err(e) => warn!("cannot tune frequency of trace {}: {}", self.model.name, e)
+        }
+    }
+
+    fn symbolize(&self, filename: &str) {
+        match fetch_debuginfo(&self.model, filename) {
+            Ok(0) => (),
+            Ok(n) => info!("fetched debuginfo of {} binaries for trace {}", n, self.model.name),
+            Err(e) => warn!("cannot fetch debuginfo for trace {}: {}", self.model.name, e)
+        }
+    }
+
+    async fn ship_flamegraph(&mut self, filename: &str, output: FlamegraphOutput) {
+        let title = format!("{} round {}", self.model.name, self.round.index);
+        let result = fold_perf_script(&self.model, filename)
+            .and_then(|folded| match output {
+                FlamegraphOutput::Folded => Ok((None, Some(folded))),
+                FlamegraphOutput::Svg => render_flamegraph(title, &folded).map(|svg| (Some(svg), None)),
+                FlamegraphOutput::Both => render_flamegraph(title, &folded).map(|svg| (Some(svg), Some(folded))),
+            });
+        match result {
+            Ok((svg, folded)) => {
+                if let Some(svg) = svg {
+                    self.ship_bytes(svg, "svg").await;
+                }
+                if let Some(folded) = folded {
+                    self.ship_bytes(folded, "folded").await;
+                }
+            }
+            Err(e) => self.report_error(format!("cannot render flamegraph: {}", e))
+        }
+    }
+
     async fn handle_perf_ending(&mut self, ctx: &Context<Self>) {
         for i in &self.local_pids {
             self.running_pids.remove(i.value());
         }
-        if let Some(child) = self.child.take() {
-            nix::sys::signal::kill(Pid::from_raw(child.id() as i32), nix::sys::signal::SIGINT)
-                .map_err(|x| x.into())
-                .check_error();
+        let mut within_budget = true;
+        if let Some(mut child) = self.child.take() {
+            let elapsed = crate::utils::unix_now().saturating_sub(self.round.started_at).max(1);
+            match crate::utils::cpu_seconds(child.id() as i32) {
+                Ok(seconds) => within_budget = self.enforce_overhead(seconds * 100.0 / elapsed as f64),
+                Err(e) => warn!("cannot measure overhead of trace {}: {}", self.model.name, e)
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => info!("perf of trace {} already exited with {}", self.model.name, status),
+                _ => crate::utils::stop_process(child.id(), self.model.follow_children, crate::utils::Stop::Interrupt)
+                    .check_error()
+            }
             async_std::task::sleep(Duration::from_millis(500)).await;
+            self.stop_perf(child).await;
             let filename = format!("/tmp/girasol-perf-{}.data", self.model.name);
-            match std::process::Command::new("perf")
-                .arg("report")
-                .arg("-i")
-                .arg(filename)
-                .arg("-n")
-                .arg("--sort")
-                .arg("symbol_from,symbol_to")
-                .arg("--stdio")
-                .stdout(Stdio::piped())
-                .spawn()
-                .and_then(|x| x.wait_with_output())
-                .map(|x| { x.stdout }) {
-                Err(e) => if let Some(send_client) = &mut self.send_client {
-                    send_client.send(TraceError {
-                        trace_name: self.model.name.clone(),
-                        content: e.to_string(),
-                    }).check_error();
-                }
-                Ok(output) => {
-                    let reader = output.lines();
-                    let mut data = Vec::new();
-                    for i in reader {
-                        if let Ok(i) = i {
-                            let res: &str = i.trim();
-                            if !res.starts_with("#") && !res.is_empty() {
-                                let mut words = res.split_ascii_whitespace();
-                                words.next();
-                                if let Some((count, from, to)) = words.next().and_then(
-                                    |count| {
-                                        words.next();
-                                        words.next()
-                                            .and_then(|from| {
-                                                words.next();
-                                                words.next().map(|to|
-                                                    (count, from, to)
-                                                )
-                                            })
-                                    }
-                                ) {
-                                    let count: usize = count.parse().unwrap_or(0);
-                                    if from.starts_with("0x") || to.starts_with("0x") {
-                                        continue;
-                                    }
-                                    if let Some(sender) = &mut self.send_client {
-                                        sender.send(Connect {
-                                            trace_name: self.model.name.clone(),
-                                            callee: to.to_string(),
-                                            caller: from.to_string(),
-                                            weight: count,
-                                        }).check_error();
-                                    } else {
-                                        data.push(Connect {
-                                            trace_name: self.model.name.clone(),
-                                            callee: to.to_string(),
-                                            caller: from.to_string(),
-                                            weight: count,
-                                        });
+            self.tune_frequency(filename.as_str());
+            self.symbolize(filename.as_str());
+            let (call_graph, flamegraph) = match &self.model.content {
+                TraceContent::PerfBranch { call_graph, flamegraph, .. } => (*call_graph, *flamegraph),
+                _ => (None, None)
+            };
+            if let Some(output) = flamegraph {
+                self.ship_flamegraph(filename.as_str(), output).await;
+            }
+            if call_graph.is_some() {
+                self.report_stacks(filename.as_str()).await;
+            } else {
+                match perf_post_command(&self.model)
+                    .arg("report")
+                    .arg("-i")
+                    .arg(&filename)
+                    .arg("-n")
+                    .arg("--sort")
+                    .arg("symbol_from,symbol_to")
+                    .arg("--stdio")
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .map(|mut x| (std::io::BufReader::new(x.stdout.take().unwrap()), x)) {
+                    Err(e) => if let Some(send_client) = &mut self.send_client {
+                        send_client.send(TraceError {
+                            trace_name: self.model.name.clone(),
+                            content: e.to_string(),
+                        }).check_error();
+                    }
+                    Ok((output, mut child)) => {
+                        let reader = output.lines();
+                        let mut data = Vec::new();
+                        for i in reader {
+                            if let Ok(i) = i {
+                                let res: &str = i.trim();
+                                if !res.starts_with("#") && !res.is_empty() {
+                                    let mut words = res.split_ascii_whitespace();
+                                    words.next();
+                                    if let Some((count, from, to)) = words.next().and_then(
+                                        |count| {
+                                            words.next();
+                                            words.next()
+                                                .and_then(|from| {
+                                                    words.next();
+                                                    words.next().map(|to|
+                                                        (count, from, to)
+                                                    )
+                                                })
+                                        }
+                                    ) {
+                                        let count: usize = count.parse().unwrap_or(0);
+                                        if from.starts_with("0x") || to.starts_with("0x") {
+                                            continue;
+                                        }
+                                        if let Some(sender) = &mut self.send_client {
+                                            sender.send(Connect {
+                                                trace_name: self.model.name.clone(),
+                                                callee: to.to_string(),
+                                                caller: from.to_string(),
+                                                weight: count,
+                                            }).check_error();
+                                        } else {
+                                            data.push(Connect {
+                                                trace_name: self.model.name.clone(),
+                                                callee: to.to_string(),
+                                                caller: from.to_string(),
+                                                weight: count,
+                                            });
+                                        }
                                     }
                                 }
                             }
                         }
-                    }
-                    if self.send_client.is_none() {
-                        let json = simd_json::to_string_pretty(&data).unwrap();
-                        let handle = self.written.1.lock().await;
-                        std::fs::write(format!("{}-{}.json", self.pattern, handle.load(SeqCst)), json).unwrap();
-                        handle.fetch_sub(1, SeqCst);
-                        self.written.0.notify_one();
+                        child.wait().map_err(|x| x.into()).check_error();
+                        if self.send_client.is_none() {
+                            let json = simd_json::to_string_pretty(&data).unwrap();
+                            self.write_round("json", json).await.check_error();
+                        }
                     }
                 }
             }
+            if let Some(kind) = self.model.compression {
+                std::fs::File::open(&filename)
+                    .and_then(|input| std::fs::File::create(format!("{}.{}", filename, kind.extension()))
+                        .map(|output| (input, output)))
+                    .map_err(|x| x.into())
+                    .and_then(|(input, output)| kind.compress_stream(input, output))
+                    .and_then(|_| std::fs::remove_file(&filename).map_err(|x| x.into()))
+                    .check_error();
+            }
+        }
+        if !within_budget {
+            self.commit_suicide().await;
+            return;
         }
-        ctx.send_later(TraceEvent::NextRound, Duration::from_secs(self.model.interval as u64))
+        self.schedule_next(ctx)
     }
     async fn handle_perf(&mut self, ctx: &Context<Self>) {
         match &self.model.content {
             crate::database::TraceContent::PerfBranch {
-                frequency, absolute_path, additional_args, ..
+                frequency, absolute_path, additional_args, call_graph, cpus, tid_filter, ..
             } => {
-                match crate::utils::find_running(absolute_path.as_str())
+                let frequency = match resolve_frequency(self.model.name.as_str(), *frequency) {
+                    Ok(frequency) => self.adjusted_frequency.or(frequency),
+                    Err(e) => {
+                        error!("trace {} cannot resolve frequency: {}", self.model.name, e);
+                        if let Some(sender) = &mut self.send_client {
+                            sender.send(TraceError {
+                                trace_name: self.model.name.clone(),
+                                content: e.to_string(),
+                            }).check_error();
+                        }
+                        return;
+                    }
+                };
+                match self.round_pids(absolute_path.as_str())
                     .map(|x| {
                         self.local_pids.clear();
                         for i in x.iter()
@@ -332,27 +4343,13 @@ impl TraceActor {
                     })
                     .map(|x| x.collect::<Vec<_>>().join(",")) {
                     Ok(pids) if !pids.is_empty() => {
-                        info!("perf start with pids: {}", pids);
-                        let mut child = std::process::Command::new("perf");
-                        child.arg("record")
-                            .arg("--no-buffering")
-                            .arg("--branch-filter=any_call,u")
-                            .arg("-e")
-                            .arg("branches:u")
-                            .arg("-p")
-                            .arg(pids)
-                            .arg("-o")
-                            .arg(format!("/tmp/girasol-perf-{}.data", self.model.name))
-                            .args(additional_args.iter())
-                            .stderr(Stdio::piped());
-                        match frequency {
-                            crate::database::Frequency::Max => {
-                                child.arg("-Fmax");
-                            }
-                            crate::database::Frequency::Specific(value) => {
-                                child.arg("-F").arg(value.to_string());
-                            }
-                            _ => ()
+                        info!("perf start with pids: {}", pids);
+                        let mut child = perf_command(&self.model, pids.as_str(),
+                                                     frequency, *call_graph, cpus, tid_filter,
+                                                     additional_args);
+                        child.stderr(Stdio::piped());
+                        if self.model.follow_children {
+                            own_process_group(&mut child);
                         }
                         match child.spawn() {
                             Ok(mut c) => {
@@ -405,12 +4402,233 @@ impl TraceActor {
     }
 }
 
+#[async_trait::async_trait]
+impl Handler<SlotGranted> for TraceActor {
+    async fn handle(&mut self, ctx: &Context<Self>, _: SlotGranted) {
+        self.holding_slot = true;
+        if self.draining {
+            self.release_slot();
+            return;
+        }
+        match self.pending_round {
+            // handled in place, so that no other event sneaks in while the slot is held
+            Some(event) => Handler::<TraceEvent>::handle(self, ctx, event).await,
+            None => self.release_slot()
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<GroupStart> for TraceActor {
+    async fn handle(&mut self, ctx: &Context<Self>, msg: GroupStart) {
+        if self.child.is_some() || self.pending_round.is_some() {
+            warn!("trace {} is in the middle of a round, sitting out group round {}", self.model.name, msg.0);
+            if let (Some(group), Some(keeper)) = (&self.model.group, &mut self.house_keeper) {
+                keeper.send(KeeperMsg::GroupDone {
+                    group: group.clone(),
+                    name: self.model.name.clone(),
+                }).check_error();
+            }
+            return;
+        }
+        self.round.group_round = Some(msg.0);
+        Handler::<TraceEvent>::handle(self, ctx, TraceEvent::Trigger).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Drain> for TraceActor {
+    async fn handle(&mut self, _: &Context<Self>, _: Drain) -> bool {
+        if !self.draining {
+            info!("trace {} stops starting rounds", self.model.name);
+            self.draining = true;
+        }
+        self.pending_round = None;
+        self.child.is_some() || self.holding_slot
+    }
+}
+
+/// Only rounds spanning several events, i.e. perf recordings, can be cut short,
+/// other rounds hold their slot until they finish.
+#[async_trait::async_trait]
+impl Handler<Preempt> for TraceActor {
+    async fn handle(&mut self, _: &Context<Self>, _: Preempt) {
+        let child = match (self.holding_slot, self.child.take()) {
+            (true, Some(child)) => child,
+            (_, child) => {
+                self.child = child;
+                return;
+            }
+        };
+        warn!("round {} of trace {} preempted", self.round.index, self.model.name);
+        // perf gets to wrap up like at the end of a round, it is only killed after the grace period
+        crate::utils::stop_process(child.id(), self.model.follow_children, crate::utils::Stop::Interrupt)
+            .check_error();
+        self.stop_perf(child).await;
+        for i in &self.local_pids {
+            self.running_pids.remove(i.value());
+        }
+        self.round.stale_endings += 1;
+        if let Some(sender) = &mut self.send_client {
+            sender.send(RoundSkipped {
+                trace_name: self.model.name.clone(),
+                time: crate::utils::unix_now(),
+                reason: "preempted by a higher priority trace".to_string(),
+            }).check_error();
+        }
+        self.release_slot();
+        // the round starts over once a slot is free again
+        if let Some(keeper) = &mut self.house_keeper {
+            keeper.send(KeeperMsg::RequestSlot(self.model.name.clone())).check_error();
+            self.pending_round = Some(TraceEvent::Retry);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Pause> for TraceActor {
+    async fn handle(&mut self, _: &Context<Self>, msg: Pause) {
+        self.model.paused = msg.0;
+        if !msg.0 {
+            let owed = self.model.catch_up.owed(self.round.missed);
+            if owed > 0 {
+                info!("trace {} catches up on {} of {} rounds missed while paused",
+                      self.model.name, owed, self.round.missed);
+                // the catch-up rounds follow the next scheduled one
+                self.round.catch_up.set(owed);
+            }
+        }
+        self.round.missed = 0;
+    }
+}
+
 #[async_trait::async_trait]
 impl Handler<TraceEvent> for TraceActor {
     async fn handle(&mut self, ctx: &Context<Self>, event: TraceEvent) {
         log::debug!("received message");
+        // a round handed over by the slot went through the checks below when it asked for the slot
+        let granted = self.holding_slot && self.pending_round.take().is_some();
+        if let (TraceEvent::NextRound, false) = (event, granted) {
+            if self.child.is_some() || self.holding_slot || self.pending_round.is_some() {
+                debug!("trace {} is in the middle of a round, scheduled round dropped", self.model.name);
+                // the running round schedules the next one itself
+                self.round.suppressed = self.round.suppressed.saturating_sub(1);
+                return;
+            }
+        }
+        if let (TraceEvent::PerfEnding, true) = (event, self.round.stale_endings > 0) {
+            self.round.stale_endings -= 1;
+            return;
+        }
+        match event {
+            TraceEvent::PerfEnding => (),
+            _ if self.draining => {
+                debug!("trace {} is draining, round dropped", self.model.name);
+                return;
+            }
+            _ => ()
+        }
+        if let TraceEvent::Trigger = event {
+            if self.child.is_some() || self.pending_round.is_some() {
+                warn!("trace {} is in the middle of a round, trigger ignored", self.model.name);
+                return;
+            }
+            if !self.holding_slot && self.self_scheduled() {
+                // the triggered round schedules its own successor, which takes over the pending one
+                self.round.suppressed += 1;
+            }
+        }
+        if let (TraceEvent::NextRound, false) = (event, granted) {
+            if self.grouped() {
+                debug!("trace {} waits for the round of its group", self.model.name);
+                return;
+            }
+            if self.round.suppressed > 0 {
+                self.round.suppressed -= 1;
+                return;
+            }
+            if self.expired() {
+                self.expire();
+                return;
+            }
+            // local and single rounds are asked for explicitly, so they run regardless
+            if self.model.paused && self.house_keeper.is_some() && !self.one_shot {
+                debug!("trace {} is paused, skipping round", self.model.name);
+                self.round.missed += 1;
+                self.schedule_next(ctx);
+                return;
+            }
+            if let Ok(false) = self.model.in_window(chrono::Local::now().time()) {
+                info!("trace {} skips a round outside of its allowed windows", self.model.name);
+                if let Some(sender) = &mut self.send_client {
+                    sender.send(RoundSkipped {
+                        trace_name: self.model.name.clone(),
+                        time: crate::utils::unix_now(),
+                        reason: "outside of allowed windows".to_string(),
+                    }).check_error();
+                }
+                self.schedule_next(ctx);
+                return;
+            }
+        }
+        let starting = match event {
+            TraceEvent::PerfEnding => false,
+            _ => true
+        };
+        if let (true, Some(Err(e))) = (starting, self.model.target.as_mut().map(|x| x.resolve())) {
+            // the container may be restarting, so keep trying in later rounds
+            error!("trace {} error: {}", self.model.name, e);
+            self.send_error(e.to_string());
+            self.release_slot();
+            self.schedule_next(ctx);
+            return;
+        }
+        if let (TraceEvent::NextRound, Some(TraceTarget::AttachPid(pid))) = (&event, &self.model.target) {
+            let pid = *pid;
+            if !crate::utils::pid_alive(pid) {
+                warn!("target process {} of trace {} has exited, stopping", pid, self.model.name);
+                self.commit_suicide().await;
+                self.release_written().await;
+                return;
+            }
+        }
+        if starting && !self.holding_slot {
+            if let Some(keeper) = &mut self.house_keeper {
+                if self.pending_round.is_none() {
+                    keeper.send(KeeperMsg::RequestSlot(self.model.name.clone())).check_error();
+                    self.pending_round = Some(event);
+                } else {
+                    debug!("trace {} is still waiting for a slot", self.model.name);
+                }
+                return;
+            }
+        }
+        let finishing = match (&event, &self.model.content) {
+            (TraceEvent::NextRound, TraceContent::PerfBranch { .. })
+            | (TraceEvent::Retry, TraceContent::PerfBranch { .. })
+            | (TraceEvent::Trigger, TraceContent::PerfBranch { .. }) => {
+                self.begin_round();
+                false
+            }
+            (TraceEvent::NextRound, _) | (TraceEvent::Retry, _) | (TraceEvent::Trigger, _) => {
+                self.begin_round();
+                true
+            }
+            (TraceEvent::PerfEnding, _) => true
+        };
+        let pre_hook = self.model.pre_hook.clone();
+        if starting && !self.run_hook("pre", pre_hook) {
+            self.release_slot();
+            self.schedule_next(ctx);
+            self.finish_round().await;
+            self.conclude_round(ctx, true);
+            if self.round.attempt == 0 {
+                self.count_round().await;
+            }
+            return;
+        }
         match event {
-            TraceEvent::NextRound => match self.model.content {
+            TraceEvent::NextRound | TraceEvent::Retry | TraceEvent::Trigger => match self.model.content {
                 crate::database::TraceContent::SystemTap {
                     ..
                 } => {
@@ -422,17 +4640,313 @@ impl Handler<TraceEvent> for TraceActor {
                     log::debug!("start perfing");
                     self.handle_perf(ctx).await
                 }
+                crate::database::TraceContent::BpfTrace {
+                    ..
+                } => {
+                    self.handle_bpftrace(ctx).await
+                }
+                crate::database::TraceContent::Ftrace {
+                    ..
+                } => {
+                    self.handle_ftrace(ctx).await
+                }
+                crate::database::TraceContent::Strace {
+                    ..
+                } => {
+                    self.handle_strace(ctx).await
+                }
+                crate::database::TraceContent::PerfStat {
+                    ..
+                } => {
+                    self.handle_perf_stat(ctx).await
+                }
+                crate::database::TraceContent::OffCpu {
+                    ..
+                } => {
+                    self.handle_offcpu(ctx).await
+                }
+                crate::database::TraceContent::IntelPt {
+                    ..
+                } => {
+                    self.handle_intel_pt(ctx).await
+                }
+                crate::database::TraceContent::Callgrind {
+                    ..
+                } => {
+                    self.handle_callgrind(ctx).await
+                }
+                crate::database::TraceContent::Custom {
+                    ..
+                } => {
+                    self.handle_custom(ctx).await
+                }
+                crate::database::TraceContent::SchedLatency {
+                    ..
+                } => {
+                    self.handle_sched_latency(ctx).await
+                }
+                crate::database::TraceContent::PerfMem {
+                    ..
+                } => {
+                    self.handle_perf_mem(ctx).await
+                }
+                crate::database::TraceContent::Sampler {
+                    ..
+                } => {
+                    self.handle_sampler(ctx).await
+                }
+                crate::database::TraceContent::AsyncProfiler {
+                    ..
+                } => {
+                    self.handle_async_profiler(ctx).await
+                }
+                crate::database::TraceContent::Pcap {
+                    ..
+                } => {
+                    self.handle_pcap(ctx).await
+                }
+                crate::database::TraceContent::PerfC2C {
+                    ..
+                } => {
+                    self.handle_perf_c2c(ctx).await
+                }
+                crate::database::TraceContent::Energy {
+                    ..
+                } => {
+                    self.handle_energy(ctx).await
+                }
+                crate::database::TraceContent::Gpu {
+                    ..
+                } => {
+                    self.handle_gpu(ctx).await
+                }
+                crate::database::TraceContent::Heaptrack {
+                    ..
+                } => {
+                    self.handle_heaptrack(ctx).await
+                }
+                crate::database::TraceContent::Lttng {
+                    ..
+                } => {
+                    self.handle_lttng(ctx).await
+                }
+                crate::database::TraceContent::DTrace {
+                    ..
+                } => {
+                    self.handle_dtrace(ctx).await
+                }
+                crate::database::TraceContent::Etw {
+                    ..
+                } => {
+                    self.handle_etw(ctx).await
+                }
+                crate::database::TraceContent::PerfLive {
+                    ..
+                } => {
+                    self.handle_perf_live(ctx).await
+                }
             }
             TraceEvent::PerfEnding => self.handle_perf_ending(ctx).await
         }
+        if finishing {
+            let post_hook = self.model.post_hook.clone();
+            self.run_hook("post", post_hook);
+            self.finish_round().await;
+            self.release_slot();
+            self.conclude_round(ctx, true);
+            // retried rounds count once they are over
+            if self.round.attempt == 0 {
+                self.count_round().await;
+            }
+            if self.expired() {
+                self.expire();
+            }
+        } else if self.child.is_none() {
+            // perf failed to start, so no ending will follow to schedule the next round
+            self.round.failed = true;
+            self.finish_round().await;
+            self.release_slot();
+            if !self.conclude_round(ctx, false) {
+                self.schedule_next(ctx);
+            }
+            if self.round.attempt == 0 {
+                self.count_round().await;
+            }
+        } else {
+            return;
+        }
+        if self.one_shot {
+            self.commit_suicide().await;
+        }
     }
 }
 
 impl HouseKeeper {
-    async fn create_actor(&mut self, model: TraceModel, ctx: &Context<Self>) -> Result<()> {
+    fn grant_slot(&mut self, name: String) {
+        if self.draining {
+            return;
+        }
+        if let Some(trace) = self.running_trace.get_mut(name.as_str()) {
+            trace.addr.send(SlotGranted).check_error();
+            self.round_slots.active.insert(name);
+        }
+    }
+
+    /// Starts a round on every active member, unless the previous one is still going.
+    fn start_group(&mut self, group: String) {
+        if let Some(round) = self.groups.get(group.as_str()) {
+            if !round.pending.is_empty() {
+                debug!("group round {} is still running", round.id);
+                return;
+            }
+        }
+        let id = format!("{}-{}", group, crate::utils::unix_now());
+        let mut pending = hashbrown::HashSet::new();
+        for (name, trace) in self.running_trace.iter_mut() {
+            if trace.paused || trace.group.as_ref() != Some(&group) {
+                continue;
+            }
+            trace.addr.send(GroupStart(id.clone())).check_error();
+            pending.insert(name.clone());
+        }
+        info!("group round {} started with {} traces", id, pending.len());
+        let round = self.group_round(group);
+        round.id = id;
+        round.pending = pending;
+    }
+
+    fn group_round(&mut self, group: String) -> &mut GroupRound {
+        self.groups.entry(group).or_insert_with(|| GroupRound {
+            id: String::new(),
+            pending: Default::default(),
+            armed: false,
+        })
+    }
+
+    /// Schedules the next round of a group after the schedule of one of its members.
+    /// Each group has a single timer, which stops once the group has no member left.
+    fn arm_group(&mut self, group: String, delay: Option<Duration>, ctx: &Context<Self>) {
+        let now = chrono::Local::now();
+        let delay = delay.or_else(|| self.running_trace.values()
+            .find(|x| x.group.as_ref() == Some(&group))
+            .map(|x| match &x.schedule {
+                Some(_) => x.next_run(&now)
+                    .and_then(|next| (next - now).to_std().ok())
+                    .unwrap_or_default(),
+                None => Duration::from_secs(x.interval as u64) + crate::utils::jitter(x.jitter_secs)
+            }));
+        let round = self.group_round(group.clone());
+        match delay {
+            Some(delay) => {
+                round.armed = true;
+                ctx.send_later(KeeperMsg::GroupRound(group), delay);
+            }
+            None => {
+                round.armed = false;
+                debug!("group {} has no member left, its schedule stops", group);
+            }
+        }
+    }
+
+    fn leave_groups(&mut self, name: &str) {
+        for round in self.groups.values_mut() {
+            round.pending.remove(name);
+        }
+    }
+
+    fn request_slot(&mut self, name: String) {
+        if self.round_slots.available() {
+            self.grant_slot(name);
+        } else if !self.round_slots.waiting.contains(&name) {
+            info!("round of trace {} queued behind {} running", name, self.round_slots.active.len());
+            self.enqueue_slot(name.clone());
+            self.preempt_for(name.as_str());
+        }
+    }
+
+    fn priority(&self, name: &str) -> i32 {
+        self.running_trace.get(name).map(|x| x.priority).unwrap_or(0)
+    }
+
+    fn enqueue_slot(&mut self, name: String) {
+        let priority = self.priority(name.as_str());
+        let position = self.round_slots.waiting.iter()
+            .position(|x| self.priority(x.as_str()) < priority)
+            .unwrap_or_else(|| self.round_slots.waiting.len());
+        self.round_slots.waiting.insert(position, name);
+    }
+
+    /// Stops the lowest priority round below the one of `name`; its slot is granted once released.
+    fn preempt_for(&mut self, name: &str) {
+        let priority = self.priority(name);
+        let victim = self.round_slots.active.iter()
+            .filter(|x| !self.round_slots.preempting.contains(x.as_str()))
+            .map(|x| (self.priority(x.as_str()), x))
+            .filter(|(x, _)| *x < priority)
+            .min_by_key(|(x, _)| *x)
+            .map(|(_, x)| x.clone());
+        if let Some(victim) = victim {
+            if let Some(trace) = self.running_trace.get_mut(victim.as_str()) {
+                info!("preempting trace {} for trace {}", victim, name);
+                trace.addr.send(Preempt).check_error();
+                self.round_slots.preempting.insert(victim);
+            }
+        }
+    }
+
+    fn free_slot(&mut self, name: &str) {
+        self.round_slots.deferred.retain(|x| x != name);
+        self.round_slots.active.remove(name);
+        self.round_slots.preempting.remove(name);
+        self.round_slots.waiting.retain(|x| x != name);
+        while self.round_slots.available() {
+            match self.round_slots.waiting.pop_front() {
+                Some(next) => self.grant_slot(next),
+                None => break
+            }
+        }
+    }
+
+    async fn create_actor(&mut self, model: TraceModel, one_shot: bool, ctx: &Context<Self>) -> Result<()> {
         let flag = self.running_trace.contains_key(model.name.as_str());
-        if !flag {
+        if self.draining {
+            Err(anyhow!("{} not started while shutting down", model.name))
+        } else if !model.enabled {
+            Err(anyhow!("{} is disabled", model.name))
+        } else if model.archived_at.is_some() {
+            Err(anyhow!("{} is archived", model.name))
+        } else if !flag {
             let name = model.name.clone();
+            let method = model.content.method();
+            let lasting = model.lasting;
+            let schedule = model.cron()?;
+            let jitter_secs = model.jitter_secs;
+            let interval = model.interval;
+            let paused = model.paused;
+            let priority = model.priority;
+            let load_limit = model.load_limit;
+            let group = model.group.clone();
+            let run_after = model.run_after.clone();
+            let exec_watch = model.exec_pattern()?;
+            let setup = setup_of(&model);
+            // rounds that are not shipped land next to the agent, one set of files per trace
+            let pattern = crate::utils::safe_name(model.name.as_str());
+            if exec_watch.is_some() && self.exec_watcher.is_none() {
+                match watch_exec(ctx.address()) {
+                    Ok(child) => self.exec_watcher = Some(child),
+                    Err(e) => error!("cannot watch exec for trace {}: {}", model.name, e)
+                }
+            }
+            let triggers = model.triggers.iter()
+                .map(|x| TriggerState {
+                    rule: x.clone(),
+                    since: None,
+                    fired: false,
+                })
+                .collect();
+            let written: Arc<(async_std::sync::Condvar, async_std::sync::Mutex<AtomicUsize>)> =
+                Arc::new(Default::default());
+            let progress: Arc<RoundProgress> = Arc::new(Default::default());
             let actor = TraceActor {
                 running_pids: self.running_pids.clone(),
                 local_pids: Default::default(),
@@ -441,11 +4955,49 @@ impl HouseKeeper {
                 model,
                 file: None,
                 child: None,
-                written: Arc::new(Default::default()),
-                pattern: "".to_string(),
+                written,
+                pattern,
+                db: Some(self.db.clone()),
+                round: Default::default(),
+                tool_version: None,
+                adjusted_frequency: None,
+                one_shot,
+                holding_slot: false,
+                pending_round: None,
+                draining: false,
+                pinned: Vec::new(),
+                redactor: self.redactor.clone(),
+                progress: progress.clone(),
             };
             let addr = actor.start().await;
-            self.running_trace.insert(name, addr);
+            let mut trace = RunningTrace {
+                addr,
+                method,
+                lasting,
+                paused,
+                priority,
+                load_limit,
+                progress,
+                schedule,
+                interval,
+                jitter_secs,
+                next_run: None,
+                triggers,
+                exec_watch,
+                group,
+                run_after,
+                setup,
+            };
+            match trace.group.clone() {
+                // members of a group share the schedule of the group
+                Some(group) => {
+                    if !self.group_round(group.clone()).armed {
+                        self.arm_group(group, Some(Duration::from_secs(0)), ctx);
+                    }
+                }
+                None => trace.next_run = trace.next_run(&chrono::Local::now())
+            }
+            self.running_trace.insert(name, trace);
             Ok(())
         } else {
             Err(anyhow!("{} already running", model.name))
@@ -453,10 +5005,178 @@ impl HouseKeeper {
     }
 }
 
+/// Keeps the running traces in step with the models in the database.
+pub async fn follow_models(mut db: Addr<crate::database::DataActor>, mut keeper: Addr<HouseKeeper>) -> Result<()> {
+    use futures::StreamExt;
+    let mut changes = match db.call(DbMsg::Subscribe).await?? {
+        DbReply::Subscription(changes) => changes,
+        _ => return Err(anyhow!("unexpected database reply to the subscription"))
+    };
+    while let Some(change) = changes.next().await {
+        match change {
+            crate::database::ModelChange::Stored(model) => keeper.send(KeeperMsg::Stored(model))?,
+            crate::database::ModelChange::Removed(name) => keeper.send(KeeperMsg::Unregister(name))?
+        }
+    }
+    Ok(())
+}
+
 #[async_trait::async_trait]
 impl Actor for HouseKeeper {
-    async fn started(&mut self, _: &Context<Self>) {
+    async fn started(&mut self, ctx: &Context<Self>) {
         info!("house keeper started");
+        ctx.send_interval_with(|| SchedulerTick, Duration::from_secs(1));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SetPaused> for HouseKeeper {
+    async fn handle(&mut self, _: &Context<Self>, msg: SetPaused) -> anyhow::Result<()> {
+        self.db.call(DbMsg::SetPaused { name: msg.name.clone(), paused: msg.paused }).await??;
+        // models that are not running pick the state up from the database when started
+        if let Some(trace) = self.running_trace.get_mut(msg.name.as_str()) {
+            trace.paused = msg.paused;
+            trace.addr.send(Pause(msg.paused))?;
+        }
+        if msg.paused {
+            info!("trace {} paused", msg.name);
+        } else {
+            info!("trace {} resumed", msg.name);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<DrainAll> for HouseKeeper {
+    async fn handle(&mut self, _: &Context<Self>, msg: DrainAll) {
+        self.draining = true;
+        if let Some(mut watcher) = self.exec_watcher.take() {
+            watcher.kill().ok();
+            watcher.wait().ok();
+        }
+        let deadline = std::time::Instant::now() + msg.0;
+        loop {
+            let mut busy = Vec::new();
+            for (name, trace) in self.running_trace.iter_mut() {
+                // a trace in the middle of a round only answers once the round is over
+                let left = deadline.saturating_duration_since(std::time::Instant::now());
+                match async_std::future::timeout(left, trace.addr.call(Drain)).await {
+                    Ok(Ok(false)) => (),
+                    _ => busy.push(name.as_str())
+                }
+            }
+            if busy.is_empty() {
+                info!("all traces drained");
+                return;
+            }
+            if std::time::Instant::now() >= deadline {
+                warn!("drain timed out with traces still running: {}", busy.join(", "));
+                return;
+            }
+            debug!("waiting for {} running traces", busy.len());
+            async_std::task::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SchedulerTick> for HouseKeeper {
+    async fn handle(&mut self, ctx: &Context<Self>, _: SchedulerTick) {
+        if self.draining {
+            return;
+        }
+        let exited = match &mut self.exec_watcher {
+            Some(watcher) => match watcher.try_wait() {
+                Ok(None) => false,
+                Ok(Some(status)) => {
+                    warn!("exec watcher exited with {}", status);
+                    true
+                }
+                Err(e) => {
+                    warn!("cannot check exec watcher {}, pid: {}", e, watcher.id());
+                    false
+                }
+            },
+            None => true
+        };
+        if exited && self.running_trace.values().any(|x| x.exec_watch.is_some()) {
+            self.exec_watcher = None;
+            match watch_exec(ctx.address()) {
+                Ok(child) => {
+                    info!("exec watcher restarted");
+                    self.exec_watcher = Some(child);
+                }
+                Err(e) => error!("cannot restart exec watcher: {}", e)
+            }
+        }
+        for name in std::mem::take(&mut self.round_slots.deferred) {
+            let reason = self.running_trace.get(name.as_str())
+                .and_then(|x| x.load_limit.as_ref())
+                .and_then(overloaded);
+            match reason {
+                Some(_) => self.round_slots.deferred.push(name),
+                None if self.running_trace.contains_key(name.as_str()) => {
+                    info!("host calmed down, resuming round of trace {}", name);
+                    self.request_slot(name);
+                }
+                None => ()
+            }
+        }
+        let cpu_percent = match crate::utils::cpu_times() {
+            Ok((busy, total)) => {
+                let percent = self.cpu_sample
+                    .filter(|x| total > x.1)
+                    .map(|x| busy.saturating_sub(x.0) as f64 * 100.0 / (total - x.1) as f64);
+                self.cpu_sample.replace((busy, total));
+                percent
+            }
+            Err(_) => None
+        };
+        for (name, trace) in self.running_trace.iter_mut() {
+            if trace.paused {
+                continue;
+            }
+            for state in trace.triggers.iter_mut() {
+                let value = match sample_metric(&state.rule.metric, cpu_percent) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        debug!("cannot sample {} for trace {}: {}", state.rule.metric.name(), name, e);
+                        continue;
+                    }
+                };
+                if value <= state.rule.above {
+                    state.since = None;
+                    state.fired = false;
+                    continue;
+                }
+                let since = *state.since.get_or_insert_with(std::time::Instant::now);
+                if !state.fired && since.elapsed() >= Duration::from_secs(state.rule.for_secs) {
+                    state.fired = true;
+                    info!("trace {} triggered by {} at {:.2}", name, state.rule.metric.name(), value);
+                    trace.addr.send(TraceEvent::Trigger).check_error();
+                    self.send_client.send(TraceTriggered {
+                        trace_name: name.clone(),
+                        metric: state.rule.metric.name().to_string(),
+                        value,
+                        threshold: state.rule.above,
+                    }).check_error();
+                }
+            }
+        }
+        let now = chrono::Local::now();
+        for (name, trace) in self.running_trace.iter_mut() {
+            if !trace.next_run.map(|x| x <= now).unwrap_or(false) {
+                continue;
+            }
+            trace.next_run = trace.next_run(&now);
+            if self.round_slots.busy(name.as_str()) {
+                info!("trace {} is still in its last round, scheduled round skipped", name);
+                continue;
+            }
+            debug!("scheduled round of trace {} fired", name);
+            trace.addr.send(TraceEvent::NextRound).check_error();
+        }
     }
 }
 
@@ -467,28 +5187,161 @@ impl Handler<KeeperMsg> for HouseKeeper {
             KeeperMsg::Unregister(name) =>
                 {
                     for mut i in self.running_trace.remove(name.as_str()) {
-                        i.stop(None).check_error();
-                        info!("send stop to trace {} at {}", name, i.actor_id());
+                        i.addr.stop(None).check_error();
+                        info!("send stop to trace {} at {}", name, i.addr.actor_id());
                     }
+                    self.free_slot(name.as_str());
+                    self.leave_groups(name.as_str());
+                }
+            KeeperMsg::RequestSlot(name) => {
+                let reason = self.running_trace.get(name.as_str())
+                    .and_then(|x| x.load_limit.as_ref())
+                    .and_then(overloaded);
+                match reason {
+                    Some(reason) => if !self.round_slots.deferred.contains(&name) {
+                        info!("round of trace {} deferred: {}", name, reason);
+                        self.round_slots.deferred.push(name);
+                    },
+                    None => self.request_slot(name)
+                }
+            }
+            KeeperMsg::ReleaseSlot(name) => self.free_slot(name.as_str()),
+            KeeperMsg::Expire { name, rounds, remove } => {
+                for mut i in self.running_trace.remove(name.as_str()) {
+                    i.addr.stop(None).check_error();
                 }
+                self.free_slot(name.as_str());
+                self.leave_groups(name.as_str());
+                let msg = if remove {
+                    DbMsg::Remove { name: name.clone(), revision: None }
+                } else {
+                    DbMsg::SetEnabled { name: name.clone(), enabled: false }
+                };
+                match self.db.call(msg).await
+                    .map_err(|x| x.into())
+                    .and_then(|x| x) {
+                    Ok(_) => self.send_client.send(TraceExpired {
+                        trace_name: name,
+                        rounds,
+                        removed: remove,
+                    }).check_error(),
+                    Err(e) => error!("cannot retire expired trace {}: {}", name, e)
+                }
+            }
+            KeeperMsg::Backoff { name, until } => {
+                use chrono::TimeZone;
+                if let Some(trace) = self.running_trace.get_mut(name.as_str()) {
+                    trace.next_run = trace.next_run(&chrono::Local.timestamp(until as i64, 0));
+                }
+            }
             KeeperMsg::StartAll(list) => {
                 for i in list {
-                    if !self.running_trace.contains_key(i.name.as_str()) {
-                        self.create_actor(i, ctx).await.check_error();
+                    if !i.runnable() {
+                        info!("skip disabled or archived trace {}", i.name);
+                    } else if !self.running_trace.contains_key(i.name.as_str()) {
+                        self.create_actor(i, false, ctx).await.check_error();
                     }
                 }
             }
             KeeperMsg::Start(model) => {
                 if !self.running_trace.contains_key(model.name.as_str()) {
-                    self.create_actor(model, ctx).await.check_error();
+                    self.create_actor(model, false, ctx).await.check_error();
+                }
+            }
+            KeeperMsg::Stored(model) => {
+                let current = self.running_trace.get(model.name.as_str())
+                    .map(|x| (x.setup == setup_of(&model), x.paused));
+                match current {
+                    None if model.runnable() => self.create_actor(model, false, ctx).await.check_error(),
+                    None => (),
+                    Some((true, paused)) if model.runnable() => if paused != model.paused {
+                        if let Some(trace) = self.running_trace.get_mut(model.name.as_str()) {
+                            trace.paused = model.paused;
+                            trace.addr.send(Pause(model.paused)).check_error();
+                        }
+                    },
+                    Some(_) => {
+                        for mut i in self.running_trace.remove(model.name.as_str()) {
+                            i.addr.stop(None).check_error();
+                        }
+                        self.free_slot(model.name.as_str());
+                        self.leave_groups(model.name.as_str());
+                        if model.runnable() {
+                            info!("restarting trace {} with its changed model", model.name);
+                            self.create_actor(model, false, ctx).await.check_error();
+                        } else {
+                            info!("trace {} stopped as it is disabled or archived", model.name);
+                        }
+                    }
+                }
+            }
+            KeeperMsg::RunNow(model) => match self.running_trace.get_mut(model.name.as_str()) {
+                Some(trace) => trace.addr.send(TraceEvent::Trigger).check_error(),
+                None => {
+                    info!("spawning trace {} for a single round", model.name);
+                    self.create_actor(model, true, ctx).await.check_error();
+                }
+            },
+            KeeperMsg::Retain { pattern, retention } => {
+                enforce_retention(pattern.as_str(), &retention);
+            }
+            KeeperMsg::RoundDone { name, succeeded } => {
+                for (next, trace) in self.running_trace.iter_mut() {
+                    if trace.run_after.as_ref() != Some(&name) {
+                        continue;
+                    }
+                    if !succeeded {
+                        info!("trace {} skips its round as {} failed", next, name);
+                        self.send_client.send(RoundSkipped {
+                            trace_name: next.clone(),
+                            time: crate::utils::unix_now(),
+                            reason: format!("{} failed", name),
+                        }).check_error();
+                    } else if trace.paused {
+                        debug!("trace {} is paused, not running after {}", next, name);
+                    } else {
+                        debug!("trace {} runs after {}", next, name);
+                        trace.addr.send(TraceEvent::Trigger).check_error();
+                    }
+                }
+            }
+            KeeperMsg::GroupRound(group) => {
+                if !self.draining {
+                    self.start_group(group.clone());
+                }
+                self.arm_group(group, None, ctx);
+            }
+            KeeperMsg::GroupDone { group, name } => {
+                if let Some(round) = self.groups.get_mut(group.as_str()) {
+                    round.pending.remove(name.as_str());
+                    if round.pending.is_empty() {
+                        info!("group round {} finished", round.id);
+                    }
+                }
+            }
+            KeeperMsg::Exec { pid, filename } => {
+                for (name, trace) in self.running_trace.iter_mut() {
+                    let matched = trace.exec_watch.as_ref()
+                        .map(|x| x.is_match(filename.as_str()))
+                        .unwrap_or(false);
+                    if matched && !trace.paused {
+                        info!("trace {} triggered by exec of {} as {}", name, filename, pid);
+                        trace.addr.send(TraceEvent::Trigger).check_error();
+                    }
                 }
             }
             KeeperMsg::StopAll => {
-                for (name, addr) in self.running_trace.iter_mut() {
-                    addr.stop(None).check_error();
-                    info!("send stop to trace {} at {}", name, addr.actor_id());
+                for (name, trace) in self.running_trace.iter_mut() {
+                    trace.addr.stop(None).check_error();
+                    info!("send stop to trace {} at {}", name, trace.addr.actor_id());
                 }
                 self.running_trace.clear();
+                if let Some(mut watcher) = self.exec_watcher.take() {
+                    if let Err(e) = watcher.kill() {
+                        error!("cannot kill exec watcher {}, pid: {}", e, watcher.id())
+                    }
+                    watcher.wait().ok();
+                }
             }
         }
     }
@@ -499,4 +5352,23 @@ impl Handler<AllRunning> for HouseKeeper {
     async fn handle(&mut self, _: &Context<Self>, _: AllRunning) -> <AllRunning as Message>::Result {
         self.running_trace.keys().map(|x| x.clone()).collect()
     }
-}
\ No newline at end of file
+}
+
+#[async_trait::async_trait]
+impl Handler<AllStatus> for HouseKeeper {
+    async fn handle(&mut self, _: &Context<Self>, _: AllStatus) -> <AllStatus as Message>::Result {
+        let mut result = Vec::new();
+        for (name, trace) in self.running_trace.iter() {
+            let started_at = trace.progress.started_at.load(SeqCst);
+            result.push(TraceStatus {
+                name: name.clone(),
+                method: trace.method.to_string(),
+                running: if started_at == 0 { 0 } else { crate::utils::unix_now().saturating_sub(started_at) },
+                lasting: trace.lasting,
+                sent: trace.progress.sent.load(SeqCst),
+                paused: trace.paused,
+            });
+        }
+        result
+    }
+}
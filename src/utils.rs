@@ -5,6 +5,69 @@ use serde_json::Value;
 use prettytable::*;
 use anyhow::*;
 use std::fs::{read_dir, read_link};
+use std::path::PathBuf;
+
+/// Masks every match of the configured patterns in the strings of a payload.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    rules: std::sync::Arc<Vec<regex::Regex>>,
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let rules = patterns.iter()
+            .map(|x| regex::Regex::new(x.as_str())
+                .map_err(|e| anyhow!("invalid redaction rule {}: {}", x, e)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Redactor { rules: std::sync::Arc::new(rules) })
+    }
+
+    pub fn redact_str(&self, content: &str) -> String {
+        let mut content = content.to_string();
+        for i in self.rules.iter() {
+            if i.is_match(content.as_str()) {
+                content = i.replace_all(content.as_str(), REDACTED).into_owned();
+            }
+        }
+        content
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(x) => *x = self.redact_str(x.as_str()),
+            Value::Array(x) => x.iter_mut().for_each(|x| self.redact_value(x)),
+            Value::Object(x) => x.iter_mut().for_each(|(_, x)| self.redact_value(x)),
+            _ => ()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies the rules to a text artifact; binary content cannot be redacted, so it is refused.
+    pub fn redact_bytes(&self, content: Vec<u8>) -> Result<Vec<u8>> {
+        if self.rules.is_empty() {
+            return Ok(content);
+        }
+        String::from_utf8(content)
+            .map(|x| self.redact_str(x.as_str()).into_bytes())
+            .map_err(|_| anyhow!("binary content cannot be redacted"))
+    }
+
+    /// Serializes `data` into json, with the rules applied to every string inside, so
+    /// that no match can break the json structure.
+    pub fn to_json<T: Serialize>(&self, data: &T) -> Result<String> {
+        if self.rules.is_empty() {
+            return simd_json::to_string(data).map_err(|x| x.into());
+        }
+        let mut value = serde_json::to_value(data)?;
+        self.redact_value(&mut value);
+        serde_json::to_string(&value).map_err(|x| x.into())
+    }
+}
 
 pub trait CheckError {
     fn check_error(&self);
@@ -94,3 +157,284 @@ pub fn find_running(s: &str) -> Result<Vec<i32>> {
     }).map_err(std::io::Error::into)
 }
 
+#[cfg(unix)]
+pub fn executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.is_file() && meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(windows)]
+pub fn executable(meta: &std::fs::Metadata) -> bool {
+    meta.is_file()
+}
+
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::is_separator) {
+        let path = PathBuf::from(program);
+        return Some(path).filter(|x| x.metadata()
+            .map(|x| executable(&x))
+            .unwrap_or(false));
+    }
+    // windows leaves the extension out of the command name
+    let names = if cfg!(windows) {
+        vec![program.to_string(), format!("{}.exe", program)]
+    } else {
+        vec![program.to_string()]
+    };
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .flat_map(|x| names.iter().map(move |name| x.join(name)))
+            .find(|x| x.metadata()
+                .map(|x| executable(&x))
+                .unwrap_or(false))
+    })
+}
+
+/// How a running tool is asked to stop.
+#[derive(Copy, Clone, Debug)]
+pub enum Stop {
+    Interrupt,
+    Terminate,
+    Kill,
+}
+
+/// Stops the process, or the process group it leads.
+#[cfg(unix)]
+pub fn stop_process(pid: u32, group: bool, stop: Stop) -> Result<()> {
+    use nix::sys::signal::{kill, killpg, Signal};
+    let signal = match stop {
+        Stop::Interrupt => Signal::SIGINT,
+        Stop::Terminate => Signal::SIGTERM,
+        Stop::Kill => Signal::SIGKILL
+    };
+    let pid = nix::unistd::Pid::from_raw(pid as i32);
+    if group {
+        killpg(pid, signal)?;
+    } else {
+        kill(pid, signal)?;
+    }
+    Ok(())
+}
+
+/// Stops the process, or the process tree it leads. There are no signals, so short of
+/// a kill taskkill only asks the process to close.
+#[cfg(windows)]
+pub fn stop_process(pid: u32, group: bool, stop: Stop) -> Result<()> {
+    let mut command = std::process::Command::new("taskkill");
+    command.arg("/PID").arg(pid.to_string());
+    if group {
+        command.arg("/T");
+    }
+    if let Stop::Kill = stop {
+        command.arg("/F");
+    }
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("taskkill failed: {}", String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+pub fn pid_alive(pid: u32) -> bool {
+    std::path::Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+pub fn find_by_name(name: &str) -> Result<Vec<i32>> {
+    read_dir("/proc").map(|entry| {
+        entry.filter_map(Result::ok)
+            .map(|x|x.file_name())
+            .filter_map(|name|name.to_str().and_then(|x|x.parse::<i32>().ok()))
+            .filter(|x| std::fs::read_to_string(format!("/proc/{}/comm", x))
+                .map(|x| x.trim() == name)
+                .unwrap_or(false))
+            .collect()
+    }).map_err(std::io::Error::into)
+}
+
+pub fn find_descendants(roots: &[i32]) -> Result<Vec<i32>> {
+    let parents = read_dir("/proc").map(|entry| {
+        entry.filter_map(Result::ok)
+            .map(|x|x.file_name())
+            .filter_map(|name|name.to_str().and_then(|x|x.parse::<i32>().ok()))
+            .filter_map(|x| std::fs::read_to_string(format!("/proc/{}/stat", x))
+                .ok()
+                // the command name may contain spaces, so skip past its closing parenthesis
+                .and_then(|stat| stat.rfind(')')
+                    .and_then(|index| stat[index + 1..].split_whitespace().nth(1))
+                    .and_then(|ppid| ppid.parse::<i32>().ok()))
+                .map(|ppid| (x, ppid)))
+            .collect::<Vec<_>>()
+    })?;
+    let mut result = roots.to_vec();
+    let mut index = 0;
+    while index < result.len() {
+        let current = result[index];
+        result.extend(parents.iter()
+            .filter(|(pid, ppid)| *ppid == current && !roots.contains(pid))
+            .map(|(pid, _)| *pid));
+        index += 1;
+    }
+    Ok(result)
+}
+
+pub fn load_average() -> Result<f32> {
+    use systemstat::Platform;
+    Ok(systemstat::System::new().load_average()?.one)
+}
+
+/// `avg10` of the `some` line in `/proc/pressure/<resource>`, as a percentage.
+pub fn pressure(resource: &str) -> Result<f64> {
+    let content = std::fs::read_to_string(format!("/proc/pressure/{}", resource))?;
+    content.lines()
+        .filter(|x| x.starts_with("some"))
+        .flat_map(|x| x.split_ascii_whitespace())
+        .filter(|x| x.starts_with("avg10="))
+        .filter_map(|x| x["avg10=".len()..].parse().ok())
+        .next()
+        .ok_or_else(|| anyhow!("no avg10 pressure of {} found", resource))
+}
+
+/// Busy and total jiffies of all cpus, from the first line of `/proc/stat`.
+pub fn cpu_times() -> Result<(u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat")?;
+    let fields = stat.lines()
+        .next()
+        .filter(|x| x.starts_with("cpu "))
+        .ok_or_else(|| anyhow!("malformed /proc/stat"))?
+        .split_ascii_whitespace()
+        .skip(1)
+        .filter_map(|x| x.parse::<u64>().ok())
+        .collect::<Vec<_>>();
+    let total = fields.iter().sum::<u64>();
+    // idle and iowait are the 4th and 5th fields
+    let idle = fields.iter().skip(3).take(2).sum::<u64>();
+    Ok((total - idle, total))
+}
+
+pub fn rss_bytes(pid: i32) -> Result<u64> {
+    let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid))?;
+    let pages = statm.split_ascii_whitespace()
+        .nth(1)
+        .and_then(|x| x.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("malformed statm of process {}", pid))?;
+    #[cfg(unix)]
+    let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+        .ok()
+        .and_then(|x| x)
+        .unwrap_or(4096);
+    #[cfg(not(unix))]
+    let page_size = 4096;
+    Ok(pages * page_size as u64)
+}
+
+pub fn cgroup_pids(cgroup: &str) -> Result<Vec<i32>> {
+    let cgroup = cgroup.trim_start_matches('/');
+    // cgroup v2 exposes a unified hierarchy, while v1 keeps perf_event as its own controller
+    let content = std::fs::read_to_string(format!("/sys/fs/cgroup/{}/cgroup.procs", cgroup))
+        .or_else(|_| std::fs::read_to_string(format!("/sys/fs/cgroup/perf_event/{}/cgroup.procs", cgroup)))?;
+    Ok(content.lines()
+        .filter_map(|x| x.trim().parse::<i32>().ok())
+        .collect())
+}
+
+/// When the process started, in clock ticks since boot.
+pub fn start_ticks(pid: i32) -> Result<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // starttime is the 22nd field, counted from the state as the 3rd past the command name
+    stat.rsplitn(2, ')')
+        .next()
+        .and_then(|x| x.split_ascii_whitespace().nth(19))
+        .and_then(|x| x.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("malformed stat of process {}", pid))
+}
+
+pub fn cpu_seconds(pid: i32) -> Result<f64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // the command name may contain spaces, so fields are counted from its closing parenthesis
+    let fields = stat.rsplitn(2, ')')
+        .next()
+        .unwrap_or("")
+        .split_ascii_whitespace()
+        .collect::<Vec<_>>();
+    let field = |index: usize| fields.get(index)
+        .and_then(|x| x.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("malformed stat of process {}", pid));
+    // utime and stime are the 14th and 15th fields, counted from the state as the 3rd
+    let ticks = field(11)? + field(12)?;
+    #[cfg(unix)]
+    let rate = nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
+        .ok()
+        .and_then(|x| x)
+        .unwrap_or(100);
+    #[cfg(not(unix))]
+    let rate = 100;
+    Ok(ticks as f64 / rate as f64)
+}
+
+/// Trace names may hold anything, so only a safe part of it goes into paths, with a hash to tell them apart.
+pub fn safe_name(name: &str) -> String {
+    let safe = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() || x == '-' || x == '_' { x } else { '_' })
+        .collect::<String>();
+    format!("{}-{:08x}", safe, adler32(name.as_bytes()))
+}
+
+fn cgroup_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("/sys/fs/cgroup/girasol-{}", safe_name(name)))
+}
+
+pub fn create_cpu_cgroup(name: &str, pct: u32) -> Result<()> {
+    let path = cgroup_path(name);
+    std::fs::create_dir_all(&path)?;
+    // quota and period are both in microseconds, so one percent is 1000 out of 100000
+    std::fs::write(path.join("cpu.max"), format!("{} 100000", pct as u64 * 1000))
+        .map_err(|x| x.into())
+}
+
+/// Opens the process list of a cgroup made by `create_cpu_cgroup`, without creating anything.
+pub fn cpu_cgroup_procs(name: &str) -> Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(cgroup_path(name).join("cgroup.procs"))
+        .map_err(|x| x.into())
+}
+
+pub fn remove_cpu_cgroup(name: &str) -> Result<()> {
+    let path = cgroup_path(name);
+    if !path.exists() {
+        return Ok(());
+    }
+    std::fs::remove_dir(path).map_err(|x| x.into())
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest block that cannot overflow before reducing
+    for block in data.chunks(5552) {
+        for i in block {
+            a += *i as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// A random delay of up to `secs` seconds, at millisecond granularity.
+pub fn jitter(secs: u64) -> std::time::Duration {
+    use rand::Rng;
+    if secs == 0 {
+        return std::time::Duration::from_secs(0);
+    }
+    std::time::Duration::from_millis(rand::thread_rng().gen_range(0, secs * 1000 + 1))
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}